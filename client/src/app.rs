@@ -1,16 +1,22 @@
 use crate::app::common::table::TableBase;
+use chrono::{DateTime, Local};
 use eframe::egui;
 use egui::{ComboBox, Label, TextWrapMode, Ui, Widget};
 use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
 use log::{error, warn};
-use netpix_common::{MpegtsStreamKey, Request, Response, RtpStreamKey, Source};
+use netpix_common::{
+    CaptureStats, FragmentStats, MpegtsStreamKey, ReplayState, Request, Response, RtpStreamKey,
+    SessionView, Source, SourceInfo,
+};
 
+use calls_table::CallsTable;
 use packets_table::PacketsTable;
 use rtcp_packets_table::RtcpPacketsTable;
 use rtp_packets_table::RtpPacketsTable;
 use rtp_streams_table::RtpStreamsTable;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use mpegts_info_table::MpegTsInformationTable;
 use mpegts_packets_table::MpegTsPacketsTable;
@@ -20,8 +26,11 @@ use tab::Tab;
 
 use crate::app::tab::{MpegTsSection, RtpSection};
 use crate::streams::RefStreams;
+use decode_rules_menu::DecodeRulesMenu;
 use rtp_streams_plot::RtpStreamsPlot;
+use subscription_menu::SubscriptionMenu;
 
+mod calls_table;
 mod packets_table;
 mod rtcp_packets_table;
 mod rtp_packets_table;
@@ -38,6 +47,10 @@ mod tab;
 
 mod common;
 
+mod decode_rules_menu;
+mod subscription_menu;
+mod upload;
+
 const SOURCE_KEY: &str = "source";
 const TAB_KEY: &str = "tab";
 
@@ -49,6 +62,7 @@ pub struct App {
     // but this will do
     streams: RefStreams,
     sources: Vec<Source>,
+    source_infos: HashMap<Source, SourceInfo>,
     selected_source: Option<Source>,
     tab: Tab,
     // would rather keep this in `Tab` enum
@@ -62,8 +76,22 @@ pub struct App {
     mpegts_packets_table: MpegTsPacketsTable,
     mpegts_streams_table: MpegTsStreamsTable,
     mpegts_info_table: MpegTsInformationTable,
+
+    calls_table: CallsTable,
+    subscription_menu: SubscriptionMenu,
+    decode_rules_menu: DecodeRulesMenu,
     discharged_count: usize,
     overwritten_count: usize,
+    // packets the server didn't send as we couldn't keep up with them
+    dropped_count: usize,
+    fragment_stats: FragmentStats,
+    // packets dropped before reaching netpix, known for interfaces only
+    capture_stats: Option<CaptureStats>,
+    replay_state: Option<ReplayState>,
+    // position picked with the slider, kept until it's released
+    replay_seek: Option<f64>,
+    // outcome of the last session save, the file path or the error
+    session_saved: Option<Result<String, String>>,
 }
 
 impl eframe::App for App {
@@ -72,6 +100,7 @@ impl eframe::App for App {
             self.receive_packets()
         }
 
+        self.upload_dropped_files(ctx);
         self.build_side_panel(ctx);
         self.build_top_bar(ctx, frame);
         self.build_bottom_bar(ctx);
@@ -89,14 +118,22 @@ impl eframe::App for App {
                 MpegTsSection::Streams => self.mpegts_streams_table.ui(ctx),
                 MpegTsSection::Information => self.mpegts_info_table.ui(ctx),
             },
+            Tab::Calls => self.calls_table.ui(ctx),
         };
+
+        self.fetch_history();
     }
 }
 
 impl App {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let host = &cc.integration_info.web_info.location.host;
-        let uri = format!("ws://{}/ws", host);
+        let location = &cc.integration_info.web_info.location;
+        let scheme = if location.protocol == "https:" {
+            "wss"
+        } else {
+            "ws"
+        };
+        let uri = format!("{}://{}/ws", scheme, location.host);
 
         let ctx = cc.egui_ctx.clone();
         let wakeup = move || ctx.request_repaint(); // wake up UI thread on new message
@@ -115,6 +152,8 @@ impl App {
         let mpegts_packets_table = MpegTsPacketsTable::new(streams.clone());
         let mpegts_streams_table = MpegTsStreamsTable::new(streams.clone());
         let mpegts_info_table = MpegTsInformationTable::new(streams.clone());
+        let calls_table = CallsTable::new(streams.clone());
+        let subscription_menu = SubscriptionMenu::new(streams.clone());
 
         let (tab, selected_source) = get_initial_state(cc);
 
@@ -124,6 +163,7 @@ impl App {
             is_capturing: true,
             streams,
             sources: Vec::new(),
+            source_infos: HashMap::new(),
             selected_source,
             tab,
             packets_table,
@@ -134,8 +174,17 @@ impl App {
             mpegts_packets_table,
             mpegts_streams_table,
             mpegts_info_table,
+            calls_table,
+            subscription_menu,
+            decode_rules_menu: DecodeRulesMenu::default(),
             discharged_count: 0,
             overwritten_count: 0,
+            dropped_count: 0,
+            fragment_stats: FragmentStats::default(),
+            capture_stats: None,
+            replay_state: None,
+            replay_seek: None,
+            session_saved: None,
         }
     }
 
@@ -178,15 +227,22 @@ impl App {
                         self.streams.borrow_mut().clear();
                     }
 
-                    //TODO: implement more optimal way to do that - with lots of packages it is too much for wasm to handle this
                     let button = side_button("↻");
                     let resp = ui
                         .add(button)
-                        .on_hover_text("Refetch all previously captured packets");
+                        .on_hover_text("Refetch the most recent captured packets, older ones are loaded on scrolling up");
                     if resp.clicked() {
                         self.streams.borrow_mut().clear();
                         self.refetch_packets()
                     }
+
+                    let button = side_button("💾");
+                    let resp = ui
+                        .add(button)
+                        .on_hover_text("Save the packets of all sources and this view to the server's session file");
+                    if resp.clicked() {
+                        self.save_session();
+                    }
                 });
 
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::Center), |ui| {
@@ -203,9 +259,14 @@ impl App {
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 self.build_dropdown_source(ui, frame);
+                self.build_remove_source_button(ui);
                 ui.separator();
                 self.build_menu_button(ui, frame);
                 Label::new(selected).ui(ui);
+                ui.separator();
+                self.build_subscription_menu(ui);
+                self.build_decode_rules_menu(ui);
+                self.build_replay_controls(ui);
             });
         });
     }
@@ -231,13 +292,87 @@ impl App {
         });
     }
 
+    fn build_subscription_menu(&mut self, ui: &mut Ui) {
+        let Some(subscription) = self.subscription_menu.ui(ui) else {
+            return;
+        };
+
+        // the server sends the matching packets of the current source again
+        self.streams.borrow_mut().clear();
+        self.send_request(Request::Subscribe(subscription));
+    }
+
+    fn build_decode_rules_menu(&mut self, ui: &mut Ui) {
+        if let Some(rules) = self.decode_rules_menu.ui(ui) {
+            self.send_request(Request::SetDecodeRules(rules));
+        }
+    }
+
+    fn build_replay_controls(&mut self, ui: &mut Ui) {
+        let Some(state) = self.replay_state else {
+            return;
+        };
+
+        ui.separator();
+
+        if state.paused {
+            let resp = ui.button("▶").on_hover_text("Resume replay");
+            if resp.clicked() {
+                self.send_request(Request::ResumeReplay);
+            }
+        } else {
+            let resp = ui
+                .add_enabled(!state.finished, egui::Button::new("⏸"))
+                .on_hover_text("Pause replay");
+            if resp.clicked() {
+                self.send_request(Request::PauseReplay);
+            }
+        }
+
+        let position = self.replay_position(&state);
+        let mut seconds = self.replay_seek.unwrap_or(position.as_secs_f64());
+        let slider =
+            egui::Slider::new(&mut seconds, 0.0..=state.duration.as_secs_f64()).show_value(false);
+        let resp = ui.add(slider).on_hover_text("Seek replay");
+
+        if resp.dragged() {
+            self.replay_seek = Some(seconds);
+        }
+        if resp.drag_stopped() || (resp.changed() && !resp.dragged()) {
+            self.replay_seek = None;
+            self.send_request(Request::SeekReplay(Duration::from_secs_f64(seconds)));
+        }
+
+        let label = format!(
+            "{} / {} • {}x",
+            format_replay_time(Duration::from_secs_f64(seconds)),
+            format_replay_time(state.duration),
+            state.speed
+        );
+        ui.label(label);
+    }
+
+    fn replay_position(&self, state: &ReplayState) -> Duration {
+        if state.finished {
+            return state.duration;
+        }
+
+        match self.streams.borrow().packets.last() {
+            Some(packet) => packet
+                .timestamp
+                .saturating_sub(state.start)
+                .min(state.duration),
+            None => state.position,
+        }
+    }
+
     fn build_dropdown_source(&mut self, ui: &mut Ui, frame: &mut eframe::Frame) {
         let selected = match self.selected_source {
-            Some(ref source) => source.to_string(),
+            Some(ref source) => source_label(source, &self.source_infos),
             None => "Select packets source...".to_string(),
         };
 
-        ComboBox::from_id_salt("source_picker")
+        let resp = ComboBox::from_id_salt("source_picker")
             .width(300.0)
             .wrap_mode(TextWrapMode::Extend)
             .selected_text(selected)
@@ -245,18 +380,21 @@ impl App {
                 let mut was_changed = false;
 
                 for source in self.sources.iter() {
-                    let resp = ui.selectable_value(
+                    let mut resp = ui.selectable_value(
                         &mut self.selected_source,
                         Some(source.clone()),
-                        source.to_string(),
+                        source_label(source, &self.source_infos),
                     );
+                    if let Some(info) = self.source_infos.get(source) {
+                        resp = resp.on_hover_text(format_source_info(info));
+                    }
                     if resp.clicked() {
                         was_changed = true;
                     }
                 }
 
                 if was_changed {
-                    self.streams.borrow_mut().clear();
+                    self.streams.borrow_mut().reset();
                     self.change_source_request();
                     if let Some(storage) = frame.storage_mut() {
                         let source = self.selected_source.as_ref().unwrap();
@@ -264,6 +402,46 @@ impl App {
                     }
                 }
             });
+
+        let info = self
+            .selected_source
+            .as_ref()
+            .and_then(|source| self.source_infos.get(source));
+        if let Some(info) = info {
+            resp.response.on_hover_text(format_source_info(info));
+        }
+    }
+
+    fn build_remove_source_button(&mut self, ui: &mut Ui) {
+        let button = egui::Button::new("⏏");
+        let resp = ui
+            .add_enabled(self.selected_source.is_some(), button)
+            .on_hover_text("Remove the source from netpix");
+        if resp.clicked() {
+            if let Some(source) = self.selected_source.clone() {
+                self.send_request(Request::RemoveSource(source));
+            }
+        }
+    }
+
+    fn upload_dropped_files(&mut self, ctx: &egui::Context) {
+        let is_hovering = ctx.input(|i| !i.raw.hovered_files.is_empty());
+        if is_hovering {
+            egui::Area::new(egui::Id::new("drop_overlay"))
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.heading("Drop a pcap file to add it as a source");
+                });
+        }
+
+        let dropped = ctx.input_mut(|i| std::mem::take(&mut i.raw.dropped_files));
+        for file in dropped {
+            let Some(bytes) = file.bytes else {
+                warn!("Dropped file {} has no content", file.name);
+                continue;
+            };
+            upload::upload_file(file.name, bytes);
+        }
     }
 
     fn build_bottom_bar(&self, ctx: &egui::Context) {
@@ -279,11 +457,51 @@ impl App {
 
                 let discharged_label = format!("Discharged: {}", self.discharged_count);
                 let overwritten_label = format!("Overwritten: {}", self.overwritten_count);
+                let dropped_label = format!("Dropped: {}", self.dropped_count);
+                let reassembled_label = format!("Reassembled: {}", self.fragment_stats.reassembled);
                 let label = format!(
-                    "{} • {} • {} • {}",
-                    count_label, captured_label, discharged_label, overwritten_label
+                    "{} • {} • {} • {} • {} • {}",
+                    count_label,
+                    captured_label,
+                    discharged_label,
+                    overwritten_label,
+                    dropped_label,
+                    reassembled_label
+                );
+                let stats = &self.fragment_stats;
+                let fragments_hover = format!(
+                    "IP fragments: {}\nExpired datagrams: {}\nDropped fragments: {}",
+                    stats.fragments, stats.expired, stats.dropped
                 );
-                ui.label(label);
+                ui.label(label).on_hover_text(fragments_hover);
+
+                if let Some(ref capture) = self.capture_stats {
+                    let capture_hover = format!(
+                        "Received by libpcap: {}\nDropped by the capture: {}\nDropped by the interface: {}",
+                        capture.received, capture.dropped, capture.if_dropped
+                    );
+                    ui.separator();
+                    ui.label(format!(
+                        "Capture dropped: {}",
+                        capture.dropped + capture.if_dropped
+                    ))
+                    .on_hover_text(capture_hover);
+                }
+
+                match self.session_saved {
+                    Some(Ok(ref path)) => {
+                        ui.separator();
+                        ui.label(format!("Session saved to {}", path));
+                    }
+                    Some(Err(ref err)) => {
+                        ui.separator();
+                        ui.colored_label(
+                            egui::Color32::RED,
+                            format!("Failed to save session: {}", err),
+                        );
+                    }
+                    None => {}
+                }
             });
         });
     }
@@ -306,37 +524,191 @@ impl App {
                 continue;
             };
 
-            match response {
-                Response::Packet(packet) => {
-                    let mut streams = self.streams.borrow_mut();
-                    streams.add_packet(packet);
-                }
-                Response::Sources(sources) => {
-                    if let Some(ref source) = self.selected_source {
-                        if !sources.contains(source) {
-                            self.selected_source = None;
-                        } else {
-                            self.change_source_request();
-                        }
+            self.handle_response(response);
+        }
+
+        self.streams.borrow_mut().refresh();
+    }
+
+    fn handle_response(&mut self, response: Response) {
+        match response {
+            Response::Packet(packet) => {
+                let mut streams = self.streams.borrow_mut();
+                streams.add_packet(packet);
+            }
+            Response::Sources(sources) => {
+                if let Some(ref source) = self.selected_source {
+                    if !sources.contains(source) {
+                        self.selected_source = None;
+                        self.replay_state = None;
+                    } else if !self.sources.contains(source) {
+                        // the source restored from the storage became available
+                        self.change_source_request();
                     }
-                    self.sources = sources;
                 }
-                Response::Sdp(stream_key, sdp) => {
-                    let mut streams = self.streams.borrow_mut();
-                    if let Some(stream) = streams.rtp_streams.get_mut(&stream_key) {
-                        stream.add_sdp(sdp);
+                self.source_infos
+                    .retain(|source, _| sources.contains(source));
+                self.sources = sources;
+            }
+            Response::SourceInfo(info) => {
+                self.source_infos.insert(info.source.clone(), info);
+            }
+            Response::Sdp(stream_key, sdp) => {
+                let mut streams = self.streams.borrow_mut();
+                streams.add_sdp(stream_key, sdp);
+            }
+            Response::PacketsStats(stats) => {
+                self.discharged_count = stats.discharged;
+                self.overwritten_count = stats.overwritten;
+                self.fragment_stats = stats.fragments;
+                self.capture_stats = stats.capture;
+            }
+            Response::ReplayState(state) => {
+                // after seeking backwards the file is replayed from its beginning
+                let restarted = self
+                    .replay_state
+                    .is_some_and(|current| current.generation != state.generation);
+                if restarted {
+                    self.streams.borrow_mut().clear();
+                }
+                self.replay_state = Some(state);
+            }
+            Response::Batch(compressed) => match Response::unbatch(&compressed) {
+                Ok(responses) => {
+                    for response in responses {
+                        self.handle_response(response);
                     }
                 }
-                Response::PacketsStats(stats) => {
-                    self.discharged_count = stats.discharged;
-                    self.overwritten_count = stats.overwritten;
+                Err(err) => error!("Failed to decode batch of responses: {}", err),
+            },
+            Response::Dropped(count) => {
+                warn!("Server dropped {} packets", count);
+                self.dropped_count += count;
+            }
+            Response::FetchedRange {
+                remaining,
+                from_id,
+                to_id,
+            } => {
+                let mut streams = self.streams.borrow_mut();
+                streams.history.fetched(remaining, from_id, to_id);
+            }
+            Response::SessionView(view) => self.apply_session_view(view),
+            Response::DecodeRules(rules) => self.decode_rules_menu.set_rules(rules),
+            Response::SessionSaved(result) => {
+                if let Err(ref err) = result {
+                    error!("Failed to save session: {}", err);
                 }
+                self.session_saved = Some(result);
+            }
+        }
+    }
+
+    fn save_session(&mut self) {
+        let view = SessionView {
+            source: self.selected_source.clone(),
+            tab: Some(self.tab.to_string()),
+            filters: self.filters(),
+            aliases: self
+                .streams
+                .borrow()
+                .aliases()
+                .map(|(key, alias)| (*key, alias.clone()))
+                .collect(),
+        };
+        self.send_request(Request::SaveSession(view));
+    }
+
+    // sent before the sources, which then make us switch to the session's one
+    fn apply_session_view(&mut self, view: SessionView) {
+        if let Some(tab) = view.tab.and_then(Tab::from_string) {
+            self.tab = tab;
+        }
+
+        for (tab, filter) in view.filters {
+            match Tab::from_string(tab.clone()) {
+                Some(tab) => self.set_filter(tab, filter),
+                None => warn!("Unknown tab of a session filter: {}", tab),
+            }
+        }
+
+        let mut streams = self.streams.borrow_mut();
+        if view.source.is_some() {
+            self.selected_source = view.source;
+            streams.reset();
+        }
+        for (key, alias) in view.aliases {
+            streams.set_alias(key, alias);
+        }
+    }
+
+    fn filters(&self) -> Vec<(String, String)> {
+        let filters = [
+            (Tab::Packets, self.packets_table.filter()),
+            (
+                Tab::RtpSection(RtpSection::Packets),
+                self.rtp_packets_table.filter(),
+            ),
+            (
+                Tab::RtpSection(RtpSection::Streams),
+                self.rtp_streams_table.filter(),
+            ),
+            (
+                Tab::MpegTsSection(MpegTsSection::Packets),
+                self.mpegts_packets_table.filter(),
+            ),
+            (
+                Tab::MpegTsSection(MpegTsSection::Streams),
+                self.mpegts_streams_table.filter(),
+            ),
+            (
+                Tab::MpegTsSection(MpegTsSection::Information),
+                self.mpegts_info_table.filter(),
+            ),
+        ];
+
+        filters
+            .into_iter()
+            .filter(|(_, filter)| !filter.is_empty())
+            .map(|(tab, filter)| (tab.to_string(), filter.to_string()))
+            .collect()
+    }
+
+    fn set_filter(&mut self, tab: Tab, filter: String) {
+        match tab {
+            Tab::Packets => self.packets_table.set_filter(filter),
+            Tab::RtpSection(RtpSection::Packets) => self.rtp_packets_table.set_filter(filter),
+            Tab::RtpSection(RtpSection::Streams) => self.rtp_streams_table.set_filter(filter),
+            Tab::MpegTsSection(MpegTsSection::Packets) => {
+                self.mpegts_packets_table.set_filter(filter)
             }
+            Tab::MpegTsSection(MpegTsSection::Streams) => {
+                self.mpegts_streams_table.set_filter(filter)
+            }
+            Tab::MpegTsSection(MpegTsSection::Information) => {
+                self.mpegts_info_table.set_filter(filter)
+            }
+            _ => warn!("Tab {} has no filter", tab),
         }
     }
 
     fn refetch_packets(&mut self) {
-        let request = Request::FetchAll;
+        // the most recent packets, the older ones are fetched as they're needed
+        let request = Request::FetchRange {
+            from_id: 0,
+            to_id: usize::MAX,
+        };
+        self.send_request(request);
+    }
+
+    fn fetch_history(&mut self) {
+        let request = self.streams.borrow_mut().history.take_request();
+        if let Some(request) = request {
+            self.send_request(request);
+        }
+    }
+
+    fn send_request(&mut self, request: Request) {
         let Ok(msg) = request.encode() else {
             error!("Failed to encode a request message");
             return;
@@ -347,7 +719,13 @@ impl App {
     }
 
     fn change_source_request(&mut self) {
+        // sent again by the server if the new source is replayed
+        self.replay_state = None;
+        self.replay_seek = None;
+        self.dropped_count = 0;
+        self.capture_stats = None;
         let selected = self.selected_source.as_ref().unwrap().clone();
+        self.streams.borrow_mut().source = Some(selected.clone());
         let request = Request::ChangeSource(selected);
         let Ok(msg) = request.encode() else {
             log::error!("Failed to encode a request message");
@@ -376,6 +754,50 @@ fn get_initial_state(cc: &eframe::CreationContext<'_>) -> (Tab, Option<Source>)
     }
 }
 
+fn format_replay_time(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+/// Interfaces capturing in promiscuous mode are marked with an eye.
+fn source_label(source: &Source, infos: &HashMap<Source, SourceInfo>) -> String {
+    match infos.get(source) {
+        Some(info) if info.promisc => format!("{} 👁️", source),
+        _ => source.to_string(),
+    }
+}
+
+fn format_source_info(info: &SourceInfo) -> String {
+    let mut lines = vec![format!("Kind: {}", info.source.kind())];
+    if info.promisc {
+        lines.push("Promiscuous mode".to_string());
+    }
+    if !info.filter.is_empty() {
+        lines.push(format!("Filter: {}", info.filter));
+    }
+    if let Some(ref link_type) = info.link_type {
+        lines.push(format!("Link type: {}", link_type));
+    }
+
+    let start_time = DateTime::from_timestamp(
+        info.start_time.as_secs() as i64,
+        info.start_time.subsec_nanos(),
+    );
+    if let Some(start_time) = start_time {
+        let start_time = start_time.with_timezone(&Local);
+        lines.push(format!(
+            "Opened: {}",
+            start_time.format("%Y-%m-%d %H:%M:%S")
+        ));
+    }
+
+    lines.push(format!(
+        "Packets: {} ({} bytes), errors: {}",
+        info.packets, info.bytes, info.errors
+    ));
+    lines.join("\n")
+}
+
 fn side_button(text: &str) -> egui::Button {
     egui::Button::new(text)
         .min_size((30.0, 30.0).into())
@@ -0,0 +1,151 @@
+use crate::streams::rtpStream::RtpStream;
+use crate::streams::RefStreams;
+use egui_extras::{Column, TableBody, TableBuilder};
+use netpix_common::sip::Call;
+use std::time::Duration;
+
+pub struct CallsTable {
+    streams: RefStreams,
+}
+
+impl CallsTable {
+    pub fn new(streams: RefStreams) -> Self {
+        Self { streams }
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.build_table(ui);
+        });
+    }
+
+    fn build_table(&mut self, ui: &mut egui::Ui) {
+        let header_labels = [
+            ("Call-ID", "Call-ID of the SIP messages of the call"),
+            ("From", "Caller, as in the From header of the INVITE"),
+            ("To", "Callee, as in the To header of the INVITE"),
+            ("Start", "Timestamp of the INVITE"),
+            ("Setup time", "Time from the INVITE to its 2xx response"),
+            ("Duration", "Time from the answer to the end of the call"),
+            ("Teardown", "How the call ended, or its state if it didn't"),
+            ("RTP streams", "RTP streams sent to the media of the SDP offer and answer, with the offered codecs"),
+        ];
+
+        TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .stick_to_bottom(true)
+            .column(Column::initial(200.0).at_least(100.0))
+            .columns(Column::initial(200.0).at_least(100.0), 2)
+            .columns(Column::initial(80.0).at_least(80.0), 3)
+            .column(Column::initial(170.0).at_least(100.0))
+            .column(Column::remainder().at_least(300.0))
+            .header(30.0, |mut header| {
+                for (label, desc) in header_labels {
+                    header.col(|ui| {
+                        ui.heading(label.to_string())
+                            .on_hover_text(desc.to_string());
+                    });
+                }
+            })
+            .body(|body| {
+                self.build_table_body(body);
+            });
+    }
+
+    fn build_table_body(&mut self, body: TableBody) {
+        let streams = &self.streams.borrow();
+        let calls = streams.calls.calls();
+        let Some(first_packet) = streams.packets.first() else {
+            return;
+        };
+        let first_ts = first_packet.timestamp;
+
+        // each stream belongs to the latest call with its media, the ports may be reused
+        let mut call_streams: Vec<Vec<&RtpStream>> = vec![Vec::new(); calls.len()];
+        for (key, stream) in streams.rtp_streams.iter() {
+            if let Some(ix) = calls.iter().rposition(|call| call.media(key).is_some()) {
+                call_streams[ix].push(stream);
+            }
+        }
+        for streams in call_streams.iter_mut() {
+            streams.sort_by(|a, b| a.alias.cmp(&b.alias));
+        }
+
+        let heights = call_streams
+            .iter()
+            .map(|streams| streams.len().max(1) as f32 * 20.0);
+
+        body.heterogeneous_rows(heights, |mut row| {
+            let ix = row.index();
+            let call = &calls[ix];
+
+            row.col(|ui| {
+                ui.label(&call.call_id);
+            });
+            row.col(|ui| {
+                ui.label(&call.from);
+            });
+            row.col(|ui| {
+                ui.label(&call.to);
+            });
+            row.col(|ui| {
+                let timestamp = call.invited.saturating_sub(first_ts);
+                ui.label(format!("{:.4}", timestamp.as_secs_f64()));
+            });
+            row.col(|ui| {
+                ui.label(format_duration(call.setup_time()));
+            });
+            row.col(|ui| {
+                let duration = call
+                    .answered
+                    .zip(call.ended)
+                    .and_then(|(answered, ended)| ended.checked_sub(answered));
+                ui.label(format_duration(duration));
+            });
+            row.col(|ui| {
+                ui.label(call_state(call));
+            });
+            row.col(|ui| {
+                ui.vertical(|ui| {
+                    for stream in &call_streams[ix] {
+                        ui.label(stream_label(call, stream));
+                    }
+                });
+            });
+        });
+    }
+}
+
+fn call_state(call: &Call) -> String {
+    match (&call.teardown, call.answered) {
+        (Some(teardown), _) => teardown.to_string(),
+        (None, Some(_)) => "Established".to_string(),
+        (None, None) => "Setting up".to_string(),
+    }
+}
+
+fn stream_label(call: &Call, stream: &RtpStream) -> String {
+    let key = (
+        stream.source_addr,
+        stream.destination_addr,
+        stream.protocol,
+        stream.ssrc,
+    );
+    let codecs = call
+        .media(&key)
+        .map(|media| media.codecs.join(", "))
+        .unwrap_or_default();
+
+    format!(
+        "{}: {} → {} ({})",
+        stream.alias, stream.source_addr, stream.destination_addr, codecs
+    )
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{:.3} s", duration.as_secs_f64()),
+        None => "-".to_string(),
+    }
+}
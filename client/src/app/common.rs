@@ -64,6 +64,15 @@ macro_rules! impl_table_base {
                 self.filter_input.set_error(result.err());
             }
 
+            fn filter(&self) -> &str {
+                self.filter_input.get_filter()
+            }
+
+            fn set_filter(&mut self, filter: String) {
+                self.filter_input.filter_buffer = filter;
+                self.check_filter();
+            }
+
             fn build_header($self:&mut Self, $header: &mut TableRow) {
                 $header_impl
             }
@@ -109,6 +118,15 @@ macro_rules! impl_table_base {
                 self.filter_input.set_error(result.err());
             }
 
+            fn filter(&self) -> &str {
+                self.filter_input.get_filter()
+            }
+
+            fn set_filter(&mut self, filter: String) {
+                self.filter_input.filter_buffer = filter;
+                self.check_filter();
+            }
+
             fn build_header($self:&mut Self, $header: &mut TableRow) {
                 $header_impl
             }
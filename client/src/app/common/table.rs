@@ -7,6 +7,8 @@ pub trait TableBase {
     fn new(streams: RefStreams) -> Self;
     fn ui(&mut self, ctx: &Context);
     fn check_filter(&mut self);
+    fn filter(&self) -> &str;
+    fn set_filter(&mut self, filter: String);
     fn build_header(&mut self, header: &mut egui_extras::TableRow);
     fn build_table_body(&mut self, body: egui_extras::TableBody);
 }
@@ -0,0 +1,84 @@
+use eframe::egui::{self, Ui};
+use netpix_common::DecodeRule;
+
+/// Edits the rules the server decodes the packets with before guessing their protocols.
+#[derive(Default)]
+pub struct DecodeRulesMenu {
+    rules: Vec<DecodeRule>,
+    // one rule per line, kept while being edited
+    text: String,
+    error: Option<String>,
+}
+
+impl DecodeRulesMenu {
+    /// Shows the rules the server uses, unless they're being edited.
+    pub fn set_rules(&mut self, rules: Vec<DecodeRule>) {
+        if self.text == format_rules(&self.rules) {
+            self.text = format_rules(&rules);
+        }
+        self.rules = rules;
+    }
+
+    /// Returns the rules to send once they're applied.
+    pub fn ui(&mut self, ui: &mut Ui) -> Option<Vec<DecodeRule>> {
+        let title = if self.rules.is_empty() {
+            "🔍 Decode as".to_string()
+        } else {
+            format!("🔍 Decode as ({})", self.rules.len())
+        };
+
+        let mut applied = None;
+        ui.menu_button(title, |ui| {
+            ui.label("Rules, the first matching one applies");
+            let editor = egui::TextEdit::multiline(&mut self.text)
+                .hint_text("5004=rtp\n5000-5010=rtcp\n239.1.1.1:8000=mpegts")
+                .desired_rows(6);
+            ui.add(editor).on_hover_text(
+                "PORT, PORT-PORT, ADDRESS or ADDRESS:PORT = rtp, rtcp, mpegts, stun, rtsp, sip or unknown",
+            );
+            if let Some(ref error) = self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            ui.label("Only the packets captured from now on are affected");
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    match parse_rules(&self.text) {
+                        Ok(rules) => {
+                            self.error = None;
+                            applied = Some(rules);
+                        }
+                        Err(error) => self.error = Some(error),
+                    }
+                }
+                if ui.button("Revert").clicked() {
+                    self.text = format_rules(&self.rules);
+                    self.error = None;
+                }
+            });
+
+            if applied.is_some() {
+                ui.close_menu();
+            }
+        });
+
+        applied
+    }
+}
+
+fn parse_rules(text: &str) -> Result<Vec<DecodeRule>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse())
+        .collect()
+}
+
+fn format_rules(rules: &[DecodeRule]) -> String {
+    rules
+        .iter()
+        .map(DecodeRule::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -160,7 +160,10 @@ impl FilterParser for FilterType {
                          - proto:tcp (TCP protocol)\n\
                          - proto:rtp (RTP protocol)\n\
                          - proto:rtcp (RTCP protocol)\n
-                         - proto:mpeg-ts (MPEG Transport Stream)"
+                         - proto:mpeg-ts (MPEG Transport Stream)\n\
+                         - proto:stun (STUN and TURN messages)\n\
+                         - proto:rtsp (RTSP messages)\n\
+                         - proto:sip (SIP messages)"
                             .into(),
                     )
                 }),
@@ -185,7 +188,10 @@ impl FilterParser for FilterType {
                         "Invalid packet type.\nMust be one of:\n\
                      - type:RTP (Real-time Transport Protocol)\n\
                      - type:RTCP (Real-time Control Protocol)\n\
-                     - type:MPEG-TS (MPEG Transport Stream)\n"
+                     - type:MPEG-TS (MPEG Transport Stream)\n\
+                     - type:STUN (STUN and TURN messages)\n\
+                     - type:RTSP (Real Time Streaming Protocol)\n\
+                     - type:SIP (Session Initiation Protocol)\n"
                             .into(),
                     )
                 }),
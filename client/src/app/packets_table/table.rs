@@ -10,7 +10,7 @@ use egui_extras::TableBody;
 use egui_extras::TableBuilder;
 use egui_extras::{Column, TableRow};
 use ewebsock::{WsMessage, WsSender};
-use netpix_common::packet::{Packet, SessionProtocol};
+use netpix_common::packet::{Packet, SessionPacket, SessionProtocol};
 use netpix_common::Request;
 
 declare_table_struct!(
@@ -26,7 +26,7 @@ impl_table_base!(
         .filter("dest:<ip>", "Filter by destination IP address")
         .filter(
             "proto:<protocol> or protocol:<protocol>",
-            "Filter by protocol (TCP, UDP, RTP, RTCP, MPEG-TS)",
+            "Filter by protocol (TCP, UDP, RTP, RTCP, MPEG-TS, STUN, RTSP, SIP)",
         )
         .filter("type:<protocol>", "Filter by protocol type")
         .filter("length:<op><size>", "Filter by packet size")
@@ -87,13 +87,19 @@ impl_table_base!(
             Vec::new()
         };
 
+        // only the visible rows are built
+        let mut is_top_visible = false;
         body.rows(self.config.row_height, filtered_packets.len(), |mut row| {
+            is_top_visible |= row.index() == 0;
             let packet = &filtered_packets[row.index()];
             let timestamp = packet.timestamp - first_ts;
 
             // ID column
             row.col(|ui| {
-                ui.label(packet.id.to_string());
+                let label = ui.label(packet.id.to_string());
+                if let Some(file) = streams.origin(packet) {
+                    label.on_hover_text(format!("Read from {}", file));
+                }
             });
 
             // Time column
@@ -113,7 +119,10 @@ impl_table_base!(
 
             // Protocol column
             row.col(|ui| {
-                ui.label(packet.transport_protocol.to_string());
+                match packet.framing {
+                    Some(framing) => ui.label(format!("{} ({})", packet.transport_protocol, framing)),
+                    None => ui.label(packet.transport_protocol.to_string()),
+                };
             });
 
             // Length column
@@ -123,7 +132,10 @@ impl_table_base!(
 
             // Session protocol column with context menu
             let (_, resp) = row.col(|ui| {
-                ui.label(packet.session_protocol.to_string());
+                let label = ui.label(session_label(packet));
+                if let Some(details) = session_details(packet) {
+                    label.on_hover_text(details);
+                }
             });
 
             resp.context_menu(|ui| {
@@ -134,6 +146,9 @@ impl_table_base!(
         });
 
         drop(streams);
+        if is_top_visible {
+            self.streams.borrow_mut().history.fetch_older();
+        }
         requests
             .iter()
             .for_each(|req| self.send_parse_request(req.clone()));
@@ -206,3 +221,44 @@ impl PacketsTable {
         }
     }
 }
+
+fn session_label(packet: &Packet) -> String {
+    match packet.relay {
+        Some(_) => format!("{} (TURN)", packet.session_protocol),
+        None => packet.session_protocol.to_string(),
+    }
+}
+
+/// Describes the STUN, RTSP or SIP message, or the TURN message the contents were relayed in.
+fn session_details(packet: &Packet) -> Option<String> {
+    let (stun, prefix) = match (&packet.relay, &packet.contents) {
+        (Some(relay), _) => (relay, "Relayed in "),
+        (None, SessionPacket::Stun(stun)) => (stun, ""),
+        (None, SessionPacket::Rtsp(rtsp)) => {
+            return Some(message_details(&rtsp.start_line, &rtsp.headers));
+        }
+        (None, SessionPacket::Sip(sip)) => {
+            return Some(message_details(&sip.start_line, &sip.headers));
+        }
+        _ => return None,
+    };
+
+    let mut lines = vec![format!("{}{}", prefix, stun)];
+    if let Some(transaction_id) = stun.transaction_id() {
+        lines.push(format!("Transaction ID: {}", transaction_id));
+    }
+    lines.extend(stun.attributes().iter().map(ToString::to_string));
+
+    Some(lines.join("\n"))
+}
+
+// the start line and the headers of a text message
+fn message_details(start_line: &impl ToString, headers: &[(String, String)]) -> String {
+    let mut lines = vec![start_line.to_string()];
+    lines.extend(
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value)),
+    );
+    lines.join("\n")
+}
@@ -18,6 +18,7 @@ use netpix_common::{Packet, RtcpPacket, RtpPacket};
 use std::cell::Ref;
 use std::collections::HashMap;
 use std::fmt::{Display, Error, Formatter};
+use std::time::Duration;
 
 struct PointData {
     x: f64,
@@ -253,17 +254,37 @@ impl RtpStreamsPlot {
             .show_axes([true, false])
             .label_formatter(|name, _value| name.to_string());
 
-        if self.requires_reset {
+        let response = if self.requires_reset {
             plot.reset().show(ui, |plot_ui| {
                 self.draw_points(plot_ui);
-            });
+            })
         } else {
             plot.show(ui, |plot_ui| {
                 self.draw_points(plot_ui);
-            });
-        }
+            })
+        };
         self.requires_reset = false;
         self.last_rtp_packets_len = number_of_rtp_packets;
+
+        self.fetch_earlier_packets(response.transform.bounds());
+    }
+
+    // packets before the first received one are shown once the plot is moved or zoomed out past it
+    fn fetch_earlier_packets(&mut self, bounds: &PlotBounds) {
+        if self.x_axis != RawTimestamp || bounds.min()[0] >= 0.0 {
+            return;
+        }
+
+        let mut streams = self.streams.borrow_mut();
+        let Some(first) = streams.packets.first().map(|packet| packet.timestamp) else {
+            return;
+        };
+        let Ok(before) = Duration::try_from_secs_f64(-bounds.min()[0]) else {
+            return;
+        };
+        streams
+            .history
+            .fetch_time_range(first.saturating_sub(before), first);
     }
 
     fn number_of_rtp_packets(&mut self) -> usize {
@@ -114,6 +114,8 @@ impl_table_base!(
                 self.stream_matches_filter(&ctx)
             })
             .collect();
+        // applied once the streams aren't borrowed by the rows anymore
+        let mut edited_alias = None;
 
         body.rows(self.config.row_height, filtered_streams.len(), |mut row| {
             let id = row.index();
@@ -123,11 +125,7 @@ impl_table_base!(
             let mut alias = stream.alias.clone();
             row.col(|ui| {
                 if ui.add(TextEdit::singleline(&mut alias).frame(false)).changed() {
-                    if let Ok(mut streams) = self.streams.try_borrow_mut() {
-                        if let Some(stream) = streams.rtp_streams.get_mut(key) {
-                            stream.alias = alias.clone();
-                        }
-                    }
+                    edited_alias = Some((**key, alias.clone()));
                 }
             });
 
@@ -247,6 +245,12 @@ impl_table_base!(
                 });
             });
         });
+
+        drop(filtered_streams);
+        drop(streams);
+        if let Some((key, alias)) = edited_alias {
+            self.streams.borrow_mut().set_alias(key, alias);
+        }
     }
 );
 
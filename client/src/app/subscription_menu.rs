@@ -0,0 +1,179 @@
+use crate::streams::RefStreams;
+use eframe::egui::{self, Ui};
+use netpix_common::packet::SessionProtocol;
+use netpix_common::{MpegtsStreamKey, PortRange, RtpStreamKey, Subscription};
+use std::collections::BTreeSet;
+
+const PROTOCOLS: [SessionProtocol; 7] = [
+    SessionProtocol::Rtp,
+    SessionProtocol::Rtcp,
+    SessionProtocol::Mpegts,
+    SessionProtocol::Stun,
+    SessionProtocol::Rtsp,
+    SessionProtocol::Sip,
+    SessionProtocol::Unknown,
+];
+
+/// Picks the packets the server forwards for the current source.
+pub struct SubscriptionMenu {
+    streams: RefStreams,
+    protocols: Vec<SessionProtocol>,
+    rtp_streams: BTreeSet<RtpStreamKey>,
+    mpegts_streams: BTreeSet<MpegtsStreamKey>,
+    ports: String,
+    ports_error: Option<String>,
+    active: bool,
+}
+
+impl SubscriptionMenu {
+    pub fn new(streams: RefStreams) -> Self {
+        Self {
+            streams,
+            protocols: Vec::new(),
+            rtp_streams: BTreeSet::new(),
+            mpegts_streams: BTreeSet::new(),
+            ports: String::new(),
+            ports_error: None,
+            active: false,
+        }
+    }
+
+    /// Returns the subscription to send once it's applied or reset.
+    pub fn ui(&mut self, ui: &mut Ui) -> Option<Subscription> {
+        let title = if self.active {
+            "🔔 Subscribed"
+        } else {
+            "🔔 Subscribe"
+        };
+
+        let mut subscription = None;
+        ui.menu_button(title, |ui| {
+            ui.label("Protocols");
+            for protocol in PROTOCOLS {
+                let mut checked = self.protocols.contains(&protocol);
+                if ui.checkbox(&mut checked, protocol.to_string()).changed() {
+                    if checked {
+                        self.protocols.push(protocol);
+                    } else {
+                        self.protocols.retain(|other| *other != protocol);
+                    }
+                }
+            }
+
+            ui.separator();
+            ui.label("Ports");
+            ui.text_edit_singleline(&mut self.ports)
+                .on_hover_text("e.g. 5000-5010, 8000");
+            if let Some(ref error) = self.ports_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            ui.separator();
+            self.build_streams(ui);
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Apply").clicked() {
+                    match parse_ports(&self.ports) {
+                        Ok(ports) => {
+                            self.ports_error = None;
+                            subscription = Some(Subscription {
+                                protocols: self.protocols.clone(),
+                                rtp_streams: self.rtp_streams.iter().copied().collect(),
+                                mpegts_streams: self.mpegts_streams.iter().copied().collect(),
+                                ports,
+                            });
+                        }
+                        Err(error) => self.ports_error = Some(error),
+                    }
+                }
+                if ui.button("Reset").clicked() {
+                    *self = Self::new(self.streams.clone());
+                    subscription = Some(Subscription::default());
+                }
+            });
+
+            if subscription.is_some() {
+                ui.close_menu();
+            }
+        });
+
+        if let Some(ref subscription) = subscription {
+            self.active = !subscription.is_empty();
+        }
+
+        subscription
+    }
+
+    fn build_streams(&mut self, ui: &mut Ui) {
+        let streams = self.streams.borrow();
+
+        // selected streams are listed even if none of their packets arrived yet
+        let mut rtp_streams: BTreeSet<_> = streams.rtp_streams.keys().copied().collect();
+        rtp_streams.extend(self.rtp_streams.iter().copied());
+        let mut mpegts_streams: BTreeSet<_> = streams.mpeg_ts_streams.keys().copied().collect();
+        mpegts_streams.extend(self.mpegts_streams.iter().copied());
+
+        if rtp_streams.is_empty() && mpegts_streams.is_empty() {
+            ui.label("No streams");
+            return;
+        }
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                if !rtp_streams.is_empty() {
+                    ui.label("RTP streams");
+                }
+                for key in rtp_streams {
+                    let (source, destination, _, ssrc) = key;
+                    let alias = match streams.rtp_streams.get(&key) {
+                        Some(stream) => stream.alias.clone(),
+                        None => format!("{:x}", ssrc),
+                    };
+                    let text = format!("{} {} → {}", alias, source, destination);
+                    toggle(ui, &mut self.rtp_streams, key, text);
+                }
+
+                if !mpegts_streams.is_empty() {
+                    ui.label("MPEG-TS streams");
+                }
+                for key in mpegts_streams {
+                    let (source, destination, _) = key;
+                    let text = match streams.mpeg_ts_streams.get(&key) {
+                        Some(stream) => format!("{} {} → {}", stream.alias, source, destination),
+                        None => format!("{} → {}", source, destination),
+                    };
+                    toggle(ui, &mut self.mpegts_streams, key, text);
+                }
+            });
+    }
+}
+
+fn toggle<K: Ord>(ui: &mut Ui, selected: &mut BTreeSet<K>, key: K, text: String) {
+    let mut checked = selected.contains(&key);
+    if ui.checkbox(&mut checked, text).changed() {
+        if checked {
+            selected.insert(key);
+        } else {
+            selected.remove(&key);
+        }
+    }
+}
+
+fn parse_ports(ports: &str) -> Result<Vec<PortRange>, String> {
+    ports
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = start.trim().parse::<u16>();
+            let end = end.trim().parse::<u16>();
+            match (start, end) {
+                (Ok(start), Ok(end)) if start <= end => Ok(PortRange::new(start, end)),
+                _ => Err(format!("Invalid port range `{}`", range)),
+            }
+        })
+        .collect()
+}
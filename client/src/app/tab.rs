@@ -10,6 +10,7 @@ pub enum Tab {
     Packets,
     RtpSection(RtpSection),
     MpegTsSection(MpegTsSection),
+    Calls,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -31,6 +32,7 @@ impl Tab {
         let mut tabs = vec![Self::Packets];
         tabs.extend(RtpSection::iter().map(Self::RtpSection));
         tabs.extend(MpegTsSection::iter().map(Self::MpegTsSection));
+        tabs.push(Self::Calls);
         tabs
     }
 
@@ -45,6 +47,7 @@ impl Tab {
                 "📺 MPEG-TS".to_string(),
                 MpegTsSection::iter().map(Self::MpegTsSection).collect(),
             ),
+            ("📞 SIP".to_string(), vec![Self::Calls]),
         ]
     }
 
@@ -59,6 +62,7 @@ impl Tab {
             Self::Packets => "📦 Packets".to_string(),
             Self::RtpSection(section) => section.display_name(),
             Self::MpegTsSection(section) => section.display_name(),
+            Self::Calls => "📞 Calls".to_string(),
         }
     }
 }
@@ -69,6 +73,7 @@ impl fmt::Display for Tab {
             Self::Packets => write!(f, "📦 Packets"),
             Self::RtpSection(section) => section.fmt(f),
             Self::MpegTsSection(section) => section.fmt(f),
+            Self::Calls => write!(f, "📞 Calls"),
         }
     }
 }
@@ -0,0 +1,41 @@
+use eframe::wasm_bindgen::{JsCast, JsValue};
+use eframe::web_sys::{
+    self,
+    js_sys::{self, Uint8Array},
+};
+use log::{error, info};
+use std::sync::Arc;
+
+const UPLOAD_PATH: &str = "upload";
+
+/// Sends a pcap file dropped into the browser to the server,
+/// which adds it to the sources once it's stored.
+pub fn upload_file(name: String, bytes: Arc<[u8]>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match post(&name, &bytes).await {
+            Ok(()) => info!("Uploaded {}", name),
+            Err(err) => error!("Failed to upload {}: {:?}", name, err),
+        }
+    });
+}
+
+async fn post(name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or("No window")?;
+    let url = format!("/{}/{}", UPLOAD_PATH, js_sys::encode_uri_component(name));
+
+    let init = web_sys::RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&Uint8Array::from(bytes).into());
+
+    let response =
+        wasm_bindgen_futures::JsFuture::from(window.fetch_with_str_and_init(&url, &init))
+            .await?
+            .dyn_into::<web_sys::Response>()?;
+
+    if !response.ok() {
+        let message = wasm_bindgen_futures::JsFuture::from(response.text()?).await?;
+        return Err(message);
+    }
+
+    Ok(())
+}
@@ -1,15 +1,18 @@
 #![allow(dead_code)]
+pub use history::History;
 use mpegts_stream::MpegTsStream;
 use packets::Packets;
 use rtpStream::RtpStream;
 use netpix_common::packet::SessionPacket;
-use netpix_common::{packet::TransportProtocol, Packet, RtcpPacket};
-use netpix_common::{MpegtsStreamKey, RtpStreamKey};
+use netpix_common::sip::CallTracker;
+use netpix_common::{packet::TransportProtocol, Packet, RtcpPacket, Source};
+use netpix_common::{MpegtsStreamKey, RtpStreamKey, Sdp};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::rc::Rc;
 
+mod history;
 pub mod mpegts_stream;
 mod packets;
 #[allow(non_snake_case)]
@@ -23,6 +26,15 @@ pub struct Streams {
     pub packets: Packets,
     pub rtp_streams: HashMap<RtpStreamKey, RtpStream>,
     pub mpeg_ts_streams: HashMap<MpegtsStreamKey, MpegTsStream>,
+    pub calls: CallTracker,
+    pub history: History,
+    /// Source of the packets, which tells the files they were read from
+    pub source: Option<Source>,
+    // the streams need to be recalculated, see `refresh`
+    stale: bool,
+    // assigned to the streams, also the ones yet to be (re)created
+    aliases: HashMap<RtpStreamKey, String>,
+    sdps: HashMap<RtpStreamKey, Sdp>,
 }
 
 impl Streams {
@@ -30,34 +42,107 @@ impl Streams {
         self.packets.clear();
         self.rtp_streams.clear();
         self.mpeg_ts_streams.clear();
+        self.calls.clear();
+        self.history = History::default();
+        self.stale = false;
+    }
+
+    /// Clears the streams along with the aliases and SDPs assigned to them, e.g. for another source.
+    pub fn reset(&mut self) {
+        self.clear();
+        self.aliases.clear();
+        self.sdps.clear();
+    }
+
+    /// File the packet was read from, if the source is one or several files.
+    pub fn origin(&self, packet: &Packet) -> Option<&str> {
+        self.source.as_ref()?.origin(packet)
     }
 
     pub fn add_packet(&mut self, packet: Packet) {
         let is_new = self.packets.is_new(&packet);
 
-        if is_new {
-            handle_packet(&mut self.rtp_streams, &mut self.mpeg_ts_streams, &packet);
+        if is_new && !self.stale {
+            let stream_count = self.rtp_streams.len();
+            handle_packet(
+                &mut self.rtp_streams,
+                &mut self.mpeg_ts_streams,
+                &mut self.calls,
+                &packet,
+            );
             self.packets.add_packet(packet);
+            if self.rtp_streams.len() != stream_count {
+                self.apply_assignments();
+            }
         } else {
             // if the packet is not new (its id is smaller that the last packet's id)
-            // that this must be result of `parse_as` request or refetch (tho packets should be
-            // pruned before refetch) in that case, recalculate everything,
-            // this can be optimised if it proves to be to slow
+            // that this must be result of `parse_as` request or of fetching older packets,
+            // in that case everything is recalculated once the whole batch is added
             self.packets.add_packet(packet);
+            self.stale = true;
+        }
+    }
+
+    /// Recalculates the streams if packets were added out of order since the last call.
+    pub fn refresh(&mut self) {
+        if self.stale {
             self.recalculate();
+            self.stale = false;
         }
     }
 
     fn recalculate(&mut self) {
         let mut new_rtp_streams = HashMap::new();
         let mut new_mpegts_streams = HashMap::new();
+        let mut new_calls = CallTracker::default();
 
         self.packets.values().for_each(|packet| {
-            handle_packet(&mut new_rtp_streams, &mut new_mpegts_streams, packet)
+            handle_packet(
+                &mut new_rtp_streams,
+                &mut new_mpegts_streams,
+                &mut new_calls,
+                packet,
+            )
         });
 
         self.rtp_streams = new_rtp_streams;
         self.mpeg_ts_streams = new_mpegts_streams;
+        self.calls = new_calls;
+        self.apply_assignments();
+    }
+
+    pub fn set_alias(&mut self, key: RtpStreamKey, alias: String) {
+        if let Some(stream) = self.rtp_streams.get_mut(&key) {
+            stream.alias = alias.clone();
+        }
+        self.aliases.insert(key, alias);
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = (&RtpStreamKey, &String)> {
+        self.aliases.iter()
+    }
+
+    pub fn add_sdp(&mut self, key: RtpStreamKey, sdp: Sdp) {
+        if let Some(stream) = self.rtp_streams.get_mut(&key) {
+            stream.add_sdp(sdp.clone());
+        }
+        self.sdps.insert(key, sdp);
+    }
+
+    fn apply_assignments(&mut self) {
+        for (key, alias) in self.aliases.iter() {
+            if let Some(stream) = self.rtp_streams.get_mut(key) {
+                stream.alias.clone_from(alias);
+            }
+        }
+
+        for (key, sdp) in self.sdps.iter() {
+            if let Some(stream) = self.rtp_streams.get_mut(key) {
+                if !stream.has_sdp() {
+                    stream.add_sdp(sdp.clone());
+                }
+            }
+        }
     }
 }
 
@@ -66,9 +151,11 @@ impl Streams {
 fn handle_packet(
     rtp_streams: &mut HashMap<RtpStreamKey, RtpStream>,
     mpegts_streams: &mut HashMap<MpegtsStreamKey, MpegTsStream>,
+    calls: &mut CallTracker,
     packet: &Packet,
 ) {
     match packet.contents {
+        SessionPacket::Sip(_) => calls.add_packet(packet),
         SessionPacket::Mpegts(ref mpegts) => {
             let stream_key = (
                 packet.source_addr,
@@ -0,0 +1,74 @@
+use netpix_common::Request;
+use std::ops::Range;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fetch {
+    Ids(Range<usize>),
+    Time(Range<Duration>),
+}
+
+/// Packets buffered by the server older than the received ones,
+/// which are fetched page by page once they are needed.
+#[derive(Debug, Default)]
+pub struct History {
+    // ids of the packets left out of the last fetched page
+    older: Option<Range<usize>>,
+    pending: bool,
+    last: Option<Fetch>,
+    request: Option<Fetch>,
+}
+
+impl History {
+    pub fn has_older(&self) -> bool {
+        self.older.is_some()
+    }
+
+    /// Fetches the next page of the packets left out so far.
+    pub fn fetch_older(&mut self) {
+        if let Some(ref older) = self.older {
+            self.fetch(Fetch::Ids(older.clone()));
+        }
+    }
+
+    pub fn fetch_time_range(&mut self, from: Duration, to: Duration) {
+        if from < to {
+            self.fetch(Fetch::Time(from..to));
+        }
+    }
+
+    fn fetch(&mut self, fetch: Fetch) {
+        // the same range would be sent again until its packets arrive
+        if self.pending || self.last.as_ref() == Some(&fetch) {
+            return;
+        }
+
+        self.pending = true;
+        self.last = Some(fetch.clone());
+        self.request = Some(fetch);
+    }
+
+    pub fn fetched(&mut self, remaining: usize, from_id: usize, to_id: usize) {
+        self.pending = false;
+        // packets older than a whole time range may still be left
+        if remaining > 0 || !matches!(self.last, Some(Fetch::Time(_))) {
+            self.older = (remaining > 0).then_some(from_id..to_id);
+        }
+    }
+
+    /// Returns the request to be sent to the server, if any packets are needed.
+    pub fn take_request(&mut self) -> Option<Request> {
+        let request = match self.request.take()? {
+            Fetch::Ids(ids) => Request::FetchRange {
+                from_id: ids.start,
+                to_id: ids.end,
+            },
+            Fetch::Time(time) => Request::FetchTimeRange {
+                from: time.start,
+                to: time.end,
+            },
+        };
+
+        Some(request)
+    }
+}
@@ -22,6 +22,13 @@ impl Packets {
         }
     }
 
+    pub fn last(&self) -> Option<&Packet> {
+        match self.packets.last_key_value() {
+            Some((_, v)) => Some(v),
+            _ => None,
+        }
+    }
+
     pub fn values(&self) -> Values<'_, usize, Packet> {
         self.packets.values()
     }
@@ -31,7 +38,9 @@ impl Packets {
     }
 
     pub fn is_new(&self, packet: &Packet) -> bool {
-        !self.packets.contains_key(&packet.id)
+        self.packets
+            .last_key_value()
+            .map_or(true, |(id, _)| packet.id > *id)
     }
 
     pub fn is_empty(&self) -> bool {
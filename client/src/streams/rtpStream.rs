@@ -110,6 +110,10 @@ impl RtpStream {
         self.recalculate();
     }
 
+    pub fn has_sdp(&self) -> bool {
+        self.sdp.is_some()
+    }
+
     pub fn get_duration(&self) -> Duration {
         self.last_time.saturating_sub(self.first_time)
     }
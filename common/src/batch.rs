@@ -0,0 +1,66 @@
+use crate::Response;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{Read, Write};
+
+impl Response {
+    /// Coalesces responses, each already encoded with [`Response::encode`],
+    /// into a single gzip-compressed [`Response::Batch`].
+    pub fn batch<T: AsRef<[u8]>>(encoded: &[T]) -> Result<Self, bincode::Error> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+
+        // same layout as an encoded `Vec<Response>`, without decoding the responses again
+        encoder.write_all(&(encoded.len() as u64).to_le_bytes())?;
+        for response in encoded {
+            encoder.write_all(response.as_ref())?;
+        }
+
+        Ok(Self::Batch(encoder.finish()?))
+    }
+
+    /// Decompresses the responses of a [`Response::Batch`].
+    pub fn unbatch(compressed: &[u8]) -> Result<Vec<Self>, bincode::Error> {
+        let mut encoded = Vec::new();
+        GzDecoder::new(compressed).read_to_end(&mut encoded)?;
+
+        bincode::deserialize(&encoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PacketsStats, Source};
+
+    #[test]
+    fn batch_round_trip() {
+        let responses = vec![
+            Response::Sources(vec![Source::File("rtp.pcap".to_string())]),
+            Response::Dropped(3),
+            Response::PacketsStats(PacketsStats::default()),
+        ];
+        let encoded: Vec<_> = responses.iter().map(|r| r.encode().unwrap()).collect();
+
+        let batch =
+            Response::decode(&Response::batch(&encoded).unwrap().encode().unwrap()).unwrap();
+        let Response::Batch(compressed) = batch else {
+            panic!("expected a batch, got {:?}", batch);
+        };
+        let decoded = Response::unbatch(&compressed).unwrap();
+
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", responses));
+    }
+
+    #[test]
+    fn empty_batch() {
+        let Response::Batch(compressed) = Response::batch::<Vec<u8>>(&[]).unwrap() else {
+            unreachable!();
+        };
+
+        assert!(Response::unbatch(&compressed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupted_batch() {
+        assert!(Response::unbatch(b"not gzip").is_err());
+    }
+}
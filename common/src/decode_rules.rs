@@ -0,0 +1,202 @@
+use crate::packet::SessionProtocol;
+use crate::{Packet, PortRange};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Packets a decode rule applies to, matched against both their source and destination.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleTarget {
+    Ports(PortRange),
+    Address(IpAddr),
+    SocketAddress(SocketAddr),
+}
+
+/// Decodes the matching packets as the protocol instead of guessing it, written
+/// as `TARGET=PROTOCOL`, e.g. `5004=rtp`, `5000-5010=rtcp`, `239.1.1.1=mpegts`
+/// or `239.1.1.1:5004=rtp`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DecodeRule {
+    pub target: RuleTarget,
+    pub protocol: SessionProtocol,
+}
+
+impl RuleTarget {
+    fn matches_addr(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Ports(ports) => ports.contains(addr.port()),
+            Self::Address(ip) => addr.ip() == *ip,
+            Self::SocketAddress(target) => addr == target,
+        }
+    }
+}
+
+impl DecodeRule {
+    pub fn matches(&self, packet: &Packet) -> bool {
+        self.target.matches_addr(&packet.source_addr)
+            || self.target.matches_addr(&packet.destination_addr)
+    }
+
+    /// The protocol of the first rule matching the packet.
+    pub fn find(rules: &[DecodeRule], packet: &Packet) -> Option<SessionProtocol> {
+        rules
+            .iter()
+            .find(|rule| rule.matches(packet))
+            .map(|rule| rule.protocol)
+    }
+}
+
+impl FromStr for RuleTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        if let Ok(port) = target.parse::<u16>() {
+            return Ok(Self::Ports(PortRange::new(port, port)));
+        }
+        if let Some((start, end)) = target.split_once('-') {
+            return match (start.parse::<u16>(), end.parse::<u16>()) {
+                (Ok(start), Ok(end)) if start <= end => Ok(Self::Ports(PortRange::new(start, end))),
+                _ => Err(format!("invalid port range `{}`", target)),
+            };
+        }
+        if let Ok(addr) = target.parse::<SocketAddr>() {
+            return Ok(Self::SocketAddress(addr));
+        }
+        if let Ok(ip) = target.parse::<IpAddr>() {
+            return Ok(Self::Address(ip));
+        }
+
+        Err(format!(
+            "`{}` is neither a port, a port range nor an address",
+            target
+        ))
+    }
+}
+
+impl FromStr for DecodeRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let Some((target, protocol)) = rule.split_once('=') else {
+            return Err(format!("`{}` isn't written as TARGET=PROTOCOL", rule));
+        };
+
+        let target = target.trim().parse()?;
+        let Ok(protocol) = protocol.trim().parse() else {
+            return Err(format!("unknown protocol `{}`", protocol.trim()));
+        };
+
+        Ok(Self { target, protocol })
+    }
+}
+
+impl fmt::Display for RuleTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ports(ports) if ports.start == ports.end => write!(f, "{}", ports.start),
+            Self::Ports(ports) => write!(f, "{}-{}", ports.start, ports.end),
+            Self::Address(ip) => write!(f, "{}", ip),
+            Self::SocketAddress(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+impl fmt::Display for DecodeRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            self.target,
+            self.protocol.to_string().to_lowercase()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn packet(source: &str, destination: &str) -> Packet {
+        Packet::build_from_datagram(
+            &[],
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: DecodeRule = "5004=rtp".parse().unwrap();
+        assert_eq!(rule.target, RuleTarget::Ports(PortRange::new(5004, 5004)));
+        assert_eq!(rule.protocol, SessionProtocol::Rtp);
+
+        let rule: DecodeRule = "5000-5010 = RTCP".parse().unwrap();
+        assert_eq!(rule.target, RuleTarget::Ports(PortRange::new(5000, 5010)));
+        assert_eq!(rule.protocol, SessionProtocol::Rtcp);
+
+        let rule: DecodeRule = "239.1.1.1=mpegts".parse().unwrap();
+        assert_eq!(
+            rule.target,
+            RuleTarget::Address("239.1.1.1".parse().unwrap())
+        );
+
+        let rule: DecodeRule = "[ff02::1]:5004=unknown".parse().unwrap();
+        assert_eq!(
+            rule.target,
+            RuleTarget::SocketAddress("[ff02::1]:5004".parse().unwrap())
+        );
+        assert_eq!(rule.protocol, SessionProtocol::Unknown);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for rule in [
+            "5004",
+            "5004=h323",
+            "5010-5000=rtp",
+            "host=rtp",
+            "70000=rtp",
+        ] {
+            assert!(rule.parse::<DecodeRule>().is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn rule_string_round_trip() {
+        for rule in [
+            "5004=rtp",
+            "5000-5010=rtcp",
+            "10.0.0.1=mpeg-ts",
+            "[::1]:5004=rtp",
+        ] {
+            assert_eq!(rule.parse::<DecodeRule>().unwrap().to_string(), rule);
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules: Vec<DecodeRule> = ["239.1.1.1:5005=rtcp", "5004-5005=rtp", "10.0.0.1=mpeg-ts"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+
+        let find = |source, destination| DecodeRule::find(&rules, &packet(source, destination));
+        assert_eq!(
+            find("10.0.0.1:4000", "239.1.1.1:5005"),
+            Some(SessionProtocol::Rtcp)
+        );
+        assert_eq!(
+            find("10.0.0.1:5004", "239.1.1.1:6000"),
+            Some(SessionProtocol::Rtp)
+        );
+        assert_eq!(
+            find("10.0.0.1:4000", "239.1.1.1:6000"),
+            Some(SessionProtocol::Mpegts)
+        );
+        assert_eq!(find("10.0.0.2:4000", "239.1.1.1:6000"), None);
+    }
+}
@@ -1,72 +1,186 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::Duration;
 
 pub use crate::mpegts::MpegtsPacket;
 pub use crate::rtcp::RtcpPacket;
 pub use crate::rtp::RtpPacket;
+pub use crate::rtsp::RtspPacket;
+pub use crate::sip::SipPacket;
+pub use crate::stun::StunPacket;
 pub use packet::Packet;
 pub use sdp::Sdp;
 
+mod batch;
+mod decode_rules;
 pub mod mpegts;
 pub mod packet;
 pub mod rtcp;
 pub mod rtp;
+pub mod rtsp;
 pub mod sdp;
+pub mod sip;
 mod stream_keys;
+pub mod stun;
+mod subscription;
+#[cfg(not(target_arch = "wasm32"))]
+mod text_message;
 pub mod utils;
 
+pub use decode_rules::{DecodeRule, RuleTarget};
 pub use stream_keys::{MpegtsStreamKey, PacketAssociationTable, RtpStreamKey};
+pub use subscription::{PortRange, Subscription};
 
 pub const PACKET_MAX_AGE_SECS: u64 = 120; // 2 minutes
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
 pub enum Source {
     File(String),
+    /// Files merged into a single source, their packets ordered by timestamps
+    Files(Vec<String>),
     Interface(String),
+    /// UDP socket receiving the datagrams sent to a (multicast) address, without capturing
+    Socket(String),
 }
 
 impl Source {
+    /// Parses the string produced by `Display`, the name may contain spaces.
     pub fn from_string(src_str: String) -> Option<Self> {
-        let words: Vec<_> = src_str.split(' ').collect();
-
-        if words.len() != 2 {
+        let (icon, name) = src_str.split_once(' ')?;
+        if name.is_empty() {
             return None;
         }
 
-        let name = words.last().unwrap().to_string();
-
-        match *words.first().unwrap() {
+        let name = name.to_string();
+        match icon {
             "📁" => Some(Source::File(name)),
+            "📚" => Some(Source::Files(Self::split_files(&name))),
             "🌐" => Some(Source::Interface(name)),
+            "📡" => Some(Source::Socket(name)),
             _ => None,
         }
     }
+
+    /// Joins the merged files with commas, escaping the commas and backslashes of their paths.
+    pub fn join_files(files: &[String]) -> String {
+        let escaped: Vec<_> = files
+            .iter()
+            .map(|file| file.replace('\\', "\\\\").replace(',', "\\,"))
+            .collect();
+        escaped.join(",")
+    }
+
+    /// Splits the files joined by [`Source::join_files`].
+    pub fn split_files(files: &str) -> Vec<String> {
+        let mut split = Vec::new();
+        let mut file = String::new();
+        let mut chars = files.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => file.extend(chars.next()),
+                ',' => split.push(std::mem::take(&mut file)),
+                _ => file.push(c),
+            }
+        }
+        split.push(file);
+        split
+    }
+
+    /// File the packet was read from, one of the merged files for those sources.
+    pub fn origin(&self, packet: &Packet) -> Option<&str> {
+        match self {
+            Self::File(file) => Some(file),
+            Self::Files(files) => files.get(packet.origin?).map(String::as_str),
+            Self::Interface(_) | Self::Socket(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::File(_) => "file",
+            Self::Files(_) => "merged files",
+            Self::Interface(_) => "interface",
+            Self::Socket(_) => "socket",
+        }
+    }
 }
 
 impl fmt::Display for Source {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (icon, name) = match self {
-            Self::File(file) => ("📁", file),
-            Self::Interface(interface) => ("🌐", interface),
-        };
-
-        write!(f, "{} {}", icon, name)
+        match self {
+            Self::File(file) => write!(f, "📁 {}", file),
+            Self::Files(files) => write!(f, "📚 {}", Self::join_files(files)),
+            Self::Interface(interface) => write!(f, "🌐 {}", interface),
+            Self::Socket(address) => write!(f, "📡 {}", address),
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Request {
-    FetchAll,
+    /// Fetches the buffered packets of the current source with ids within `from_id..to_id`,
+    /// only the most recent ones if there are too many, see [`Response::FetchedRange`].
+    FetchRange {
+        from_id: usize,
+        to_id: usize,
+    },
+    /// Like [`Request::FetchRange`], for the packets with timestamps within `from..to`.
+    FetchTimeRange {
+        from: Duration,
+        to: Duration,
+    },
     Reparse(usize, packet::SessionProtocol),
     ChangeSource(Source),
     ParseSdp(RtpStreamKey, String),
-    PacketsStats(PacketsStats),
+    PauseReplay,
+    ResumeReplay,
+    SeekReplay(Duration),
+    /// Opens a pcap file of the server's upload directory, e.g. uploaded earlier.
+    AddFile(String),
+    /// Starts capturing on a network interface of the server.
+    AddInterface(String),
+    /// Starts receiving on a UDP socket of the server, joining the multicast group if needed.
+    AddSocket(String),
+    RemoveSource(Source),
+    /// Limits the packets of the current source forwarded to the client.
+    Subscribe(Subscription),
+    /// Saves the packets of all the sources along with the client's view to the session file.
+    SaveSession(SessionView),
+    /// Replaces the rules deciding the protocols of the packets captured from now on.
+    SetDecodeRules(Vec<DecodeRule>),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct PacketsStats {
     pub discharged: usize,
     pub overwritten: usize,
+    pub fragments: FragmentStats,
+    /// Counters of libpcap, only known for the sources capturing on interfaces
+    pub capture: Option<CaptureStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FragmentStats {
+    /// IPv4/IPv6 fragments received
+    pub fragments: usize,
+    /// datagrams successfully put back together
+    pub reassembled: usize,
+    /// incomplete datagrams discarded after the timeout or to make room for new ones
+    pub expired: usize,
+    /// fragments discarded as truncated, oversized or inconsistent with the rest
+    pub dropped: usize,
+}
+
+/// Packets seen by the capture of an interface before netpix could receive them,
+/// as reported by libpcap since the capture was opened.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// packets that passed the capture filter
+    pub received: u32,
+    /// packets dropped as the operating system's buffer was full, not read fast enough
+    pub dropped: u32,
+    /// packets dropped by the network interface or its driver
+    pub if_dropped: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -75,6 +189,75 @@ pub enum Response {
     Sources(Vec<Source>),
     Sdp(RtpStreamKey, Sdp),
     PacketsStats(PacketsStats),
+    ReplayState(ReplayState),
+    /// Gzip-compressed responses queued for the client, see [`Response::batch`].
+    Batch(Vec<u8>),
+    /// Number of packets dropped since the previous notice
+    /// because the client didn't keep up with them.
+    Dropped(usize),
+    /// Sent after the packets of a fetched range, `remaining` older packets of it,
+    /// with ids within `from_id..to_id`, were left out to be fetched later.
+    FetchedRange {
+        remaining: usize,
+        from_id: usize,
+        to_id: usize,
+    },
+    /// View of the session the server was started with, sent to the clients as they connect.
+    SessionView(SessionView),
+    /// Path of the written session file or the reason it couldn't be written.
+    SessionSaved(Result<String, String>),
+    /// Sent for every source along with [`Response::Sources`] and then periodically.
+    SourceInfo(SourceInfo),
+    /// Rules the server decodes the packets with, sent as the clients connect and once changed.
+    DecodeRules(Vec<DecodeRule>),
+}
+
+/// How a source was opened and how much it has captured so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceInfo {
+    pub source: Source,
+    /// Whether the interface captures in promiscuous mode
+    pub promisc: bool,
+    /// BPF filter applied to the capture, empty if there's none
+    pub filter: String,
+    /// Name of the link-layer header type, unknown for sockets and restored sessions
+    pub link_type: Option<String>,
+    /// When the source was opened, since the Unix epoch
+    pub start_time: Duration,
+    pub packets: usize,
+    /// Total length of the packets, as they were on the wire
+    pub bytes: u64,
+    /// Packets that couldn't be received or decoded
+    pub errors: usize,
+}
+
+/// Playback state of a file source replayed in real time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReplayState {
+    pub speed: f64,
+    pub paused: bool,
+    pub finished: bool,
+    /// timestamp of the first packet in the file
+    pub start: Duration,
+    /// time between the first and the last packet in the file
+    pub duration: Duration,
+    /// offset of the last replayed packet from `start`
+    pub position: Duration,
+    /// incremented on every backward seek, as the file is then replayed from its beginning
+    pub generation: usize,
+}
+
+/// State of the analysis kept on the client, saved with the session so that
+/// whoever opens it sees the same.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionView {
+    pub source: Option<Source>,
+    /// Display name of the selected tab
+    pub tab: Option<String>,
+    /// Filters of the tables, by the display name of their tab
+    pub filters: Vec<(String, String)>,
+    /// Aliases assigned to the RTP streams
+    pub aliases: Vec<(RtpStreamKey, String)>,
 }
 
 impl Request {
@@ -96,3 +279,42 @@ impl Response {
         bincode::serialize(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_string_round_trip() {
+        let sources = [
+            Source::File("/captures/call with spaces.pcap".to_string()),
+            Source::Files(vec!["part 0.pcap".to_string(), "part 1.pcap".to_string()]),
+            Source::Files(vec![
+                "a,b.pcap".to_string(),
+                "C:\\captures\\".to_string(),
+                "\\,".to_string(),
+            ]),
+            Source::Interface("eth0".to_string()),
+            Source::Socket("239.1.1.1:5004".to_string()),
+        ];
+
+        for source in sources {
+            assert_eq!(Source::from_string(source.to_string()), Some(source));
+        }
+    }
+
+    #[test]
+    fn escaped_files() {
+        let files = ["a,b.pcap".to_string(), "c\\d.pcap".to_string()];
+        assert_eq!(Source::join_files(&files), "a\\,b.pcap,c\\\\d.pcap");
+        assert_eq!(Source::split_files("a.pcap,b.pcap"), ["a.pcap", "b.pcap"]);
+        assert_eq!(Source::split_files("a\\,b.pcap"), ["a,b.pcap"]);
+    }
+
+    #[test]
+    fn invalid_source_string() {
+        assert_eq!(Source::from_string("📁".to_string()), None);
+        assert_eq!(Source::from_string("📁 ".to_string()), None);
+        assert_eq!(Source::from_string("🎵 song.pcap".to_string()), None);
+    }
+}
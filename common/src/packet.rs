@@ -1,4 +1,4 @@
-use super::{MpegtsPacket, RtcpPacket, RtpPacket};
+use super::{MpegtsPacket, RtcpPacket, RtpPacket, RtspPacket, SipPacket, StunPacket};
 use serde::{Deserialize, Serialize};
 
 use std::net::SocketAddr;
@@ -6,10 +6,20 @@ use std::str::FromStr;
 use std::time::Duration;
 use std::{fmt, time::SystemTime};
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod framing;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod link;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reassembly;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::DecodeRule;
+#[cfg(not(target_arch = "wasm32"))]
+use link::{LinkFrame, NetworkProtocol};
 #[cfg(not(target_arch = "wasm32"))]
 use pnet_packet::{
-    ethernet::{EtherTypes, EthernetPacket},
-    ip::IpNextHeaderProtocols,
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
     ipv4::Ipv4Packet,
     ipv6::Ipv6Packet,
     tcp::TcpPacket,
@@ -17,12 +27,23 @@ use pnet_packet::{
     Packet as _,
 };
 
+// without options or extension headers
+#[cfg(not(target_arch = "wasm32"))]
+const IPV4_HEADER_LEN: usize = 20;
+#[cfg(not(target_arch = "wasm32"))]
+const IPV6_HEADER_LEN: usize = 40;
+#[cfg(not(target_arch = "wasm32"))]
+const UDP_HEADER_LEN: usize = 8;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub enum SessionProtocol {
     Unknown,
     Rtp,
     Rtcp,
     Mpegts,
+    Stun,
+    Rtsp,
+    Sip,
 }
 
 impl FromStr for SessionProtocol {
@@ -32,7 +53,10 @@ impl FromStr for SessionProtocol {
             "unknown" => Ok(Self::Unknown),
             "rtp" => Ok(Self::Rtp),
             "rtcp" => Ok(Self::Rtcp),
-            "mpeg-ts" => Ok(Self::Mpegts),
+            "mpeg-ts" | "mpegts" => Ok(Self::Mpegts),
+            "stun" => Ok(Self::Stun),
+            "rtsp" => Ok(Self::Rtsp),
+            "sip" => Ok(Self::Sip),
             _ => Err(()),
         }
     }
@@ -40,7 +64,15 @@ impl FromStr for SessionProtocol {
 
 impl SessionProtocol {
     pub fn all() -> Vec<Self> {
-        vec![Self::Unknown, Self::Rtp, Self::Rtcp, Self::Mpegts]
+        vec![
+            Self::Unknown,
+            Self::Rtp,
+            Self::Rtcp,
+            Self::Mpegts,
+            Self::Stun,
+            Self::Rtsp,
+            Self::Sip,
+        ]
     }
 }
 
@@ -51,6 +83,9 @@ impl fmt::Display for SessionProtocol {
             Self::Rtp => "RTP",
             Self::Rtcp => "RTCP",
             Self::Mpegts => "MPEG-TS",
+            Self::Stun => "STUN",
+            Self::Rtsp => "RTSP",
+            Self::Sip => "SIP",
         };
 
         write!(f, "{}", res)
@@ -74,12 +109,33 @@ impl fmt::Display for TransportProtocol {
     }
 }
 
+/// How a packet was framed on a TCP stream.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TcpFraming {
+    /// Prefixed with its length, see RFC 4571
+    LengthPrefixed,
+    /// Interleaved with RTSP messages on the channel, see RFC 2326
+    Interleaved(u8),
+}
+
+impl fmt::Display for TcpFraming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LengthPrefixed => write!(f, "RFC 4571"),
+            Self::Interleaved(channel) => write!(f, "interleaved {}", channel),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SessionPacket {
     Unknown,
     Rtp(RtpPacket),
     Rtcp(Vec<RtcpPacket>),
     Mpegts(MpegtsPacket),
+    Stun(StunPacket),
+    Rtsp(RtspPacket),
+    Sip(SipPacket),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -94,23 +150,78 @@ pub struct Packet {
     pub session_protocol: SessionProtocol,
     pub contents: SessionPacket,
     pub creation_time: SystemTime,
+    /// Index of the file the packet was read from, for sources merging several files
+    pub origin: Option<usize>,
+    /// TURN message the contents were relayed in, they're parsed out of its data
+    pub relay: Option<StunPacket>,
+    /// Framing of a packet split out of a TCP stream, the payload is the framed data
+    pub framing: Option<TcpFraming>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl Packet {
-    pub fn build(raw_packet: &pcap::Packet, id: usize) -> Option<Self> {
-        let ethernet_packet = EthernetPacket::new(raw_packet)?;
+    pub fn build(raw_packet: &pcap::Packet, id: usize, link_type: pcap::Linktype) -> Option<Self> {
+        let frame = LinkFrame::decode(raw_packet.data, link_type)?;
+        Self::build_from_frame(raw_packet, id, &frame)
+    }
 
-        match ethernet_packet.get_ethertype() {
-            EtherTypes::Ipv4 => Self::build_from_ip4(raw_packet, id, &ethernet_packet),
-            EtherTypes::Ipv6 => Self::build_from_ip6(raw_packet, id, &ethernet_packet),
-            _ => None,
+    /// Builds the packet out of an already decoded (and possibly reassembled) frame,
+    /// `raw_packet` is the last captured packet the frame consists of.
+    pub fn build_from_frame(
+        raw_packet: &pcap::Packet,
+        id: usize,
+        frame: &LinkFrame,
+    ) -> Option<Self> {
+        // account for the bytes cut off by the snapshot length
+        let truncated = raw_packet
+            .header
+            .len
+            .saturating_sub(raw_packet.header.caplen);
+        let length = frame.payload.len() as u32 + truncated;
+
+        match frame.network_protocol {
+            NetworkProtocol::Ipv4 => Self::build_from_ip4(raw_packet, id, length, frame),
+            NetworkProtocol::Ipv6 => Self::build_from_ip6(raw_packet, id, length, frame),
+        }
+    }
+
+    /// Builds the packet out of a datagram received on a UDP socket, which comes without
+    /// its headers, `length` accounts for them as if it were captured.
+    pub fn build_from_datagram(
+        payload: &[u8],
+        id: usize,
+        source_addr: SocketAddr,
+        destination_addr: SocketAddr,
+        timestamp: Duration,
+    ) -> Self {
+        let ip_header_len = if source_addr.is_ipv4() {
+            IPV4_HEADER_LEN
+        } else {
+            IPV6_HEADER_LEN
+        };
+        let length = (ip_header_len + UDP_HEADER_LEN + payload.len()) as u32;
+
+        Self {
+            payload: Some(payload.to_vec()),
+            id,
+            length,
+            timestamp,
+            source_addr,
+            destination_addr,
+            transport_protocol: TransportProtocol::Udp,
+            session_protocol: SessionProtocol::Unknown,
+            contents: SessionPacket::Unknown,
+            creation_time: SystemTime::now(),
+            origin: None,
+            relay: None,
+            framing: None,
         }
     }
 
     fn build_from_transport(
         raw_packet: &pcap::Packet,
         id: usize,
+        length: u32,
         source_addr: std::net::IpAddr,
         destination_addr: std::net::IpAddr,
         transport_protocol: TransportProtocol,
@@ -148,7 +259,7 @@ impl Packet {
         Some(Self {
             payload: Some(payload),
             id,
-            length: raw_packet.header.len - 14,
+            length,
             timestamp: get_duration(raw_packet),
             source_addr,
             destination_addr,
@@ -156,15 +267,19 @@ impl Packet {
             session_protocol: SessionProtocol::Unknown,
             contents: SessionPacket::Unknown,
             creation_time: SystemTime::now(),
+            origin: None,
+            relay: None,
+            framing: None,
         })
     }
 
     fn build_from_ip4(
         raw_packet: &pcap::Packet,
         id: usize,
-        ethernet_packet: &EthernetPacket,
+        length: u32,
+        frame: &LinkFrame,
     ) -> Option<Self> {
-        let ipv4_packet = Ipv4Packet::new(ethernet_packet.payload())?;
+        let ipv4_packet = Ipv4Packet::new(frame.payload)?;
         let source_addr = ipv4_packet.get_source();
         let destination_addr = ipv4_packet.get_destination();
         let ip_payload = ipv4_packet.payload();
@@ -173,10 +288,11 @@ impl Packet {
             return Self::build_from_transport(
                 raw_packet,
                 id,
+                length,
                 source_addr.into(),
                 destination_addr.into(),
                 TransportProtocol::Udp, // default to UDP for empty payload
-                ethernet_packet.payload(),
+                frame.payload,
             );
         }
 
@@ -189,6 +305,7 @@ impl Packet {
         Self::build_from_transport(
             raw_packet,
             id,
+            length,
             source_addr.into(),
             destination_addr.into(),
             transport_protocol,
@@ -199,9 +316,10 @@ impl Packet {
     fn build_from_ip6(
         raw_packet: &pcap::Packet,
         id: usize,
-        ethernet_packet: &EthernetPacket,
+        length: u32,
+        frame: &LinkFrame,
     ) -> Option<Self> {
-        let ipv6_packet = Ipv6Packet::new(ethernet_packet.payload())?;
+        let ipv6_packet = Ipv6Packet::new(frame.payload)?;
         let source_addr = ipv6_packet.get_source();
         let destination_addr = ipv6_packet.get_destination();
         let ip_payload = ipv6_packet.payload();
@@ -210,14 +328,21 @@ impl Packet {
             return Self::build_from_transport(
                 raw_packet,
                 id,
+                length,
                 source_addr.into(),
                 destination_addr.into(),
                 TransportProtocol::Udp, // default to UDP for empty payload
-                ethernet_packet.payload(),
+                frame.payload,
             );
         }
 
-        let transport_protocol = match ipv6_packet.get_next_header() {
+        // fragments are expected to go through the reassembler first
+        let upper = reassembly::ipv6_payload(ipv6_packet.get_next_header().0, ip_payload)?;
+        if upper.fragment.is_some() {
+            return None;
+        }
+
+        let transport_protocol = match IpNextHeaderProtocol(upper.next_header) {
             IpNextHeaderProtocols::Tcp => TransportProtocol::Tcp,
             IpNextHeaderProtocols::Udp => TransportProtocol::Udp,
             _ => return None,
@@ -226,41 +351,68 @@ impl Packet {
         Self::build_from_transport(
             raw_packet,
             id,
+            length,
             source_addr.into(),
             destination_addr.into(),
             transport_protocol,
-            ip_payload,
+            upper.payload,
         )
     }
 
+    /// Parses the payload as the protocol of the first matching rule, so that known ports
+    /// are always decoded the same way, or guesses the protocol otherwise.
+    pub fn decode_payload(&mut self, rules: &[DecodeRule]) {
+        match DecodeRule::find(rules, self) {
+            Some(protocol) => self.parse_as(protocol),
+            None => self.guess_payload(),
+        }
+    }
+
     pub fn guess_payload(&mut self) {
-        // could use port to determine validity
-        // TODO: STUN data, TURN channels, RTCP
+        // port based hints are given by the decode rules, see `decode_payload`
+        // TODO: RTCP
         //
         // also, some UDP ports are used by other protocols
         // see Wireshark -> View -> Internals -> Dissector Table -> UDP port
         if self.transport_protocol != TransportProtocol::Udp {
+            // RTP and RTCP are split out of TCP streams, see `framing::Deframer`,
+            // the rest of the segments may be RTSP or SIP messages
+            let contents = match self.framing {
+                Some(_) => self.payload.as_deref().and_then(guess_media),
+                None => RtspPacket::build(self)
+                    .map(SessionPacket::Rtsp)
+                    .or_else(|| SipPacket::build(self).map(SessionPacket::Sip)),
+            };
+            if let Some(contents) = contents {
+                self.set_contents(contents, None);
+            }
             return;
         }
 
         if let Some(mpegts) = MpegtsPacket::build(self) {
-            self.session_protocol = SessionProtocol::Mpegts;
-            self.contents = SessionPacket::Mpegts(mpegts);
+            self.set_contents(SessionPacket::Mpegts(mpegts), None);
             return;
         }
 
-        if let Some(rtcp) = RtcpPacket::build(self) {
-            if is_rtcp(&rtcp) {
-                self.session_protocol = SessionProtocol::Rtcp;
-                self.contents = SessionPacket::Rtcp(rtcp);
-                return;
-            }
+        // before STUN, the first bytes of some methods look like TURN channel numbers
+        if let Some(sip) = SipPacket::build(self) {
+            self.set_contents(SessionPacket::Sip(sip), None);
+            return;
         }
 
-        if let Some(rtp) = RtpPacket::build(self) {
-            if is_rtp(&rtp) {
-                self.session_protocol = SessionProtocol::Rtp;
-                self.contents = SessionPacket::Rtp(rtp);
+        let Some(payload) = self.payload.as_deref() else {
+            return;
+        };
+        match StunPacket::unmarshall(payload) {
+            // media relayed through TURN, STUN messages can't be mistaken for RTP or RTCP
+            Some((stun, relayed)) => match relayed.and_then(guess_media) {
+                Some(contents) => self.set_contents(contents, Some(stun)),
+                None => self.set_contents(SessionPacket::Stun(stun), None),
+            },
+            None => {
+                if let Some(contents) = guess_media(payload) {
+                    self.set_contents(contents, None);
+                }
             }
         }
     }
@@ -268,32 +420,89 @@ impl Packet {
     pub fn parse_as(&mut self, packet_type: SessionProtocol) {
         match packet_type {
             SessionProtocol::Rtp => {
-                let Some(rtp) = RtpPacket::build(self) else {
+                let (relay, payload) = self.unwrap_relay();
+                let Some(rtp) = RtpPacket::unmarshall(payload) else {
                     return;
                 };
-                self.session_protocol = packet_type;
-                self.contents = SessionPacket::Rtp(rtp);
+                self.set_contents(SessionPacket::Rtp(rtp), relay);
             }
             SessionProtocol::Rtcp => {
-                let Some(rtcp) = RtcpPacket::build(self) else {
+                let (relay, payload) = self.unwrap_relay();
+                let Some(rtcp) = RtcpPacket::unmarshall(payload) else {
                     return;
                 };
-                self.session_protocol = packet_type;
-                self.contents = SessionPacket::Rtcp(rtcp);
+                self.set_contents(SessionPacket::Rtcp(rtcp), relay);
             }
             SessionProtocol::Mpegts => {
                 let Some(mpegts) = MpegtsPacket::build(self) else {
                     return;
                 };
-                self.session_protocol = packet_type;
-                self.contents = SessionPacket::Mpegts(mpegts);
+                self.set_contents(SessionPacket::Mpegts(mpegts), None);
+            }
+            SessionProtocol::Stun => {
+                let Some(stun) = StunPacket::build(self) else {
+                    return;
+                };
+                self.set_contents(SessionPacket::Stun(stun), None);
+            }
+            SessionProtocol::Rtsp => {
+                let Some(rtsp) = RtspPacket::build(self) else {
+                    return;
+                };
+                self.set_contents(SessionPacket::Rtsp(rtsp), None);
+            }
+            SessionProtocol::Sip => {
+                let Some(sip) = SipPacket::build(self) else {
+                    return;
+                };
+                self.set_contents(SessionPacket::Sip(sip), None);
             }
             SessionProtocol::Unknown => {
-                self.session_protocol = packet_type;
-                self.contents = SessionPacket::Unknown;
+                self.set_contents(SessionPacket::Unknown, None);
             }
         }
     }
+
+    /// Splits the payload into the TURN message and the data it relays,
+    /// the whole payload is returned if nothing is relayed.
+    fn unwrap_relay(&self) -> (Option<StunPacket>, &[u8]) {
+        let payload = self.payload.as_deref().unwrap_or_default();
+        match StunPacket::unmarshall(payload) {
+            Some((stun, Some(relayed))) => (Some(stun), relayed),
+            _ => (None, payload),
+        }
+    }
+
+    fn set_contents(&mut self, contents: SessionPacket, relay: Option<StunPacket>) {
+        self.session_protocol = match contents {
+            SessionPacket::Unknown => SessionProtocol::Unknown,
+            SessionPacket::Rtp(_) => SessionProtocol::Rtp,
+            SessionPacket::Rtcp(_) => SessionProtocol::Rtcp,
+            SessionPacket::Mpegts(_) => SessionProtocol::Mpegts,
+            SessionPacket::Stun(_) => SessionProtocol::Stun,
+            SessionPacket::Rtsp(_) => SessionProtocol::Rtsp,
+            SessionPacket::Sip(_) => SessionProtocol::Sip,
+        };
+        self.contents = contents;
+        self.relay = relay;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn guess_media(payload: &[u8]) -> Option<SessionPacket> {
+    if let Some(rtcp) = RtcpPacket::unmarshall(payload) {
+        if is_rtcp(&rtcp) {
+            return Some(SessionPacket::Rtcp(rtcp));
+        }
+    }
+
+    if let Some(rtp) = RtpPacket::unmarshall(payload) {
+        if is_rtp(&rtp) {
+            return Some(SessionPacket::Rtp(rtp));
+        }
+    }
+
+    None
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -331,7 +540,7 @@ fn is_rtcp(packets: &[RtcpPacket]) -> bool {
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn get_duration(raw_packet: &pcap::Packet) -> Duration {
+pub fn get_duration(raw_packet: &pcap::Packet) -> Duration {
     use std::ops::Add;
 
     // i64 -> u64, but seconds should never be negative
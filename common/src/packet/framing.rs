@@ -0,0 +1,587 @@
+use super::link::{LinkFrame, NetworkProtocol};
+use super::reassembly::ipv6_payload;
+use super::{guess_media, Packet, TcpFraming, TransportProtocol};
+use crate::rtsp;
+use pnet_packet::{
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    tcp::{TcpFlags, TcpPacket},
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub const DEFAULT_FLOW_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_FLOWS: usize = 256;
+
+const IPV4_HEADER_LENGTH: usize = 20;
+const IPV6_HEADER_LENGTH: usize = 40;
+const INTERLEAVED_MAGIC: u8 = b'$';
+const INTERLEAVED_HEADER_LENGTH: usize = 4;
+const LENGTH_PREFIX_LENGTH: usize = 2;
+// RTCP packets are the shorter ones
+const MIN_PACKET_LENGTH: usize = 8;
+const RTP_VERSION: u8 = 2;
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct FlowKey {
+    source: SocketAddr,
+    destination: SocketAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TcpSegment<'a> {
+    sequence: u32,
+    // the flow ends with the segment
+    last: bool,
+    payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    fn parse(frame: &LinkFrame<'a>) -> Option<Self> {
+        let data = match frame.network_protocol {
+            NetworkProtocol::Ipv4 => {
+                let packet = Ipv4Packet::new(frame.payload)?;
+                if packet.get_next_level_protocol() != IpNextHeaderProtocols::Tcp {
+                    return None;
+                }
+
+                let header_length = packet.get_header_length() as usize * 4;
+                let total_length = (packet.get_total_length() as usize).min(frame.payload.len());
+                frame
+                    .payload
+                    .get(header_length.max(IPV4_HEADER_LENGTH)..total_length)?
+            }
+            NetworkProtocol::Ipv6 => {
+                let next_header = *frame.payload.get(6)?;
+                let upper = ipv6_payload(next_header, frame.payload.get(IPV6_HEADER_LENGTH..)?)?;
+                if upper.fragment.is_some() || upper.next_header != IpNextHeaderProtocols::Tcp.0 {
+                    return None;
+                }
+                upper.payload
+            }
+        };
+
+        let packet = TcpPacket::new(data)?;
+        let flags = packet.get_flags();
+        Some(Self {
+            sequence: packet.get_sequence(),
+            last: flags & (TcpFlags::FIN | TcpFlags::RST) != 0,
+            payload: data.get(packet.get_data_offset() as usize * 4..)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Flow {
+    interleaved: bool,
+    next_sequence: u32,
+    last_seen: Duration,
+    // beginning of a frame continued by the next segments
+    buffer: Vec<u8>,
+}
+
+enum Frame {
+    Complete {
+        framing: TcpFraming,
+        header_length: usize,
+        length: usize,
+    },
+    Incomplete,
+    // the data isn't framed, e.g. an RTSP message in between the interleaved frames
+    Invalid,
+}
+
+impl Flow {
+    fn new(interleaved: bool, sequence: u32, timestamp: Duration) -> Self {
+        Self {
+            interleaved,
+            next_sequence: sequence,
+            last_seen: timestamp,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Detects the framing out of the first segment, which has to start with a frame
+    /// and contain at least one whole, so that segments of other streams are never held.
+    fn detect(payload: &[u8], sequence: u32, timestamp: Duration) -> Option<Self> {
+        let interleaved = *payload.first()? == INTERLEAVED_MAGIC;
+        let flow = Self::new(interleaved, sequence, timestamp);
+        if payload.len() <= flow.header_length() {
+            return None;
+        }
+
+        // every frame of the segment has to be RTP or RTCP, unlike the data
+        // length prefixed by chance, interleaved frames may be followed by an RTSP message
+        let mut offset = 0;
+        let mut frames = 0;
+        loop {
+            match flow.frame(&payload[offset..]) {
+                Frame::Complete {
+                    header_length,
+                    length,
+                    ..
+                } => {
+                    let start = offset + header_length;
+                    guess_media(&payload[start..start + length])?;
+                    offset = start + length;
+                    frames += 1;
+                }
+                Frame::Incomplete => break,
+                Frame::Invalid if flow.interleaved && frames > 0 => break,
+                Frame::Invalid => return None,
+            }
+        }
+
+        (frames > 0).then_some(flow)
+    }
+
+    fn header_length(&self) -> usize {
+        if self.interleaved {
+            INTERLEAVED_HEADER_LENGTH
+        } else {
+            LENGTH_PREFIX_LENGTH
+        }
+    }
+
+    fn frame(&self, data: &[u8]) -> Frame {
+        let header_length = self.header_length();
+        let Some(header) = data.get(..header_length) else {
+            return match data.first() {
+                Some(&byte) if self.interleaved && byte != INTERLEAVED_MAGIC => Frame::Invalid,
+                _ => Frame::Incomplete,
+            };
+        };
+
+        let (framing, length) = if self.interleaved {
+            if header[0] != INTERLEAVED_MAGIC {
+                return Frame::Invalid;
+            }
+            let channel = header[1];
+            let length = u16::from_be_bytes([header[2], header[3]]);
+            (TcpFraming::Interleaved(channel), length as usize)
+        } else {
+            let length = u16::from_be_bytes([header[0], header[1]]);
+            (TcpFraming::LengthPrefixed, length as usize)
+        };
+
+        if length < MIN_PACKET_LENGTH {
+            return Frame::Invalid;
+        }
+        match data.get(header_length) {
+            Some(&byte) if !is_rtp_version(byte) => Frame::Invalid,
+            _ if data.len() < header_length + length => Frame::Incomplete,
+            _ => Frame::Complete {
+                framing,
+                header_length,
+                length,
+            },
+        }
+    }
+}
+
+/// Per-capture stage splitting TCP streams into the RTP and RTCP packets framed on them,
+/// either prefixed with their length (RFC 4571) or interleaved with RTSP messages
+/// (RFC 2326, section 10.12). Streams idle for `timeout` of capture time are forgotten.
+#[derive(Debug)]
+pub struct Deframer {
+    timeout: Duration,
+    max_flows: usize,
+    flows: HashMap<FlowKey, Flow>,
+}
+
+impl Default for Deframer {
+    fn default() -> Self {
+        Self::new(DEFAULT_FLOW_TIMEOUT, DEFAULT_MAX_FLOWS)
+    }
+}
+
+impl Deframer {
+    pub fn new(timeout: Duration, max_flows: usize) -> Self {
+        Self {
+            timeout,
+            max_flows,
+            flows: HashMap::new(),
+        }
+    }
+
+    pub fn flow_count(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Forgets the streams, used when the capture starts over.
+    pub fn clear(&mut self) {
+        self.flows.clear();
+    }
+
+    /// Splits the segment into the packets framed in it, the first one keeps the ID
+    /// of the segment and the others are given `next_id`. Their lengths are the ones
+    /// of the frames, without the TCP/IP headers. Segments that don't carry framed
+    /// packets are returned as they are, and none are returned while a frame of a stream
+    /// detected as framed is incomplete.
+    pub fn deframe(
+        &mut self,
+        frame: &LinkFrame,
+        segment: Packet,
+        next_id: impl FnMut() -> usize,
+    ) -> Vec<Packet> {
+        if segment.transport_protocol != TransportProtocol::Tcp {
+            return vec![segment];
+        }
+
+        match TcpSegment::parse(frame) {
+            Some(tcp) => self.deframe_segment(tcp, segment, next_id),
+            None => vec![segment],
+        }
+    }
+
+    fn deframe_segment(
+        &mut self,
+        tcp: TcpSegment,
+        segment: Packet,
+        mut next_id: impl FnMut() -> usize,
+    ) -> Vec<Packet> {
+        self.expire(segment.timestamp);
+
+        let key = FlowKey {
+            source: segment.source_addr,
+            destination: segment.destination_addr,
+        };
+        if tcp.payload.is_empty() {
+            if tcp.last {
+                self.flows.remove(&key);
+            }
+            return vec![segment];
+        }
+
+        let mut payload = tcp.payload;
+        match self.flows.get(&key).map(|flow| flow.next_sequence) {
+            Some(next_sequence) if next_sequence == tcp.sequence => {}
+            Some(next_sequence) if is_before(tcp.sequence, next_sequence) => {
+                // retransmitted, possibly along with some new data
+                let retransmitted = next_sequence.wrapping_sub(tcp.sequence) as usize;
+                if retransmitted >= payload.len() {
+                    return vec![segment];
+                }
+                payload = &payload[retransmitted..];
+            }
+            _ => {
+                // the beginning of the stream or a segment following a lost one
+                self.flows.remove(&key);
+                let Some(flow) = Flow::detect(payload, tcp.sequence, segment.timestamp) else {
+                    return vec![segment];
+                };
+                if self.flows.len() >= self.max_flows {
+                    self.evict_oldest();
+                }
+                self.flows.insert(key, flow);
+            }
+        }
+
+        let Some(flow) = self.flows.get_mut(&key) else {
+            return vec![segment];
+        };
+        let segment = Packet {
+            payload: None,
+            ..segment
+        };
+
+        flow.next_sequence = flow.next_sequence.wrapping_add(payload.len() as u32);
+        flow.last_seen = segment.timestamp;
+        flow.buffer.extend_from_slice(payload);
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        let mut synchronized = true;
+        loop {
+            match flow.frame(&flow.buffer[offset..]) {
+                Frame::Complete {
+                    framing,
+                    header_length,
+                    length,
+                } => {
+                    let start = offset + header_length;
+                    packets.push(Packet {
+                        payload: Some(flow.buffer[start..start + length].to_vec()),
+                        length: (header_length + length) as u32,
+                        framing: Some(framing),
+                        ..segment.clone()
+                    });
+                    offset = start + length;
+                }
+                Frame::Incomplete => break,
+                Frame::Invalid => {
+                    // an RTSP message in between, which the frames may follow
+                    let data = &flow.buffer[offset..];
+                    let message_length = flow
+                        .interleaved
+                        .then(|| rtsp::message_length(data))
+                        .flatten();
+                    if let Some(length) = message_length {
+                        packets.push(Packet {
+                            payload: Some(data[..length].to_vec()),
+                            ..segment.clone()
+                        });
+                        offset += length;
+                        continue;
+                    }
+
+                    // a message continued in the next segments or, for length prefixes,
+                    // the stream is lost
+                    synchronized = flow.interleaved;
+                    packets.push(Packet {
+                        payload: Some(flow.buffer[offset..].to_vec()),
+                        ..segment.clone()
+                    });
+                    offset = flow.buffer.len();
+                    break;
+                }
+            }
+        }
+        flow.buffer.drain(..offset);
+
+        if !synchronized || tcp.last {
+            self.flows.remove(&key);
+        }
+
+        for (index, packet) in packets.iter_mut().enumerate() {
+            if index > 0 {
+                packet.id = next_id();
+            }
+        }
+        packets
+    }
+
+    fn expire(&mut self, now: Duration) {
+        let timeout = self.timeout;
+        self.flows
+            .retain(|_, flow| now.saturating_sub(flow.last_seen) <= timeout);
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .flows
+            .iter()
+            .min_by_key(|(_, flow)| flow.last_seen)
+            .map(|(key, _)| *key);
+
+        if let Some(key) = oldest {
+            self.flows.remove(&key);
+        }
+    }
+}
+
+fn is_rtp_version(byte: u8) -> bool {
+    byte >> 6 == RTP_VERSION
+}
+
+// sequence numbers wrap around
+fn is_before(sequence: u32, other: u32) -> bool {
+    (sequence.wrapping_sub(other) as i32) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{SessionPacket, SessionProtocol};
+
+    fn rtp(sequence_number: u16) -> Vec<u8> {
+        let mut packet = vec![0x80, 96];
+        packet.extend_from_slice(&sequence_number.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1, 0x12, 0x34, 0x56, 0x78]);
+        packet.extend_from_slice(&[0xab; 20]);
+        packet
+    }
+
+    fn interleaved(channel: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![INTERLEAVED_MAGIC, channel];
+        frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn length_prefixed(data: &[u8]) -> Vec<u8> {
+        let mut frame = (data.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    struct Stream {
+        deframer: Deframer,
+        sequence: u32,
+        id: usize,
+    }
+
+    impl Stream {
+        fn new() -> Self {
+            Self {
+                deframer: Deframer::default(),
+                sequence: 1000,
+                id: 0,
+            }
+        }
+
+        fn segment_at(&mut self, sequence: u32, payload: &[u8]) -> Vec<Packet> {
+            self.id += 1;
+            let mut segment = Packet::build_from_datagram(
+                payload,
+                self.id,
+                "10.0.0.1:554".parse().unwrap(),
+                "10.0.0.2:50000".parse().unwrap(),
+                Duration::from_secs(1),
+            );
+            segment.transport_protocol = TransportProtocol::Tcp;
+
+            let tcp = TcpSegment {
+                sequence,
+                last: false,
+                payload,
+            };
+            let id = &mut self.id;
+            self.deframer.deframe_segment(tcp, segment, || {
+                *id += 1;
+                *id
+            })
+        }
+
+        fn segment(&mut self, payload: &[u8]) -> Vec<Packet> {
+            let packets = self.segment_at(self.sequence, payload);
+            self.sequence += payload.len() as u32;
+            packets
+        }
+    }
+
+    fn payloads(packets: &[Packet]) -> Vec<Vec<u8>> {
+        packets
+            .iter()
+            .map(|packet| packet.payload.clone().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn interleaved_frames() {
+        let mut stream = Stream::new();
+        let mut data = [interleaved(0, &rtp(1)), interleaved(1, &rtp(2))].concat();
+        let third = interleaved(0, &rtp(3));
+        data.extend_from_slice(&third[..10]);
+
+        let packets = stream.segment(&data);
+        assert_eq!(payloads(&packets), vec![rtp(1), rtp(2)]);
+        assert_eq!(packets[0].framing, Some(TcpFraming::Interleaved(0)));
+        assert_eq!(packets[1].framing, Some(TcpFraming::Interleaved(1)));
+        assert_eq!(packets[0].length, 36);
+        assert_eq!((packets[0].id, packets[1].id), (1, 2));
+
+        // the RTSP response in between isn't framed
+        let response = b"RTSP/1.0 200 OK\r\nCSeq: 5\r\n\r\n";
+        let packets = stream.segment(&[&third[10..], &response[..]].concat());
+        assert_eq!(payloads(&packets), vec![rtp(3), response.to_vec()]);
+        assert_eq!(packets[1].framing, None);
+
+        let packets = stream.segment(&interleaved(0, &rtp(4)));
+        assert_eq!(payloads(&packets), vec![rtp(4)]);
+    }
+
+    #[test]
+    fn frames_after_rtsp_messages() {
+        let mut stream = Stream::new();
+        stream.segment(&interleaved(0, &rtp(1)));
+
+        let response = b"RTSP/1.0 200 OK\r\nCSeq: 6\r\nContent-Length: 5\r\n\r\nhello";
+        let request = b"GET_PARAMETER rtsp://camera/ RTSP/1.0\r\nCSeq: 7\r\n\r\n";
+        let data = [
+            &response[..],
+            &interleaved(0, &rtp(2)),
+            &interleaved(1, &rtp(3)),
+            &request[..],
+            &interleaved(0, &rtp(4)),
+        ]
+        .concat();
+
+        let packets = stream.segment(&data);
+        assert_eq!(
+            payloads(&packets),
+            vec![response.to_vec(), rtp(2), rtp(3), request.to_vec(), rtp(4)]
+        );
+        let framing: Vec<_> = packets.iter().map(|packet| packet.framing).collect();
+        assert_eq!(
+            framing,
+            [
+                None,
+                Some(TcpFraming::Interleaved(0)),
+                Some(TcpFraming::Interleaved(1)),
+                None,
+                Some(TcpFraming::Interleaved(0)),
+            ]
+        );
+
+        // a message continued in the next segment is passed on as it is
+        let packets = stream.segment(&response[..20]);
+        assert_eq!(payloads(&packets), vec![response[..20].to_vec()]);
+        let packets = stream.segment(&interleaved(0, &rtp(5)));
+        assert_eq!(payloads(&packets), vec![rtp(5)]);
+    }
+
+    #[test]
+    fn length_prefixed_frames() {
+        let mut stream = Stream::new();
+        let frame = length_prefixed(&rtp(2));
+
+        let packets = stream.segment(&[&length_prefixed(&rtp(1))[..], &frame[..5]].concat());
+        assert_eq!(payloads(&packets), vec![rtp(1)]);
+        let packets = stream.segment(&[&frame[5..], &length_prefixed(&rtp(3))[..]].concat());
+        assert_eq!(payloads(&packets), vec![rtp(2), rtp(3)]);
+        assert_eq!(packets[0].framing, Some(TcpFraming::LengthPrefixed));
+
+        let mut packet = packets[1].clone();
+        packet.guess_payload();
+        assert_eq!(packet.session_protocol, SessionProtocol::Rtp);
+        assert!(matches!(packet.contents, SessionPacket::Rtp(ref rtp) if rtp.sequence_number == 3));
+    }
+
+    #[test]
+    fn retransmitted_and_lost_segments() {
+        let mut stream = Stream::new();
+        let first = length_prefixed(&rtp(1));
+        let start = stream.sequence;
+        stream.segment(&first);
+
+        // already split, returned as it is
+        let packets = stream.segment_at(start, &first);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].framing, None);
+
+        // partly retransmitted
+        let second = length_prefixed(&rtp(2));
+        let packets = stream.segment_at(start + 10, &[&first[10..], &second[..]].concat());
+        assert_eq!(payloads(&packets), vec![rtp(2)]);
+
+        // after a lost segment, the stream is picked up at the next frame
+        stream.sequence += 100;
+        let packets = stream.segment(&rtp(3)[4..]);
+        assert_eq!(packets[0].framing, None);
+        let packets = stream.segment(&length_prefixed(&rtp(4)));
+        assert_eq!(payloads(&packets), vec![rtp(4)]);
+    }
+
+    #[test]
+    fn unframed_streams() {
+        let mut stream = Stream::new();
+        for payload in [
+            &b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"[..],
+            &[0x00, 0x04, 0x80, 0x00, 0x00, 0x00],
+            &[0x00, 0x20, 0x00, 0x00, 0x00, 0x00],
+            // an incomplete frame alone, e.g. in a TLS stream picked up mid-way
+            &[
+                0x05, 0xdc, 0x80, 0x17, 0x03, 0x03, 0x00, 0x40, 0x11, 0x22, 0x33, 0x44,
+            ],
+            // a complete frame that isn't RTP nor RTCP
+            &[0x00, 0x08, 0x80, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ] {
+            let packets = stream.segment(payload);
+            assert_eq!(payloads(&packets), vec![payload.to_vec()]);
+            assert_eq!(packets[0].framing, None);
+        }
+        assert_eq!(stream.deframer.flow_count(), 0);
+    }
+}
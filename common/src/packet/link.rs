@@ -0,0 +1,256 @@
+use pcap::Linktype;
+use pnet_packet::{
+    ethernet::{EtherType, EtherTypes, EthernetPacket},
+    sll::SLLPacket,
+    sll2::SLL2Packet,
+    vlan::VlanPacket,
+    PacketSize as _,
+};
+
+// DLT_RAW is 12 on most platforms and 14 on OpenBSD, both map to LINKTYPE_RAW in files
+const DLT_RAW: Linktype = Linktype(12);
+const DLT_RAW_OPENBSD: Linktype = Linktype(14);
+
+const LOOPBACK_HEADER_SIZE: usize = 4;
+// AF_INET is 2 everywhere, AF_INET6 depends on the OS that produced the capture
+const AF_INET: u32 = 2;
+const AF_INET6: [u32; 4] = [10, 24, 28, 30];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkProtocol {
+    Ipv4,
+    Ipv6,
+}
+
+/// Network layer packet with its link layer header stripped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkFrame<'a> {
+    pub network_protocol: NetworkProtocol,
+    pub header_length: usize,
+    pub payload: &'a [u8],
+}
+
+impl<'a> LinkFrame<'a> {
+    pub fn decode(data: &'a [u8], link_type: Linktype) -> Option<Self> {
+        match link_type {
+            Linktype::ETHERNET => Self::decode_ethernet(data),
+            Linktype::LINUX_SLL => Self::decode_sll(data),
+            Linktype::LINUX_SLL2 => Self::decode_sll2(data),
+            Linktype::RAW | DLT_RAW | DLT_RAW_OPENBSD | Linktype::IPV4 | Linktype::IPV6 => {
+                Self::decode_raw(data, 0)
+            }
+            // in the byte order of the host that captured it, which may not be this one
+            Linktype::NULL => {
+                Self::decode_loopback(data, &[u32::from_le_bytes, u32::from_be_bytes])
+            }
+            Linktype::LOOP => Self::decode_loopback(data, &[u32::from_be_bytes]),
+            _ => None,
+        }
+    }
+
+    fn decode_ethernet(data: &'a [u8]) -> Option<Self> {
+        let ethernet_packet = EthernetPacket::new(data)?;
+        let header_length = ethernet_packet.packet_size();
+
+        Self::decode_ethertype(data, ethernet_packet.get_ethertype(), header_length)
+    }
+
+    fn decode_sll(data: &'a [u8]) -> Option<Self> {
+        let sll_packet = SLLPacket::new(data)?;
+        let header_length = sll_packet.packet_size();
+
+        Self::decode_ethertype(data, sll_packet.get_protocol(), header_length)
+    }
+
+    fn decode_sll2(data: &'a [u8]) -> Option<Self> {
+        let sll2_packet = SLL2Packet::new(data)?;
+        let header_length = sll2_packet.packet_size();
+
+        Self::decode_ethertype(data, sll2_packet.get_protocol_type(), header_length)
+    }
+
+    fn decode_ethertype(
+        data: &'a [u8],
+        mut ethertype: EtherType,
+        mut header_length: usize,
+    ) -> Option<Self> {
+        // strip any number of stacked 802.1Q / 802.1ad (QinQ) tags
+        while matches!(
+            ethertype,
+            EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ
+        ) {
+            let vlan_packet = VlanPacket::new(data.get(header_length..)?)?;
+            ethertype = vlan_packet.get_ethertype();
+            header_length += vlan_packet.packet_size();
+        }
+
+        let network_protocol = match ethertype {
+            EtherTypes::Ipv4 => NetworkProtocol::Ipv4,
+            EtherTypes::Ipv6 => NetworkProtocol::Ipv6,
+            _ => return None,
+        };
+
+        Some(Self {
+            network_protocol,
+            header_length,
+            payload: data.get(header_length..)?,
+        })
+    }
+
+    fn decode_loopback(data: &'a [u8], byte_orders: &[fn([u8; 4]) -> u32]) -> Option<Self> {
+        let header: [u8; 4] = data.get(..LOOPBACK_HEADER_SIZE)?.try_into().ok()?;
+
+        let network_protocol =
+            byte_orders
+                .iter()
+                .find_map(|read_family| match read_family(header) {
+                    AF_INET => Some(NetworkProtocol::Ipv4),
+                    family if AF_INET6.contains(&family) => Some(NetworkProtocol::Ipv6),
+                    _ => None,
+                })?;
+
+        let frame = Self::decode_raw(data, LOOPBACK_HEADER_SIZE)?;
+        (frame.network_protocol == network_protocol).then_some(frame)
+    }
+
+    fn decode_raw(data: &'a [u8], header_length: usize) -> Option<Self> {
+        let payload = data.get(header_length..)?;
+
+        let network_protocol = match payload.first()? >> 4 {
+            4 => NetworkProtocol::Ipv4,
+            6 => NetworkProtocol::Ipv6,
+            _ => return None,
+        };
+
+        Some(Self {
+            network_protocol,
+            header_length,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPV4_HEADER: [u8; 4] = [0x45, 0x00, 0x00, 0x1c];
+    const IPV6_HEADER: [u8; 4] = [0x60, 0x00, 0x00, 0x00];
+
+    fn ethernet_header(ethertype: u16) -> Vec<u8> {
+        let mut header = vec![0; 12];
+        header.extend(ethertype.to_be_bytes());
+        header
+    }
+
+    fn vlan_tag(ethertype: u16) -> Vec<u8> {
+        let mut tag = vec![0x00, 0x64];
+        tag.extend(ethertype.to_be_bytes());
+        tag
+    }
+
+    #[test]
+    fn test_decode_ethernet() {
+        let data = [ethernet_header(0x0800), IPV4_HEADER.to_vec()].concat();
+        let frame = LinkFrame::decode(&data, Linktype::ETHERNET).unwrap();
+
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv4);
+        assert_eq!(frame.header_length, 14);
+        assert_eq!(frame.payload, IPV4_HEADER);
+    }
+
+    #[test]
+    fn test_decode_vlan_and_qinq() {
+        let data = [
+            ethernet_header(0x88a8),
+            vlan_tag(0x8100),
+            vlan_tag(0x86dd),
+            IPV6_HEADER.to_vec(),
+        ]
+        .concat();
+        let frame = LinkFrame::decode(&data, Linktype::ETHERNET).unwrap();
+
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv6);
+        assert_eq!(frame.header_length, 22);
+        assert_eq!(frame.payload, IPV6_HEADER);
+    }
+
+    #[test]
+    fn test_decode_linux_sll() {
+        let mut data = vec![0x00, 0x00, 0x00, 0x01, 0x00, 0x06];
+        data.extend([0; 8]);
+        data.extend(0x0800u16.to_be_bytes());
+        data.extend(IPV4_HEADER);
+        let frame = LinkFrame::decode(&data, Linktype::LINUX_SLL).unwrap();
+
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv4);
+        assert_eq!(frame.header_length, 16);
+        assert_eq!(frame.payload, IPV4_HEADER);
+    }
+
+    #[test]
+    fn test_decode_linux_sll2() {
+        let mut data = 0x86ddu16.to_be_bytes().to_vec();
+        data.extend([0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x06]);
+        data.extend([0; 8]);
+        data.extend(IPV6_HEADER);
+        let frame = LinkFrame::decode(&data, Linktype::LINUX_SLL2).unwrap();
+
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv6);
+        assert_eq!(frame.header_length, 20);
+        assert_eq!(frame.payload, IPV6_HEADER);
+    }
+
+    #[test]
+    fn test_decode_raw() {
+        let frame = LinkFrame::decode(&IPV4_HEADER, Linktype::RAW).unwrap();
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv4);
+        assert_eq!(frame.header_length, 0);
+
+        let frame = LinkFrame::decode(&IPV6_HEADER, DLT_RAW).unwrap();
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv6);
+
+        assert!(LinkFrame::decode(&[0x20, 0x00], Linktype::RAW).is_none());
+    }
+
+    #[test]
+    fn test_decode_loopback() {
+        let data = [AF_INET.to_ne_bytes().to_vec(), IPV4_HEADER.to_vec()].concat();
+        let frame = LinkFrame::decode(&data, Linktype::NULL).unwrap();
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv4);
+        assert_eq!(frame.header_length, 4);
+
+        let data = [30u32.to_be_bytes().to_vec(), IPV6_HEADER.to_vec()].concat();
+        let frame = LinkFrame::decode(&data, Linktype::LOOP).unwrap();
+        assert_eq!(frame.network_protocol, NetworkProtocol::Ipv6);
+        assert_eq!(frame.payload, IPV6_HEADER);
+    }
+
+    #[test]
+    fn test_decode_loopback_of_other_hosts() {
+        for family in [AF_INET.to_le_bytes(), AF_INET.to_be_bytes()] {
+            let data = [family.to_vec(), IPV4_HEADER.to_vec()].concat();
+            let frame = LinkFrame::decode(&data, Linktype::NULL).unwrap();
+            assert_eq!(frame.network_protocol, NetworkProtocol::Ipv4);
+        }
+
+        for family in [24u32.to_le_bytes(), 30u32.to_be_bytes()] {
+            let data = [family.to_vec(), IPV6_HEADER.to_vec()].concat();
+            let frame = LinkFrame::decode(&data, Linktype::NULL).unwrap();
+            assert_eq!(frame.network_protocol, NetworkProtocol::Ipv6);
+        }
+
+        // the header of LOOP is always big-endian
+        let data = [AF_INET.to_le_bytes().to_vec(), IPV4_HEADER.to_vec()].concat();
+        assert!(LinkFrame::decode(&data, Linktype::LOOP).is_none());
+        let data = [7u32.to_le_bytes().to_vec(), IPV4_HEADER.to_vec()].concat();
+        assert!(LinkFrame::decode(&data, Linktype::NULL).is_none());
+    }
+
+    #[test]
+    fn test_decode_unsupported() {
+        let data = [ethernet_header(0x0806), vec![0; 28]].concat();
+        assert!(LinkFrame::decode(&data, Linktype::ETHERNET).is_none());
+        assert!(LinkFrame::decode(&data, Linktype::IEEE802_11).is_none());
+    }
+}
@@ -0,0 +1,564 @@
+use super::get_duration;
+use super::link::{LinkFrame, NetworkProtocol};
+use crate::FragmentStats;
+use pnet_packet::{
+    ip::IpNextHeaderProtocol,
+    ipv4::{self, Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
+    ipv6::{Ipv6Packet, MutableIpv6Packet},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const DEFAULT_FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_PENDING_DATAGRAMS: usize = 1024;
+
+const MAX_DATAGRAM_LENGTH: usize = u16::MAX as usize;
+const IPV6_HEADER_LENGTH: usize = 40;
+const IPV6_FRAGMENT_HEADER_LENGTH: usize = 8;
+
+// extension headers that may precede the fragment header
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ipv6Fragment {
+    pub offset: usize,
+    pub more_fragments: bool,
+    pub identification: u32,
+}
+
+/// Upper layer of an IPv6 packet, found by skipping its extension headers.
+/// Walking stops right after the fragment header, as anything behind it
+/// may be a continuation of another fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ipv6Payload<'a> {
+    pub next_header: u8,
+    pub payload: &'a [u8],
+    pub fragment: Option<Ipv6Fragment>,
+}
+
+pub(crate) fn ipv6_payload(mut next_header: u8, mut payload: &[u8]) -> Option<Ipv6Payload> {
+    let mut fragment = None;
+
+    while fragment.is_none() {
+        let header_length = match next_header {
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS => {
+                (*payload.get(1)? as usize + 1) * 8
+            }
+            IPV6_FRAGMENT => {
+                let header = payload.get(..IPV6_FRAGMENT_HEADER_LENGTH)?;
+                let offset_and_flags = u16::from_be_bytes([header[2], header[3]]);
+                fragment = Some(Ipv6Fragment {
+                    // the offset is expressed in 8-byte units in the upper 13 bits
+                    offset: (offset_and_flags & !0b111) as usize,
+                    more_fragments: offset_and_flags & 1 == 1,
+                    identification: u32::from_be_bytes(header[4..8].try_into().ok()?),
+                });
+                IPV6_FRAGMENT_HEADER_LENGTH
+            }
+            _ => break,
+        };
+
+        next_header = *payload.first()?;
+        payload = payload.get(header_length..)?;
+    }
+
+    Some(Ipv6Payload {
+        next_header,
+        payload,
+        fragment,
+    })
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct DatagramKey {
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    identification: u32,
+}
+
+#[derive(Debug)]
+struct Fragment<'a> {
+    key: DatagramKey,
+    offset: usize,
+    more_fragments: bool,
+    // network header the datagram is rebuilt with, only kept from the first fragment
+    header: &'a [u8],
+    data: &'a [u8],
+    truncated: bool,
+}
+
+impl<'a> Fragment<'a> {
+    fn parse(frame: &LinkFrame<'a>) -> Option<Self> {
+        match frame.network_protocol {
+            NetworkProtocol::Ipv4 => Self::parse_ipv4(frame.payload),
+            NetworkProtocol::Ipv6 => Self::parse_ipv6(frame.payload),
+        }
+    }
+
+    fn parse_ipv4(data: &'a [u8]) -> Option<Self> {
+        let packet = Ipv4Packet::new(data)?;
+        let more_fragments = packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+        let offset = packet.get_fragment_offset() as usize * 8;
+        if !more_fragments && offset == 0 {
+            return None;
+        }
+
+        let header_length = packet.get_header_length() as usize * 4;
+        let total_length = packet.get_total_length() as usize;
+        let header = data.get(..header_length)?;
+        let payload = data.get(header_length..total_length.min(data.len()))?;
+
+        Some(Self {
+            key: DatagramKey {
+                source: packet.get_source().into(),
+                destination: packet.get_destination().into(),
+                protocol: packet.get_next_level_protocol().0,
+                identification: packet.get_identification() as u32,
+            },
+            offset,
+            more_fragments,
+            header,
+            data: payload,
+            truncated: data.len() < total_length,
+        })
+    }
+
+    fn parse_ipv6(data: &'a [u8]) -> Option<Self> {
+        let packet = Ipv6Packet::new(data)?;
+        let total_length = IPV6_HEADER_LENGTH + packet.get_payload_length() as usize;
+        let payload = data.get(IPV6_HEADER_LENGTH..total_length.min(data.len()))?;
+        let upper = ipv6_payload(packet.get_next_header().0, payload)?;
+        let fragment = upper.fragment?;
+
+        Some(Self {
+            key: DatagramKey {
+                source: packet.get_source().into(),
+                destination: packet.get_destination().into(),
+                protocol: upper.next_header,
+                identification: fragment.identification,
+            },
+            offset: fragment.offset,
+            more_fragments: fragment.more_fragments,
+            header: data.get(..IPV6_HEADER_LENGTH)?,
+            data: upper.payload,
+            truncated: data.len() < total_length,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct PendingDatagram {
+    first_seen: Duration,
+    header: Option<Vec<u8>>,
+    fragments: BTreeMap<usize, Vec<u8>>,
+    // known once the last fragment arrives
+    length: Option<usize>,
+}
+
+impl PendingDatagram {
+    fn new(first_seen: Duration) -> Self {
+        Self {
+            first_seen,
+            header: None,
+            fragments: BTreeMap::new(),
+            length: None,
+        }
+    }
+
+    fn insert(&mut self, fragment: &Fragment) -> bool {
+        let end = fragment.offset + fragment.data.len();
+        if end + fragment.header.len() > MAX_DATAGRAM_LENGTH {
+            return false;
+        }
+
+        if !fragment.more_fragments {
+            if self.length.is_some_and(|length| length != end) {
+                return false;
+            }
+            self.length = Some(end);
+        }
+        if self.length.is_some_and(|length| end > length) {
+            return false;
+        }
+
+        if fragment.offset == 0 {
+            self.header.get_or_insert_with(|| fragment.header.to_vec());
+        }
+        // retransmitted fragments keep the first copy
+        self.fragments
+            .entry(fragment.offset)
+            .or_insert_with(|| fragment.data.to_vec());
+
+        true
+    }
+
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let length = self.length?;
+        self.header.as_ref()?;
+
+        let mut payload = Vec::with_capacity(length);
+        for (offset, data) in &self.fragments {
+            if *offset > payload.len() {
+                return None;
+            }
+
+            let end = offset + data.len();
+            if end > payload.len() {
+                payload.extend_from_slice(&data[payload.len() - offset..]);
+            }
+        }
+
+        (payload.len() == length).then_some(payload)
+    }
+}
+
+/// Per-capture IPv4/IPv6 reassembly stage. Fragments are held until the whole
+/// datagram arrives, incomplete datagrams expire after `timeout` of capture time.
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_pending: usize,
+    pending: HashMap<DatagramKey, PendingDatagram>,
+    // the last reassembled datagram, borrowed by the returned frame
+    datagram: Vec<u8>,
+    stats: FragmentStats,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAGMENT_TIMEOUT, DEFAULT_MAX_PENDING_DATAGRAMS)
+    }
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration, max_pending: usize) -> Self {
+        Self {
+            timeout,
+            max_pending,
+            pending: HashMap::new(),
+            datagram: Vec::new(),
+            stats: FragmentStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FragmentStats {
+        self.stats
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drops incomplete datagrams without counting them as expired,
+    /// used when the capture starts over.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Returns the frame unchanged if it isn't a fragment, the complete datagram
+    /// once its last missing fragment arrives and `None` while it's incomplete.
+    pub fn reassemble<'a>(
+        &'a mut self,
+        frame: LinkFrame<'a>,
+        raw_packet: &pcap::Packet,
+    ) -> Option<LinkFrame<'a>> {
+        self.reassemble_at(frame, get_duration(raw_packet))
+    }
+
+    fn reassemble_at<'a>(
+        &'a mut self,
+        frame: LinkFrame<'a>,
+        timestamp: Duration,
+    ) -> Option<LinkFrame<'a>> {
+        self.expire(timestamp);
+
+        let Some(fragment) = Fragment::parse(&frame) else {
+            return Some(frame);
+        };
+
+        self.stats.fragments += 1;
+        if fragment.truncated {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        if !self.pending.contains_key(&fragment.key) && self.pending.len() >= self.max_pending {
+            self.evict_oldest();
+        }
+
+        let pending = self
+            .pending
+            .entry(fragment.key)
+            .or_insert_with(|| PendingDatagram::new(timestamp));
+
+        if !pending.insert(&fragment) {
+            self.pending.remove(&fragment.key);
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        let payload = pending.assemble()?;
+        let pending = self.pending.remove(&fragment.key)?;
+        let header = pending.header?;
+
+        self.datagram = match frame.network_protocol {
+            NetworkProtocol::Ipv4 => build_ipv4_datagram(header, &payload)?,
+            NetworkProtocol::Ipv6 => build_ipv6_datagram(header, fragment.key.protocol, &payload)?,
+        };
+        self.stats.reassembled += 1;
+
+        Some(LinkFrame {
+            network_protocol: frame.network_protocol,
+            header_length: frame.header_length,
+            payload: &self.datagram,
+        })
+    }
+
+    fn expire(&mut self, now: Duration) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, datagram| now.saturating_sub(datagram.first_seen) <= timeout);
+        self.stats.expired += before - self.pending.len();
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, datagram)| datagram.first_seen)
+            .map(|(key, _)| *key);
+
+        if let Some(key) = oldest {
+            self.pending.remove(&key);
+            self.stats.expired += 1;
+        }
+    }
+}
+
+fn build_ipv4_datagram(mut datagram: Vec<u8>, payload: &[u8]) -> Option<Vec<u8>> {
+    datagram.extend_from_slice(payload);
+    let total_length = u16::try_from(datagram.len()).ok()?;
+
+    let mut packet = MutableIpv4Packet::new(&mut datagram)?;
+    packet.set_total_length(total_length);
+    packet.set_flags(packet.get_flags() & !Ipv4Flags::MoreFragments);
+    packet.set_fragment_offset(0);
+    let checksum = ipv4::checksum(&packet.to_immutable());
+    packet.set_checksum(checksum);
+
+    Some(datagram)
+}
+
+fn build_ipv6_datagram(mut datagram: Vec<u8>, next_header: u8, payload: &[u8]) -> Option<Vec<u8>> {
+    // extension headers in front of the fragment header don't
+    // matter for decoding, so only the fixed header is kept
+    datagram.truncate(IPV6_HEADER_LENGTH);
+    datagram.extend_from_slice(payload);
+    let payload_length = u16::try_from(payload.len()).ok()?;
+
+    let mut packet = MutableIpv6Packet::new(&mut datagram)?;
+    packet.set_payload_length(payload_length);
+    packet.set_next_header(IpNextHeaderProtocol(next_header));
+
+    Some(datagram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::Packet as _;
+
+    const ETHERNET_HEADER_LENGTH: usize = 14;
+    const UDP: u8 = 17;
+
+    fn ipv4_fragment(id: u16, offset: usize, more_fragments: bool, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; 20];
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&((20 + data.len()) as u16).to_be_bytes());
+        packet[4..6].copy_from_slice(&id.to_be_bytes());
+        let flags = if more_fragments { 0x2000 } else { 0 };
+        packet[6..8].copy_from_slice(&(flags | (offset / 8) as u16).to_be_bytes());
+        packet[8] = 64;
+        packet[9] = UDP;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn ipv6_fragment(id: u32, offset: usize, more_fragments: bool, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; IPV6_HEADER_LENGTH];
+        packet[0] = 0x60;
+        // hop-by-hop options in front of the fragment header
+        let payload_length = 8 + IPV6_FRAGMENT_HEADER_LENGTH + data.len();
+        packet[4..6].copy_from_slice(&(payload_length as u16).to_be_bytes());
+        packet[6] = IPV6_HOP_BY_HOP;
+        packet[7] = 64;
+        packet[23] = 1;
+        packet[39] = 2;
+        packet.extend_from_slice(&[IPV6_FRAGMENT, 0, 1, 4, 0, 0, 0, 0]);
+        let offset_and_flags = offset as u16 | more_fragments as u16;
+        packet.extend_from_slice(&[UDP, 0]);
+        packet.extend_from_slice(&offset_and_flags.to_be_bytes());
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn frame(network_protocol: NetworkProtocol, payload: &[u8]) -> LinkFrame {
+        LinkFrame {
+            network_protocol,
+            header_length: ETHERNET_HEADER_LENGTH,
+            payload,
+        }
+    }
+
+    fn payload(length: usize) -> Vec<u8> {
+        (0..length).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_unfragmented_passthrough() {
+        let mut reassembler = Reassembler::default();
+        let packet = ipv4_fragment(1, 0, false, &payload(16));
+
+        let result = reassembler
+            .reassemble_at(frame(NetworkProtocol::Ipv4, &packet), Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(result.payload, packet);
+        assert_eq!(reassembler.stats(), FragmentStats::default());
+    }
+
+    #[test]
+    fn test_reassemble_ipv4_out_of_order() {
+        let mut reassembler = Reassembler::default();
+        let data = payload(40);
+        let first = ipv4_fragment(7, 0, true, &data[..16]);
+        let second = ipv4_fragment(7, 16, true, &data[16..32]);
+        let last = ipv4_fragment(7, 32, false, &data[32..]);
+
+        for fragment in [&last, &first] {
+            let result =
+                reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, fragment), Duration::ZERO);
+            assert!(result.is_none());
+        }
+
+        let result = reassembler
+            .reassemble_at(frame(NetworkProtocol::Ipv4, &second), Duration::ZERO)
+            .unwrap();
+        let packet = Ipv4Packet::new(result.payload).unwrap();
+
+        assert_eq!(result.header_length, ETHERNET_HEADER_LENGTH);
+        assert_eq!(packet.get_total_length(), 60);
+        assert_eq!(packet.get_fragment_offset(), 0);
+        assert_eq!(packet.get_flags() & Ipv4Flags::MoreFragments, 0);
+        assert_eq!(packet.get_checksum(), ipv4::checksum(&packet));
+        assert_eq!(packet.payload(), data);
+
+        let stats = reassembler.stats();
+        assert_eq!(stats.fragments, 3);
+        assert_eq!(stats.reassembled, 1);
+        assert_eq!(reassembler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_reassemble_ipv6_after_extension_header() {
+        let mut reassembler = Reassembler::default();
+        let data = payload(24);
+        let first = ipv6_fragment(0xdead, 0, true, &data[..16]);
+        let last = ipv6_fragment(0xdead, 16, false, &data[16..]);
+
+        let result =
+            reassembler.reassemble_at(frame(NetworkProtocol::Ipv6, &first), Duration::ZERO);
+        assert!(result.is_none());
+
+        let result = reassembler
+            .reassemble_at(frame(NetworkProtocol::Ipv6, &last), Duration::ZERO)
+            .unwrap();
+        let packet = Ipv6Packet::new(result.payload).unwrap();
+
+        assert_eq!(packet.get_payload_length(), 24);
+        assert_eq!(packet.get_next_header().0, UDP);
+        assert_eq!(packet.payload(), data);
+    }
+
+    #[test]
+    fn test_expire_incomplete_datagram() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 16);
+        let data = payload(32);
+        let first = ipv4_fragment(3, 0, true, &data[..16]);
+        let last = ipv4_fragment(3, 16, false, &data[16..]);
+
+        reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, &first), Duration::ZERO);
+        let result =
+            reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, &last), Duration::from_secs(2));
+
+        assert!(result.is_none());
+        assert_eq!(reassembler.stats().expired, 1);
+        assert_eq!(reassembler.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_evict_when_full() {
+        let mut reassembler = Reassembler::new(DEFAULT_FRAGMENT_TIMEOUT, 2);
+        let data = payload(16);
+
+        for id in 0..3 {
+            let fragment = ipv4_fragment(id, 0, true, &data);
+            let timestamp = Duration::from_millis(id as u64);
+            reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, &fragment), timestamp);
+        }
+
+        assert_eq!(reassembler.pending_count(), 2);
+        assert_eq!(reassembler.stats().expired, 1);
+    }
+
+    #[test]
+    fn test_drop_truncated_and_inconsistent_fragments() {
+        let mut reassembler = Reassembler::default();
+        let data = payload(32);
+
+        let mut truncated = ipv4_fragment(5, 0, true, &data[..16]);
+        truncated.truncate(30);
+        let result =
+            reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, &truncated), Duration::ZERO);
+        assert!(result.is_none());
+
+        let last = ipv4_fragment(6, 16, false, &data[16..]);
+        let beyond_end = ipv4_fragment(6, 32, true, &data[..16]);
+        for fragment in [&last, &beyond_end] {
+            reassembler.reassemble_at(frame(NetworkProtocol::Ipv4, fragment), Duration::ZERO);
+        }
+
+        let stats = reassembler.stats();
+        assert_eq!(stats.fragments, 3);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(reassembler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_ipv6_payload_without_fragment() {
+        let data = [
+            [IPV6_DESTINATION_OPTIONS, 0, 1, 4, 0, 0, 0, 0].as_slice(),
+            &[UDP, 0, 1, 4, 0, 0, 0, 0],
+            &[1, 2],
+        ]
+        .concat();
+        let upper = ipv6_payload(IPV6_HOP_BY_HOP, &data).unwrap();
+
+        assert_eq!(upper.next_header, UDP);
+        assert_eq!(upper.payload, [1, 2]);
+        assert!(upper.fragment.is_none());
+    }
+}
@@ -45,14 +45,17 @@ impl RtcpPacket {
 #[cfg(not(target_arch = "wasm32"))]
 impl RtcpPacket {
     pub fn build(packet: &super::Packet) -> Option<Vec<Self>> {
-        use rtcp::packet;
-
         // payload field should never be empty
         // except for when encoding the packet
-        let mut buffer: &[u8] = packet
+        let buffer = packet
             .payload
             .as_ref()
             .expect("Packet's payload field is empty");
+        Self::unmarshall(buffer)
+    }
+
+    pub(crate) fn unmarshall(mut buffer: &[u8]) -> Option<Vec<Self>> {
+        use rtcp::packet;
 
         let Ok(rtcp_packets) = packet::unmarshal(&mut buffer) else {
             return None;
@@ -20,15 +20,19 @@ pub struct RtpPacket {
 #[cfg(not(target_arch = "wasm32"))]
 impl RtpPacket {
     pub fn build(packet: &super::Packet) -> Option<Self> {
-        use rtp::packet::Packet;
-        use webrtc_util::marshal::Unmarshal;
-
         // payload field should never be empty
         // except for when encoding the packet
-        let mut buffer: &[u8] = packet
+        let buffer = packet
             .payload
             .as_ref()
             .expect("Packet's payload field is empty");
+        Self::unmarshall(buffer)
+    }
+
+    pub(crate) fn unmarshall(mut buffer: &[u8]) -> Option<Self> {
+        use rtp::packet::Packet;
+        use webrtc_util::marshal::Unmarshal;
+
         let Ok(Packet { header, payload }) = Packet::unmarshal(&mut buffer) else {
             return None;
         };
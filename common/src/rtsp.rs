@@ -0,0 +1,638 @@
+use crate::packet::TransportProtocol;
+use crate::{RtpStreamKey, Sdp};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::packet::SessionPacket;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::text_message::{TextMessage, TextSyntax};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::Packet;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::{HashMap, HashSet};
+
+#[cfg(not(target_arch = "wasm32"))]
+const SYNTAX: TextSyntax = TextSyntax {
+    version_prefix: "RTSP/",
+    is_method_char: |char| char.is_ascii_uppercase() || char == '_',
+};
+// requests of clients that never got their responses are dropped past that
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_PENDING_REQUESTS: usize = 256;
+// the oldest streams are forgotten past that, e.g. for cameras polled for hours
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_STREAMS: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RtspStartLine {
+    Request { method: String, uri: String },
+    Response { status: u16, reason: String },
+}
+
+/// RTSP request or response, the body is cut short if it continues in the next segments.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RtspPacket {
+    pub start_line: RtspStartLine,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RtspPacket {
+    /// Value of the header, their names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn cseq(&self) -> Option<u32> {
+        self.header("CSeq")?.parse().ok()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RtspPacket {
+    pub fn build(packet: &Packet) -> Option<Self> {
+        packet
+            .payload
+            .as_ref()
+            .and_then(|payload| Self::unmarshall(payload))
+    }
+
+    fn unmarshall(buffer: &[u8]) -> Option<Self> {
+        let message = SYNTAX.parse(buffer)?;
+        let start_line = RtspStartLine::parse(message.start_line)?;
+        let body = message.body(buffer, Some(content_length(&message)));
+
+        Some(Self {
+            start_line,
+            headers: message.headers,
+            body,
+        })
+    }
+}
+
+/// Length of the message the buffer begins with, if it's whole.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn message_length(buffer: &[u8]) -> Option<usize> {
+    let message = SYNTAX.parse(buffer)?;
+    RtspStartLine::parse(message.start_line)?;
+
+    let end = message.body_start.checked_add(content_length(&message))?;
+    (end <= buffer.len()).then_some(end)
+}
+
+// messages without a body may leave out the length
+#[cfg(not(target_arch = "wasm32"))]
+fn content_length(message: &TextMessage) -> usize {
+    message
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RtspStartLine {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let first = parts.next()?;
+        let second = parts.next()?;
+        let third = parts.next().unwrap_or_default();
+
+        if first.starts_with(SYNTAX.version_prefix) {
+            return Some(Self::Response {
+                status: second.parse().ok()?,
+                reason: third.to_string(),
+            });
+        }
+
+        if !SYNTAX.is_method(first) || !third.starts_with(SYNTAX.version_prefix) {
+            return None;
+        }
+
+        Some(Self::Request {
+            method: first.to_string(),
+            uri: second.to_string(),
+        })
+    }
+}
+
+/// Parameters of the `Transport` header negotiated by SETUP, only the first
+/// of the alternatives offered in a request is considered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtspTransport {
+    /// `RTP/AVP/TCP`, either interleaved or on a separate connection
+    pub tcp: bool,
+    pub multicast: bool,
+    pub destination: Option<IpAddr>,
+    pub source: Option<IpAddr>,
+    /// The first port of each range, the one of RTP
+    pub port: Option<u16>,
+    pub client_port: Option<u16>,
+    pub server_port: Option<u16>,
+    pub interleaved: Option<u8>,
+    pub ssrc: Option<u32>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RtspTransport {
+    pub fn parse(header: &str) -> Option<Self> {
+        let spec = header.split(',').next()?;
+        let mut params = spec.split(';').map(str::trim);
+        let protocol = params.next()?.to_uppercase();
+        if !protocol.starts_with("RTP/") {
+            return None;
+        }
+
+        let mut transport = Self {
+            tcp: protocol.ends_with("/TCP"),
+            ..Default::default()
+        };
+
+        // the first port of a range, e.g. `5000-5001`
+        let first_port = |value: &str| value.split('-').next()?.parse().ok();
+        for param in params {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            match name.to_lowercase().as_str() {
+                "multicast" => transport.multicast = true,
+                "destination" => transport.destination = value.parse().ok(),
+                "source" => transport.source = value.parse().ok(),
+                "port" => transport.port = first_port(value),
+                "client_port" => transport.client_port = first_port(value),
+                "server_port" => transport.server_port = first_port(value),
+                "interleaved" => {
+                    transport.interleaved = value
+                        .split('-')
+                        .next()
+                        .and_then(|channel| channel.parse().ok())
+                }
+                "ssrc" => transport.ssrc = u32::from_str_radix(value, 16).ok(),
+                _ => {}
+            }
+        }
+
+        Some(transport)
+    }
+}
+
+/// RTP stream set up by an RTSP session, described by the SDP of its media.
+#[derive(Debug, Clone)]
+pub struct RtspStream {
+    pub sdp: Sdp,
+    pub transport_protocol: TransportProtocol,
+    /// Unknown for multicast streams, sent from a port not negotiated by SETUP
+    pub source: Option<SocketAddr>,
+    pub destination: SocketAddr,
+    pub ssrc: Option<u32>,
+}
+
+impl RtspStream {
+    pub fn matches(&self, key: &RtpStreamKey) -> bool {
+        let (source, destination, transport_protocol, ssrc) = *key;
+        transport_protocol == self.transport_protocol
+            && destination == self.destination
+            && self.source.map_or(true, |expected| expected == source)
+            && self.ssrc.map_or(true, |expected| expected == ssrc)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RtspStream {
+    /// Describes the stream the server sends to the client, which sent the SETUP request.
+    fn new(sdp: Sdp, transport: &RtspTransport, client: SocketAddr, server: SocketAddr) -> Self {
+        // interleaved on the RTSP connection
+        if transport.tcp {
+            return Self {
+                sdp,
+                transport_protocol: TransportProtocol::Tcp,
+                source: Some(server),
+                destination: client,
+                ssrc: transport.ssrc,
+            };
+        }
+
+        let multicast = transport.multicast
+            || transport
+                .destination
+                .is_some_and(|destination| destination.is_multicast());
+        let destination_ip = transport.destination.unwrap_or(client.ip());
+        let destination_port = transport.port.or(transport.client_port).unwrap_or_default();
+        let source = match transport.server_port {
+            Some(port) if !multicast => Some(SocketAddr::new(
+                transport.source.unwrap_or(server.ip()),
+                port,
+            )),
+            _ => None,
+        };
+
+        Self {
+            sdp,
+            transport_protocol: TransportProtocol::Udp,
+            source,
+            destination: SocketAddr::new(destination_ip, destination_port),
+            ssrc: transport.ssrc,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct Media {
+    control: String,
+    sdp: Sdp,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Follows the RTSP sessions of a capture, the media described by DESCRIBE
+/// are matched with the streams set up by SETUP and then with the RTP streams.
+#[derive(Debug, Default)]
+pub struct RtspTracker {
+    // by the client, the server and the CSeq
+    requests: HashMap<(SocketAddr, SocketAddr, u32), RtspPacket>,
+    media: Vec<Media>,
+    streams: Vec<RtspStream>,
+    // RTP streams already matched, each gets the SDP of its media once
+    assigned: HashSet<RtpStreamKey>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RtspTracker {
+    /// Returns the SDP of the media set up by RTSP for the RTP stream of
+    /// the packet, only for its first packet.
+    pub fn add_packet(&mut self, packet: &Packet) -> Option<(RtpStreamKey, Sdp)> {
+        match packet.contents {
+            SessionPacket::Rtsp(ref rtsp) => {
+                let stream = self.add_message(packet, rtsp)?;
+                if self.streams.len() >= MAX_STREAMS {
+                    self.streams.remove(0);
+                }
+                self.streams.push(stream);
+                None
+            }
+            SessionPacket::Rtp(ref rtp) if !self.streams.is_empty() => {
+                let key = (
+                    packet.source_addr,
+                    packet.destination_addr,
+                    packet.transport_protocol,
+                    rtp.ssrc,
+                );
+                if self.assigned.contains(&key) {
+                    return None;
+                }
+                // the latest SETUP wins when a client sets up the same ports again
+                let stream = self
+                    .streams
+                    .iter()
+                    .rev()
+                    .find(|stream| stream.matches(&key))?;
+                self.assigned.insert(key);
+                Some((key, stream.sdp.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    // returns the stream set up once a SETUP request is answered
+    fn add_message(&mut self, packet: &Packet, rtsp: &RtspPacket) -> Option<RtspStream> {
+        let cseq = rtsp.cseq()?;
+
+        let RtspStartLine::Response { status, .. } = rtsp.start_line else {
+            if self.requests.len() >= MAX_PENDING_REQUESTS {
+                self.requests.clear();
+            }
+            let key = (packet.source_addr, packet.destination_addr, cseq);
+            self.requests.insert(key, rtsp.clone());
+            return None;
+        };
+
+        let key = (packet.destination_addr, packet.source_addr, cseq);
+        let request = self.requests.remove(&key)?;
+        let RtspStartLine::Request {
+            ref method,
+            ref uri,
+        } = request.start_line
+        else {
+            return None;
+        };
+        if !(200..300).contains(&status) {
+            return None;
+        }
+
+        match method.as_str() {
+            "DESCRIBE" => {
+                self.describe(uri, rtsp);
+                None
+            }
+            "SETUP" => {
+                let media = self.find_media(uri)?;
+                let transport = rtsp.header("Transport").or(request.header("Transport"))?;
+                let transport = RtspTransport::parse(transport)?;
+                let (client, server) = (packet.destination_addr, packet.source_addr);
+                Some(RtspStream::new(
+                    media.sdp.clone(),
+                    &transport,
+                    client,
+                    server,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn describe(&mut self, uri: &str, response: &RtspPacket) {
+        let is_sdp = response
+            .header("Content-Type")
+            .is_some_and(|content_type| content_type.eq_ignore_ascii_case("application/sdp"));
+        if !is_sdp {
+            return;
+        }
+
+        let base = response
+            .header("Content-Base")
+            .or(response.header("Content-Location"))
+            .unwrap_or(uri);
+
+        for section in media_sections(&response.body) {
+            let Some(sdp) = Sdp::build(section.clone()) else {
+                continue;
+            };
+            let control = section
+                .lines()
+                .find_map(|line| line.strip_prefix("a=control:"))
+                .map_or_else(|| base.to_string(), |control| resolve(base, control.trim()));
+
+            self.media.retain(|media| media.control != control);
+            self.media.push(Media { control, sdp });
+        }
+    }
+
+    fn find_media(&self, uri: &str) -> Option<&Media> {
+        let uri = uri.trim_end_matches('/');
+        self.media
+            .iter()
+            .rev()
+            .find(|media| media.control.trim_end_matches('/') == uri)
+    }
+}
+
+// the media descriptions of the session, each beginning with its `m=` line
+#[cfg(not(target_arch = "wasm32"))]
+fn media_sections(sdp: &str) -> Vec<String> {
+    let mut sections: Vec<String> = Vec::new();
+    for line in sdp.lines() {
+        if line.starts_with("m=") {
+            sections.push(String::new());
+        }
+        if let Some(section) = sections.last_mut() {
+            section.push_str(line);
+            section.push('\n');
+        }
+    }
+
+    sections
+}
+
+// control URLs are either absolute or relative to the base URL
+#[cfg(not(target_arch = "wasm32"))]
+fn resolve(base: &str, control: &str) -> String {
+    if control == "*" {
+        base.to_string()
+    } else if control.contains("://") {
+        control.to_string()
+    } else if base.ends_with('/') {
+        format!("{}{}", base, control)
+    } else {
+        format!("{}/{}", base, control)
+    }
+}
+
+impl fmt::Display for RtspStartLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request { method, uri } => write!(f, "{} {}", method, uri),
+            Self::Response { status, reason } => write!(f, "{} {}", status, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const CLIENT: &str = "10.0.0.2:50000";
+    const SERVER: &str = "10.0.0.1:554";
+
+    const SDP: &str = "v=0\r\n\
+                       o=- 1 1 IN IP4 10.0.0.1\r\n\
+                       s=Camera\r\n\
+                       t=0 0\r\n\
+                       a=control:*\r\n\
+                       m=video 0 RTP/AVP 96\r\n\
+                       a=rtpmap:96 H264/90000\r\n\
+                       a=control:trackID=1\r\n\
+                       m=audio 0 RTP/AVP 97\r\n\
+                       a=rtpmap:97 MPEG4-GENERIC/16000/1\r\n\
+                       a=control:trackID=2\r\n";
+
+    fn message(source: &str, destination: &str, text: &str) -> Packet {
+        let mut packet = Packet::build_from_datagram(
+            text.as_bytes(),
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::from_secs(1),
+        );
+        packet.transport_protocol = TransportProtocol::Tcp;
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    fn rtp(source: &str, destination: &str, ssrc: u32) -> Packet {
+        let mut payload = vec![0x80, 96, 0, 1, 0, 0, 0, 1];
+        payload.extend_from_slice(&ssrc.to_be_bytes());
+        payload.extend_from_slice(&[0xab; 20]);
+
+        let mut packet = Packet::build_from_datagram(
+            &payload,
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::from_secs(1),
+        );
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    fn describe(tracker: &mut RtspTracker) {
+        let request = "DESCRIBE rtsp://10.0.0.1/stream RTSP/1.0\r\nCSeq: 2\r\n\r\n";
+        let response = format!(
+            "RTSP/1.0 200 OK\r\n\
+             CSeq: 2\r\n\
+             Content-Base: rtsp://10.0.0.1/stream/\r\n\
+             Content-Type: application/sdp\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            SDP.len(),
+            SDP
+        );
+        assert!(tracker
+            .add_packet(&message(CLIENT, SERVER, request))
+            .is_none());
+        assert!(tracker
+            .add_packet(&message(SERVER, CLIENT, &response))
+            .is_none());
+    }
+
+    fn setup(tracker: &mut RtspTracker, track: &str, transport: &str) {
+        let request = format!(
+            "SETUP rtsp://10.0.0.1/stream/{} RTSP/1.0\r\nCSeq: 3\r\nTransport: {}\r\n\r\n",
+            track, transport
+        );
+        let response = format!(
+            "RTSP/1.0 200 OK\r\nCSeq: 3\r\nSession: 1234\r\nTransport: {}\r\n\r\n",
+            transport
+        );
+        assert!(tracker
+            .add_packet(&message(CLIENT, SERVER, &request))
+            .is_none());
+        assert!(tracker
+            .add_packet(&message(SERVER, CLIENT, &response))
+            .is_none());
+    }
+
+    #[test]
+    fn parse_messages() {
+        let request = "OPTIONS rtsp://10.0.0.1/stream RTSP/1.0\r\n\
+                       CSeq: 1\r\n\
+                       User-Agent: test\r\n\r\n";
+        let packet = RtspPacket::unmarshall(request.as_bytes()).unwrap();
+        assert_eq!(
+            packet.start_line,
+            RtspStartLine::Request {
+                method: "OPTIONS".to_string(),
+                uri: "rtsp://10.0.0.1/stream".to_string()
+            }
+        );
+        assert_eq!(packet.cseq(), Some(1));
+        assert_eq!(packet.header("user-agent"), Some("test"));
+
+        let response =
+            "RTSP/1.0 404 Not Found\r\nCSeq: 1\r\nContent-Length: 4\r\n\r\nbody and more";
+        let packet = RtspPacket::unmarshall(response.as_bytes()).unwrap();
+        assert_eq!(
+            packet.start_line,
+            RtspStartLine::Response {
+                status: 404,
+                reason: "Not Found".to_string()
+            }
+        );
+        assert_eq!(packet.body, "body");
+
+        // cut short to the segment
+        let response = "RTSP/1.0 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\nv=0";
+        let packet = RtspPacket::unmarshall(response.as_bytes()).unwrap();
+        assert_eq!(packet.body, "v=0");
+
+        for invalid in [
+            "GET / HTTP/1.1\r\n\r\n",
+            "OPTIONS rtsp://10.0.0.1/stream RTSP/1.0\r\nCSeq: 1\r\n",
+            "RTSP/1.0 OK\r\n\r\n",
+        ] {
+            assert!(RtspPacket::unmarshall(invalid.as_bytes()).is_none());
+        }
+    }
+
+    #[test]
+    fn parse_transports() {
+        let transport = RtspTransport::parse(
+            "RTP/AVP;unicast;client_port=5000-5001;server_port=6970-6971;ssrc=1A2B3C4D",
+        )
+        .unwrap();
+        assert!(!transport.tcp && !transport.multicast);
+        assert_eq!(transport.client_port, Some(5000));
+        assert_eq!(transport.server_port, Some(6970));
+        assert_eq!(transport.ssrc, Some(0x1a2b3c4d));
+
+        let transport =
+            RtspTransport::parse("RTP/AVP;multicast;destination=239.1.1.1;port=6032-6033;ttl=16")
+                .unwrap();
+        assert!(transport.multicast);
+        assert_eq!(transport.destination, Some("239.1.1.1".parse().unwrap()));
+        assert_eq!(transport.port, Some(6032));
+
+        let transport = RtspTransport::parse("RTP/AVP/TCP;unicast;interleaved=2-3").unwrap();
+        assert!(transport.tcp);
+        assert_eq!(transport.interleaved, Some(2));
+
+        assert!(RtspTransport::parse("MP2T/H2221/UDP;unicast;client_port=5000").is_none());
+    }
+
+    #[test]
+    fn assign_sdps_of_udp_streams() {
+        let mut tracker = RtspTracker::default();
+        describe(&mut tracker);
+        setup(
+            &mut tracker,
+            "trackID=1",
+            "RTP/AVP;unicast;client_port=5000-5001;server_port=6970-6971",
+        );
+
+        // sent from another port than the negotiated one
+        assert!(tracker
+            .add_packet(&rtp("10.0.0.1:7000", "10.0.0.2:5000", 1))
+            .is_none());
+
+        let packet = rtp("10.0.0.1:6970", "10.0.0.2:5000", 1);
+        let (key, sdp) = tracker.add_packet(&packet).unwrap();
+        assert_eq!(
+            key,
+            (
+                packet.source_addr,
+                packet.destination_addr,
+                TransportProtocol::Udp,
+                1
+            )
+        );
+        assert_eq!(sdp.payload_types[&96].name, "H264");
+
+        // only for the first packet of the stream
+        assert!(tracker.add_packet(&packet).is_none());
+    }
+
+    #[test]
+    fn assign_sdps_of_multicast_and_interleaved_streams() {
+        let mut tracker = RtspTracker::default();
+        describe(&mut tracker);
+        setup(
+            &mut tracker,
+            "trackID=2",
+            "RTP/AVP;multicast;destination=239.1.1.1;port=6032-6033;ssrc=00000002",
+        );
+
+        assert!(tracker
+            .add_packet(&rtp("10.0.0.1:1234", "239.1.1.1:6032", 3))
+            .is_none());
+        let (_, sdp) = tracker
+            .add_packet(&rtp("10.0.0.1:1234", "239.1.1.1:6032", 2))
+            .unwrap();
+        assert_eq!(sdp.payload_types[&97].name, "MPEG4-GENERIC");
+
+        setup(
+            &mut tracker,
+            "trackID=1",
+            "RTP/AVP/TCP;unicast;interleaved=0-1",
+        );
+        let mut packet = rtp(SERVER, CLIENT, 4);
+        packet.transport_protocol = TransportProtocol::Tcp;
+        let (key, sdp) = tracker.add_packet(&packet).unwrap();
+        assert_eq!(key.2, TransportProtocol::Tcp);
+        assert_eq!(sdp.payload_types[&96].name, "H264");
+    }
+}
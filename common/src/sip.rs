@@ -0,0 +1,697 @@
+use crate::packet::{SessionPacket, TransportProtocol};
+use crate::rtp::payload_type::PayloadType;
+use crate::{Packet, RtpStreamKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::text_message::TextSyntax;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::Sdp;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashSet;
+
+#[cfg(not(target_arch = "wasm32"))]
+const VERSION: &str = "SIP/2.0";
+#[cfg(not(target_arch = "wasm32"))]
+const SYNTAX: TextSyntax = TextSyntax {
+    version_prefix: VERSION,
+    is_method_char: |char| char.is_ascii_uppercase(),
+};
+// the oldest calls are forgotten past that
+const MAX_CALLS: usize = 1024;
+
+// full names of the headers with compact forms, RFC 3261 section 7.3.3
+const COMPACT_HEADERS: [(&str, &str); 10] = [
+    ("Call-ID", "i"),
+    ("Contact", "m"),
+    ("Content-Encoding", "e"),
+    ("Content-Length", "l"),
+    ("Content-Type", "c"),
+    ("From", "f"),
+    ("Subject", "s"),
+    ("Supported", "k"),
+    ("To", "t"),
+    ("Via", "v"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SipStartLine {
+    Request { method: String, uri: String },
+    Response { status: u16, reason: String },
+}
+
+/// SIP request or response, the body is cut short if it continues in the next segments.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SipPacket {
+    pub start_line: SipStartLine,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl SipPacket {
+    /// Value of the first header with the name, also written in its compact form.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn call_id(&self) -> Option<&str> {
+        self.header("Call-ID")
+    }
+
+    /// Sequence number and method of the transaction.
+    pub fn cseq(&self) -> Option<(u32, &str)> {
+        let (sequence, method) = self.header("CSeq")?.split_once(' ')?;
+        Some((sequence.trim().parse().ok()?, method.trim()))
+    }
+
+    fn has_sdp(&self) -> bool {
+        self.header("Content-Type")
+            .is_some_and(|content_type| content_type.eq_ignore_ascii_case("application/sdp"))
+            && !self.body.is_empty()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SipPacket {
+    pub fn build(packet: &Packet) -> Option<Self> {
+        packet
+            .payload
+            .as_ref()
+            .and_then(|payload| Self::unmarshall(payload))
+    }
+
+    fn unmarshall(buffer: &[u8]) -> Option<Self> {
+        let message = SYNTAX.parse(buffer)?;
+        let start_line = SipStartLine::parse(message.start_line)?;
+
+        // the length is optional for datagrams, which end with the body
+        let length = match find_header(&message.headers, "Content-Length") {
+            Some(length) => Some(length.parse().ok()?),
+            None => None,
+        };
+        let body = message.body(buffer, length);
+
+        Some(Self {
+            start_line,
+            headers: message.headers,
+            body,
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SipStartLine {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let first = parts.next()?;
+        let second = parts.next()?;
+        let third = parts.next().unwrap_or_default();
+
+        if first == VERSION {
+            return Some(Self::Response {
+                status: second.parse().ok()?,
+                reason: third.to_string(),
+            });
+        }
+
+        if !SYNTAX.is_method(first) || third != VERSION || !second.contains(':') {
+            return None;
+        }
+
+        Some(Self::Request {
+            method: first.to_string(),
+            uri: second.to_string(),
+        })
+    }
+}
+
+/// Media of an SDP offer or answer, the RTP streams sent to its address are described by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallMedia {
+    pub address: SocketAddr,
+    /// Names of the payload types, in the order of preference
+    pub codecs: Vec<String>,
+    /// The media description, beginning with its `m=` line
+    pub sdp: String,
+}
+
+impl CallMedia {
+    /// Media with an address and a port other than 0, as rejected ones have.
+    fn parse_all(sdp: &str) -> Vec<Self> {
+        let mut session_ip = None;
+        let mut media: Vec<(Option<IpAddr>, u16, Vec<&str>, String)> = Vec::new();
+
+        for line in sdp.lines() {
+            if let Some(description) = line.strip_prefix("m=") {
+                let mut fields = description.split_whitespace().skip(1);
+                let port = fields
+                    .next()
+                    .and_then(|port| port.split('/').next()?.parse().ok())
+                    .unwrap_or_default();
+                let formats = fields.skip(1).collect();
+                media.push((None, port, formats, String::new()));
+            }
+
+            if let Some(connection) = line.strip_prefix("c=") {
+                // e.g. `IN IP4 239.1.1.1/16`, with the TTL of multicast addresses
+                let ip = connection
+                    .split_whitespace()
+                    .nth(2)
+                    .and_then(|address| address.split('/').next()?.parse().ok());
+                match media.last_mut() {
+                    Some((media_ip, ..)) => *media_ip = ip,
+                    None => session_ip = ip,
+                }
+            }
+
+            if let Some((.., section)) = media.last_mut() {
+                section.push_str(line);
+                section.push('\n');
+            }
+        }
+
+        media
+            .into_iter()
+            .filter(|(_, port, ..)| *port != 0)
+            .filter_map(|(ip, port, formats, section)| {
+                let address = SocketAddr::new(ip.or(session_ip)?, port);
+                let codecs = formats
+                    .iter()
+                    .map(|format| codec_name(&section, format))
+                    .collect();
+                Some(Self {
+                    address,
+                    codecs,
+                    sdp: section,
+                })
+            })
+            .collect()
+    }
+}
+
+// the encoding name of the rtpmap, static payload types may have none
+fn codec_name(section: &str, format: &str) -> String {
+    let rtpmap = format!("a=rtpmap:{} ", format);
+    let name = section
+        .lines()
+        .find_map(|line| line.strip_prefix(&rtpmap))
+        .and_then(|encoding| encoding.split('/').next());
+
+    match (name, format.parse()) {
+        (Some(name), _) => name.to_string(),
+        (None, Ok(id)) if id < 96 => PayloadType::new(id).name,
+        (None, _) => format.to_string(),
+    }
+}
+
+/// Why a call ended, or never got established.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Teardown {
+    Bye { by: SocketAddr },
+    Cancel,
+    Rejected { status: u16, reason: String },
+}
+
+/// Dialog set up by an INVITE, along with the media negotiated by its offer and answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub call_id: String,
+    pub from: String,
+    pub to: String,
+    /// Timestamp of the first INVITE
+    pub invited: Duration,
+    /// Timestamp of the first 2xx response to the INVITE
+    pub answered: Option<Duration>,
+    pub ended: Option<Duration>,
+    pub teardown: Option<Teardown>,
+    pub offer: Vec<CallMedia>,
+    pub answer: Vec<CallMedia>,
+}
+
+impl Call {
+    fn new(call_id: &str, sip: &SipPacket, timestamp: Duration) -> Self {
+        Self {
+            call_id: call_id.to_string(),
+            from: sip.header("From").map(display_address).unwrap_or_default(),
+            to: sip.header("To").map(display_address).unwrap_or_default(),
+            invited: timestamp,
+            answered: None,
+            ended: None,
+            teardown: None,
+            offer: Vec::new(),
+            answer: Vec::new(),
+        }
+    }
+
+    /// Time from the INVITE to its answer.
+    pub fn setup_time(&self) -> Option<Duration> {
+        self.answered?.checked_sub(self.invited)
+    }
+
+    /// The media of the offer or the answer the RTP stream is sent to.
+    pub fn media(&self, key: &RtpStreamKey) -> Option<&CallMedia> {
+        let (_, destination, transport_protocol, _) = *key;
+        if transport_protocol != TransportProtocol::Udp {
+            return None;
+        }
+
+        self.offer
+            .iter()
+            .chain(self.answer.iter())
+            .find(|media| media.address == destination)
+    }
+
+    fn add_message(&mut self, sip: &SipPacket, packet: &Packet) {
+        let Some((_, method)) = sip.cseq() else {
+            return;
+        };
+
+        match sip.start_line {
+            SipStartLine::Request { ref method, .. } => match method.as_str() {
+                "INVITE" if sip.has_sdp() => self.offer = CallMedia::parse_all(&sip.body),
+                // the answer to an offer made by the 2xx response of an INVITE without SDP
+                "ACK" if sip.has_sdp() => self.answer = CallMedia::parse_all(&sip.body),
+                "BYE" => self.end(
+                    packet,
+                    Teardown::Bye {
+                        by: packet.source_addr,
+                    },
+                ),
+                "CANCEL" => self.end(packet, Teardown::Cancel),
+                _ => {}
+            },
+            SipStartLine::Response { status, ref reason } => {
+                if method != "INVITE" {
+                    return;
+                }
+
+                if sip.has_sdp() {
+                    let media = CallMedia::parse_all(&sip.body);
+                    match self.offer.is_empty() {
+                        true => self.offer = media,
+                        false => self.answer = media,
+                    }
+                }
+
+                match status {
+                    200..=299 => {
+                        self.answered.get_or_insert(packet.timestamp);
+                    }
+                    300.. if self.answered.is_none() => {
+                        let reason = reason.clone();
+                        self.end(packet, Teardown::Rejected { status, reason });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // keeps the first reason, e.g. a CANCEL rather than the 487 response to its INVITE
+    fn end(&mut self, packet: &Packet, teardown: Teardown) {
+        if self.teardown.is_none() {
+            self.ended = Some(packet.timestamp);
+            self.teardown = Some(teardown);
+        }
+    }
+}
+
+// the display name and the URI, without the parameters of the header such as the tag
+fn display_address(value: &str) -> String {
+    match value.find('>') {
+        Some(end) => value[..=end].to_string(),
+        None => value.split(';').next().unwrap_or_default().to_string(),
+    }
+}
+
+// headers may be written in their compact forms as well
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    let compact = COMPACT_HEADERS
+        .iter()
+        .find(|(full, _)| full.eq_ignore_ascii_case(name))
+        .map(|(_, compact)| *compact);
+
+    headers
+        .iter()
+        .find(|(header, _)| {
+            header.eq_ignore_ascii_case(name)
+                || compact.is_some_and(|compact| header.eq_ignore_ascii_case(compact))
+        })
+        .map(|(_, value)| value.as_str())
+}
+
+/// Follows the calls of a capture by the Call-IDs of their SIP messages.
+#[derive(Debug, Default)]
+pub struct CallTracker {
+    calls: Vec<Call>,
+    // RTP streams already matched, each gets the SDP of its media once
+    #[cfg(not(target_arch = "wasm32"))]
+    assigned: HashSet<RtpStreamKey>,
+}
+
+impl CallTracker {
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Starts a call with an INVITE, the other messages update the calls already started.
+    pub fn add_packet(&mut self, packet: &Packet) {
+        let SessionPacket::Sip(ref sip) = packet.contents else {
+            return;
+        };
+        let Some(call_id) = sip.call_id() else {
+            return;
+        };
+
+        let position = self.calls.iter().rposition(|call| call.call_id == call_id);
+        let call = match position {
+            Some(position) => &mut self.calls[position],
+            None => {
+                let is_invite = matches!(
+                    sip.start_line,
+                    SipStartLine::Request { ref method, .. } if method == "INVITE"
+                );
+                if !is_invite {
+                    return;
+                }
+                if self.calls.len() >= MAX_CALLS {
+                    self.calls.remove(0);
+                }
+                self.calls.push(Call::new(call_id, sip, packet.timestamp));
+                self.calls.last_mut().unwrap()
+            }
+        };
+
+        call.add_message(sip, packet);
+    }
+
+    /// The call whose media the RTP stream is sent to, the latest one if the ports were reused.
+    pub fn find(&self, key: &RtpStreamKey) -> Option<&Call> {
+        self.calls
+            .iter()
+            .rev()
+            .find(|call| call.media(key).is_some())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CallTracker {
+    /// Returns the SDP of the media negotiated for the RTP stream of the packet,
+    /// only for its first packet.
+    pub fn assign_sdp(&mut self, packet: &Packet) -> Option<(RtpStreamKey, Sdp)> {
+        let SessionPacket::Rtp(ref rtp) = packet.contents else {
+            return None;
+        };
+        let key = (
+            packet.source_addr,
+            packet.destination_addr,
+            packet.transport_protocol,
+            rtp.ssrc,
+        );
+        if self.assigned.contains(&key) {
+            return None;
+        }
+
+        let media = self.find(&key)?.media(&key)?;
+        let sdp = Sdp::build(media.sdp.clone())?;
+        self.assigned.insert(key);
+        Some((key, sdp))
+    }
+}
+
+impl fmt::Display for SipStartLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request { method, uri } => write!(f, "{} {}", method, uri),
+            Self::Response { status, reason } => write!(f, "{} {}", status, reason),
+        }
+    }
+}
+
+impl fmt::Display for Teardown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bye { by } => write!(f, "BYE from {}", by),
+            Self::Cancel => write!(f, "CANCEL"),
+            Self::Rejected { status, reason } => write!(f, "{} {}", status, reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALLER: &str = "200.57.7.195:5060";
+    const CALLEE: &str = "200.57.7.204:5061";
+    const TO_CALLEE: (&str, &str) = (CALLER, CALLEE);
+    const TO_CALLER: (&str, &str) = (CALLEE, CALLER);
+
+    const OFFER: &str = "v=0\r\n\
+                         o=Clarent 120386 120387 IN IP4 200.57.7.196\r\n\
+                         s=Clarent C5CM\r\n\
+                         c=IN IP4 200.57.7.196\r\n\
+                         t=0 0\r\n\
+                         m=audio 40376 RTP/AVP 8 18 0\r\n\
+                         a=rtpmap:8 PCMA/8000\r\n\
+                         a=rtpmap:18 G729/8000\r\n\
+                         m=video 0 RTP/AVP 96\r\n";
+
+    const ANSWER: &str = "v=0\r\n\
+                          o=francisco 13004970 13013442 IN IP4 200.57.7.204\r\n\
+                          s=X-Lite\r\n\
+                          t=0 0\r\n\
+                          m=audio 8000 RTP/AVP 8 101\r\n\
+                          c=IN IP4 200.57.7.204\r\n\
+                          a=rtpmap:8 pcma/8000\r\n\
+                          a=rtpmap:101 telephone-event/8000\r\n";
+
+    fn message(
+        (source, destination): (&str, &str),
+        timestamp: u64,
+        start_line: &str,
+        cseq: &str,
+        body: &str,
+    ) -> Packet {
+        let text = format!(
+            "{}\r\n\
+             From: \"Ivan\" <sip:ivan@200.57.7.195>;tag=GR52RWG346-34\r\n\
+             To: <sip:francisco@bestel.com>\r\n\
+             i: 12013223@200.57.7.195\r\n\
+             CSeq: {}\r\n\
+             Content-Type: application/sdp\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            start_line,
+            cseq,
+            body.len(),
+            body
+        );
+
+        let mut packet = Packet::build_from_datagram(
+            text.as_bytes(),
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::from_secs(timestamp),
+        );
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    fn rtp(source: &str, destination: &str) -> Packet {
+        let mut payload = vec![0x80, 8, 0, 1, 0, 0, 0, 1, 0, 0, 0, 7];
+        payload.extend_from_slice(&[0xd5; 160]);
+
+        let mut packet = Packet::build_from_datagram(
+            &payload,
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::from_secs(4),
+        );
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    fn key(packet: &Packet) -> RtpStreamKey {
+        (
+            packet.source_addr,
+            packet.destination_addr,
+            TransportProtocol::Udp,
+            7,
+        )
+    }
+
+    #[test]
+    fn parse_messages() {
+        let packet = message(
+            TO_CALLEE,
+            1,
+            "INVITE sip:francisco@bestel.com SIP/2.0",
+            "1 INVITE",
+            OFFER,
+        );
+        let SessionPacket::Sip(ref sip) = packet.contents else {
+            panic!("not decoded as SIP: {:?}", packet.contents);
+        };
+        assert_eq!(
+            sip.start_line,
+            SipStartLine::Request {
+                method: "INVITE".to_string(),
+                uri: "sip:francisco@bestel.com".to_string()
+            }
+        );
+        assert_eq!(sip.call_id(), Some("12013223@200.57.7.195"));
+        assert_eq!(sip.cseq(), Some((1, "INVITE")));
+        assert_eq!(sip.body, OFFER);
+
+        let response = "SIP/2.0 180 Ringing\r\nCall-ID: 1@host\r\n\r\n";
+        let sip = SipPacket::unmarshall(response.as_bytes()).unwrap();
+        assert_eq!(
+            sip.start_line,
+            SipStartLine::Response {
+                status: 180,
+                reason: "Ringing".to_string()
+            }
+        );
+
+        // cut short to the datagram
+        let response = "SIP/2.0 200 OK\r\nl: 18446744073709551615\r\n\r\nv=0\r\n";
+        let sip = SipPacket::unmarshall(response.as_bytes()).unwrap();
+        assert_eq!(sip.body, "v=0\r\n");
+
+        for invalid in [
+            "SIP/2.0 200 OK\r\nContent-Length: -1\r\n\r\n",
+            "SIP/2.0 200 OK\r\nContent-Length: 18446744073709551616\r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+            "OPTIONS rtsp://10.0.0.1/stream RTSP/1.0\r\n\r\n",
+            "INVITE sip:francisco@bestel.com SIP/2.0\r\n",
+            "\r\n\r\n",
+        ] {
+            assert!(SipPacket::unmarshall(invalid.as_bytes()).is_none());
+        }
+    }
+
+    #[test]
+    fn parse_media() {
+        let media = CallMedia::parse_all(OFFER);
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].address, "200.57.7.196:40376".parse().unwrap());
+        assert_eq!(media[0].codecs, ["PCMA", "G729", "PCMU"]);
+
+        let media = CallMedia::parse_all(ANSWER);
+        assert_eq!(media[0].address, "200.57.7.204:8000".parse().unwrap());
+        assert_eq!(media[0].codecs, ["pcma", "telephone-event"]);
+    }
+
+    #[test]
+    fn track_established_call() {
+        let mut tracker = CallTracker::default();
+        let packets = [
+            message(
+                TO_CALLEE,
+                1,
+                "INVITE sip:francisco@bestel.com SIP/2.0",
+                "1 INVITE",
+                OFFER,
+            ),
+            message(TO_CALLER, 2, "SIP/2.0 180 Ringing", "1 INVITE", ""),
+            message(TO_CALLER, 3, "SIP/2.0 200 OK", "1 INVITE", ANSWER),
+            message(
+                TO_CALLEE,
+                3,
+                "ACK sip:francisco@200.57.7.204 SIP/2.0",
+                "1 ACK",
+                "",
+            ),
+            message(TO_CALLER, 9, "BYE sip:200.57.7.195 SIP/2.0", "2 BYE", ""),
+        ];
+        for packet in &packets {
+            tracker.add_packet(packet);
+        }
+
+        let [call] = tracker.calls() else {
+            panic!("expected a single call: {:?}", tracker.calls());
+        };
+        assert_eq!(call.from, "\"Ivan\" <sip:ivan@200.57.7.195>");
+        assert_eq!(call.setup_time(), Some(Duration::from_secs(2)));
+        assert_eq!(call.ended, Some(Duration::from_secs(9)));
+        assert_eq!(
+            call.teardown,
+            Some(Teardown::Bye {
+                by: CALLEE.parse().unwrap()
+            })
+        );
+
+        let to_caller = rtp("200.57.7.204:8000", "200.57.7.196:40376");
+        let to_callee = rtp("200.57.7.196:40376", "200.57.7.204:8000");
+        let elsewhere = rtp("200.57.7.199:4800", "200.57.7.196:40378");
+
+        let (stream_key, sdp) = tracker.assign_sdp(&to_caller).unwrap();
+        assert_eq!(stream_key, key(&to_caller));
+        assert_eq!(sdp.payload_types[&18].name, "G729");
+        assert!(tracker.assign_sdp(&to_caller).is_none());
+
+        let (_, sdp) = tracker.assign_sdp(&to_callee).unwrap();
+        assert_eq!(sdp.payload_types[&101].name, "telephone-event");
+
+        assert!(tracker.assign_sdp(&elsewhere).is_none());
+        assert!(tracker.find(&key(&elsewhere)).is_none());
+    }
+
+    #[test]
+    fn track_failed_calls() {
+        let mut tracker = CallTracker::default();
+        let invite = message(
+            TO_CALLEE,
+            1,
+            "INVITE sip:francisco@bestel.com SIP/2.0",
+            "1 INVITE",
+            OFFER,
+        );
+
+        tracker.add_packet(&invite);
+        tracker.add_packet(&message(
+            TO_CALLEE,
+            2,
+            "CANCEL sip:francisco@bestel.com SIP/2.0",
+            "1 CANCEL",
+            "",
+        ));
+        tracker.add_packet(&message(
+            TO_CALLER,
+            2,
+            "SIP/2.0 487 Request Terminated",
+            "1 INVITE",
+            "",
+        ));
+        assert_eq!(tracker.calls()[0].teardown, Some(Teardown::Cancel));
+        assert_eq!(tracker.calls()[0].setup_time(), None);
+
+        tracker.clear();
+        tracker.add_packet(&invite);
+        tracker.add_packet(&message(
+            TO_CALLER,
+            2,
+            "SIP/2.0 486 Busy Here",
+            "1 INVITE",
+            "",
+        ));
+        assert_eq!(
+            tracker.calls()[0].teardown.as_ref().unwrap().to_string(),
+            "486 Busy Here"
+        );
+
+        // only INVITEs start calls
+        tracker.clear();
+        tracker.add_packet(&message(TO_CALLER, 2, "SIP/2.0 200 OK", "1 INVITE", ANSWER));
+        assert!(tracker.calls().is_empty());
+    }
+}
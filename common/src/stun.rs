@@ -0,0 +1,594 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[cfg(not(target_arch = "wasm32"))]
+const HEADER_SIZE: usize = 20;
+#[cfg(not(target_arch = "wasm32"))]
+const CHANNEL_DATA_HEADER_SIZE: usize = 4;
+#[cfg(not(target_arch = "wasm32"))]
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+// attribute types, see RFC 8489 and RFC 8656
+const MAPPED_ADDRESS: u16 = 0x0001;
+const USERNAME: u16 = 0x0006;
+const MESSAGE_INTEGRITY: u16 = 0x0008;
+const ERROR_CODE: u16 = 0x0009;
+const CHANNEL_NUMBER: u16 = 0x000C;
+const LIFETIME: u16 = 0x000D;
+const XOR_PEER_ADDRESS: u16 = 0x0012;
+const DATA: u16 = 0x0013;
+const REALM: u16 = 0x0014;
+const NONCE: u16 = 0x0015;
+const XOR_RELAYED_ADDRESS: u16 = 0x0016;
+const REQUESTED_TRANSPORT: u16 = 0x0019;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const PRIORITY: u16 = 0x0024;
+const USE_CANDIDATE: u16 = 0x0025;
+const SOFTWARE: u16 = 0x8022;
+const FINGERPRINT: u16 = 0x8028;
+const ICE_CONTROLLED: u16 = 0x8029;
+const ICE_CONTROLLING: u16 = 0x802A;
+
+/// STUN message or TURN ChannelData message, the latter relays data
+/// to or from the peer bound to its channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum StunPacket {
+    Message(StunMessage),
+    ChannelData { channel: u16, length: u16 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StunMessage {
+    pub class: StunClass,
+    pub method: StunMethod,
+    pub transaction_id: [u8; 12],
+    pub attributes: Vec<StunAttribute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunClass {
+    Request,
+    Indication,
+    SuccessResponse,
+    ErrorResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StunMethod {
+    Binding,
+    Allocate,
+    Refresh,
+    Send,
+    Data,
+    CreatePermission,
+    ChannelBind,
+    Other(u16),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum StunAttribute {
+    MappedAddress(SocketAddr),
+    XorMappedAddress(SocketAddr),
+    XorPeerAddress(SocketAddr),
+    XorRelayedAddress(SocketAddr),
+    Username(String),
+    Realm(String),
+    Software(String),
+    ErrorCode {
+        code: u16,
+        reason: String,
+    },
+    ChannelNumber(u16),
+    /// Seconds
+    Lifetime(u32),
+    /// Length of the relayed data
+    Data(usize),
+    Other {
+        kind: u16,
+        length: u16,
+    },
+}
+
+impl StunPacket {
+    /// Transaction ID of the message as a hex string, ChannelData messages have none.
+    pub fn transaction_id(&self) -> Option<String> {
+        use std::fmt::Write;
+
+        let Self::Message(message) = self else {
+            return None;
+        };
+
+        let mut transaction_id = String::new();
+        for byte in message.transaction_id {
+            let _ = write!(transaction_id, "{:02x}", byte);
+        }
+
+        Some(transaction_id)
+    }
+
+    pub fn attributes(&self) -> &[StunAttribute] {
+        match self {
+            Self::Message(message) => &message.attributes,
+            Self::ChannelData { .. } => &[],
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StunPacket {
+    pub fn build(packet: &super::Packet) -> Option<Self> {
+        packet
+            .payload
+            .as_ref()
+            .and_then(|payload| Self::unmarshall(payload))
+            .map(|(stun, _)| stun)
+    }
+
+    /// Parses the message along with the data it relays, if it's a ChannelData message
+    /// or a Send or Data indication.
+    pub fn unmarshall(buffer: &[u8]) -> Option<(Self, Option<&[u8]>)> {
+        match buffer.first()? {
+            // the first two bits of STUN messages are zeroes, see RFC 7983
+            0x00..=0x03 => Self::unmarshall_message(buffer),
+            0x40..=0x4F => Self::unmarshall_channel_data(buffer),
+            _ => None,
+        }
+    }
+
+    fn unmarshall_channel_data(buffer: &[u8]) -> Option<(Self, Option<&[u8]>)> {
+        let channel = read_u16(buffer, 0)?;
+        let length = read_u16(buffer, 2)?;
+
+        // over UDP, the data may or may not be padded to a multiple of 4 bytes
+        let end = CHANNEL_DATA_HEADER_SIZE + length as usize;
+        if buffer.len() < end || buffer.len() > end.next_multiple_of(4) {
+            return None;
+        }
+
+        let data = &buffer[CHANNEL_DATA_HEADER_SIZE..end];
+        Some((Self::ChannelData { channel, length }, Some(data)))
+    }
+
+    fn unmarshall_message(buffer: &[u8]) -> Option<(Self, Option<&[u8]>)> {
+        let message_type = read_u16(buffer, 0)?;
+        let length = read_u16(buffer, 2)? as usize;
+        let cookie = read_u32(buffer, 4)?;
+        if cookie != MAGIC_COOKIE || length % 4 != 0 || buffer.len() != HEADER_SIZE + length {
+            return None;
+        }
+
+        let mut transaction_id = [0; 12];
+        transaction_id.copy_from_slice(&buffer[8..HEADER_SIZE]);
+
+        let mut attributes = Vec::new();
+        let mut data = None;
+        let mut index = HEADER_SIZE;
+        while index < buffer.len() {
+            let kind = read_u16(buffer, index)?;
+            let length = read_u16(buffer, index + 2)?;
+            let start = index + 4;
+            let value = buffer.get(start..start + length as usize)?;
+            if kind == DATA {
+                data = Some(value);
+            }
+
+            attributes.push(StunAttribute::unmarshall(kind, value, &transaction_id)?);
+            index = start + (length as usize).next_multiple_of(4);
+        }
+
+        let message = StunMessage {
+            class: class_of(message_type),
+            method: StunMethod::new(method_of(message_type)),
+            transaction_id,
+            attributes,
+        };
+
+        Some((Self::Message(message), data))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StunMethod {
+    fn new(method: u16) -> Self {
+        match method {
+            0x001 => Self::Binding,
+            0x003 => Self::Allocate,
+            0x004 => Self::Refresh,
+            0x006 => Self::Send,
+            0x007 => Self::Data,
+            0x008 => Self::CreatePermission,
+            0x009 => Self::ChannelBind,
+            other => Self::Other(other),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StunAttribute {
+    fn unmarshall(kind: u16, value: &[u8], transaction_id: &[u8; 12]) -> Option<Self> {
+        let text = || String::from_utf8_lossy(value).into_owned();
+        let xor_address = || read_address(value, Some(transaction_id));
+
+        let attribute = match kind {
+            MAPPED_ADDRESS => Self::MappedAddress(read_address(value, None)?),
+            XOR_MAPPED_ADDRESS => Self::XorMappedAddress(xor_address()?),
+            XOR_PEER_ADDRESS => Self::XorPeerAddress(xor_address()?),
+            XOR_RELAYED_ADDRESS => Self::XorRelayedAddress(xor_address()?),
+            USERNAME => Self::Username(text()),
+            REALM => Self::Realm(text()),
+            SOFTWARE => Self::Software(text()),
+            ERROR_CODE => {
+                let class = *value.get(2)? & 0x07;
+                let number = *value.get(3)?;
+                Self::ErrorCode {
+                    code: u16::from(class) * 100 + u16::from(number),
+                    reason: String::from_utf8_lossy(&value[4..]).into_owned(),
+                }
+            }
+            CHANNEL_NUMBER => Self::ChannelNumber(read_u16(value, 0)?),
+            LIFETIME => Self::Lifetime(read_u32(value, 0)?),
+            DATA => Self::Data(value.len()),
+            kind => Self::Other {
+                kind,
+                length: value.len() as u16,
+            },
+        };
+
+        Some(attribute)
+    }
+}
+
+// the class bits are interleaved with the method bits
+#[cfg(not(target_arch = "wasm32"))]
+fn class_of(message_type: u16) -> StunClass {
+    match ((message_type >> 7) & 0b10) | ((message_type >> 4) & 0b01) {
+        0b00 => StunClass::Request,
+        0b01 => StunClass::Indication,
+        0b10 => StunClass::SuccessResponse,
+        _ => StunClass::ErrorResponse,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn method_of(message_type: u16) -> u16 {
+    (message_type & 0x000F) | ((message_type >> 1) & 0x0070) | ((message_type >> 2) & 0x0F80)
+}
+
+/// Reads a (XOR-)MAPPED-ADDRESS, the XORed ones are obfuscated with the magic cookie
+/// and, for IPv6, the transaction ID.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_address(value: &[u8], transaction_id: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let family = *value.get(1)?;
+    let mut port = read_u16(value, 2)?;
+    let mut mask = [0; 16];
+    if let Some(transaction_id) = transaction_id {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+
+    let ip = match family {
+        0x01 => {
+            let bytes: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            let bytes: [u8; 4] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            IpAddr::V4(Ipv4Addr::from(bytes))
+        }
+        0x02 => {
+            let bytes: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            let bytes: [u8; 16] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            IpAddr::V6(Ipv6Addr::from(bytes))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_u16(buffer: &[u8], index: usize) -> Option<u16> {
+    let bytes = buffer.get(index..index + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_u32(buffer: &[u8], index: usize) -> Option<u32> {
+    let bytes = buffer.get(index..index + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn attribute_name(kind: u16) -> Option<&'static str> {
+    let name = match kind {
+        MAPPED_ADDRESS => "MAPPED-ADDRESS",
+        USERNAME => "USERNAME",
+        MESSAGE_INTEGRITY => "MESSAGE-INTEGRITY",
+        ERROR_CODE => "ERROR-CODE",
+        CHANNEL_NUMBER => "CHANNEL-NUMBER",
+        LIFETIME => "LIFETIME",
+        XOR_PEER_ADDRESS => "XOR-PEER-ADDRESS",
+        DATA => "DATA",
+        REALM => "REALM",
+        NONCE => "NONCE",
+        XOR_RELAYED_ADDRESS => "XOR-RELAYED-ADDRESS",
+        REQUESTED_TRANSPORT => "REQUESTED-TRANSPORT",
+        XOR_MAPPED_ADDRESS => "XOR-MAPPED-ADDRESS",
+        PRIORITY => "PRIORITY",
+        USE_CANDIDATE => "USE-CANDIDATE",
+        SOFTWARE => "SOFTWARE",
+        FINGERPRINT => "FINGERPRINT",
+        ICE_CONTROLLED => "ICE-CONTROLLED",
+        ICE_CONTROLLING => "ICE-CONTROLLING",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+impl fmt::Display for StunClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let res = match self {
+            Self::Request => "Request",
+            Self::Indication => "Indication",
+            Self::SuccessResponse => "Success Response",
+            Self::ErrorResponse => "Error Response",
+        };
+
+        write!(f, "{}", res)
+    }
+}
+
+impl fmt::Display for StunMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let res = match self {
+            Self::Binding => "Binding",
+            Self::Allocate => "Allocate",
+            Self::Refresh => "Refresh",
+            Self::Send => "Send",
+            Self::Data => "Data",
+            Self::CreatePermission => "CreatePermission",
+            Self::ChannelBind => "ChannelBind",
+            Self::Other(method) => return write!(f, "0x{:03x}", method),
+        };
+
+        write!(f, "{}", res)
+    }
+}
+
+impl fmt::Display for StunPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(message) => write!(f, "{} {}", message.method, message.class),
+            Self::ChannelData { channel, .. } => write!(f, "ChannelData 0x{:04x}", channel),
+        }
+    }
+}
+
+impl fmt::Display for StunAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MappedAddress(addr) => write!(f, "MAPPED-ADDRESS: {}", addr),
+            Self::XorMappedAddress(addr) => write!(f, "XOR-MAPPED-ADDRESS: {}", addr),
+            Self::XorPeerAddress(addr) => write!(f, "XOR-PEER-ADDRESS: {}", addr),
+            Self::XorRelayedAddress(addr) => write!(f, "XOR-RELAYED-ADDRESS: {}", addr),
+            Self::Username(username) => write!(f, "USERNAME: {}", username),
+            Self::Realm(realm) => write!(f, "REALM: {}", realm),
+            Self::Software(software) => write!(f, "SOFTWARE: {}", software),
+            Self::ErrorCode { code, reason } => write!(f, "ERROR-CODE: {} {}", code, reason),
+            Self::ChannelNumber(channel) => write!(f, "CHANNEL-NUMBER: 0x{:04x}", channel),
+            Self::Lifetime(lifetime) => write!(f, "LIFETIME: {}s", lifetime),
+            Self::Data(length) => write!(f, "DATA: {} bytes", length),
+            Self::Other { kind, length } => match attribute_name(*kind) {
+                Some(name) => write!(f, "{}: {} bytes", name, length),
+                None => write!(f, "0x{:04x}: {} bytes", kind, length),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    fn message(message_type: u16, attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, value) in attributes {
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().next_multiple_of(4), 0);
+        }
+
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&message_type.to_be_bytes());
+        buffer.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buffer.extend_from_slice(&TRANSACTION_ID);
+        buffer.extend_from_slice(&body);
+        buffer
+    }
+
+    fn unmarshall_message(buffer: &[u8]) -> StunMessage {
+        match StunPacket::unmarshall(buffer) {
+            Some((StunPacket::Message(message), _)) => message,
+            other => panic!("not a STUN message: {:?}", other),
+        }
+    }
+
+    // sample IPv4 response of RFC 5769
+    #[test]
+    fn binding_response() {
+        let buffer = [
+            0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34,
+            0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74,
+            0x20, 0x76, 0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01,
+            0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99,
+            0xfd, 0x9e, 0x90, 0xc3, 0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b,
+            0xe7, 0xd7, 0x80, 0x28, 0x00, 0x04, 0xc0, 0x7d, 0x4c, 0x96,
+        ];
+
+        let message = unmarshall_message(&buffer);
+        assert_eq!(message.class, StunClass::SuccessResponse);
+        assert_eq!(message.method, StunMethod::Binding);
+        assert_eq!(message.transaction_id, TRANSACTION_ID);
+        assert_eq!(
+            message.attributes,
+            vec![
+                StunAttribute::Software("test vector".to_string()),
+                StunAttribute::XorMappedAddress("192.0.2.1:32853".parse().unwrap()),
+                StunAttribute::Other {
+                    kind: MESSAGE_INTEGRITY,
+                    length: 20
+                },
+                StunAttribute::Other {
+                    kind: FINGERPRINT,
+                    length: 4
+                },
+            ]
+        );
+    }
+
+    // sample IPv6 response of RFC 5769
+    #[test]
+    fn xor_mapped_ipv6_address() {
+        let value = [
+            0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25,
+            0xf4, 0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+        ];
+        let buffer = message(0x0101, &[(XOR_MAPPED_ADDRESS, &value)]);
+
+        let message = unmarshall_message(&buffer);
+        assert_eq!(
+            message.attributes,
+            vec![StunAttribute::XorMappedAddress(
+                "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+                    .parse()
+                    .unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn error_response() {
+        let buffer = message(0x0113, &[(ERROR_CODE, b"\x00\x00\x04\x01Unauthorized")]);
+
+        let message = unmarshall_message(&buffer);
+        assert_eq!(message.class, StunClass::ErrorResponse);
+        assert_eq!(message.method, StunMethod::Allocate);
+        assert_eq!(
+            message.attributes[0].to_string(),
+            "ERROR-CODE: 401 Unauthorized"
+        );
+    }
+
+    #[test]
+    fn send_indication_data() {
+        let peer = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let buffer = message(0x0016, &[(XOR_PEER_ADDRESS, &peer), (DATA, b"hello")]);
+
+        let (stun, data) = StunPacket::unmarshall(&buffer).unwrap();
+        assert_eq!(stun.to_string(), "Send Indication");
+        assert_eq!(data, Some(&b"hello"[..]));
+        assert_eq!(
+            stun.attributes(),
+            [
+                StunAttribute::XorPeerAddress("192.0.2.1:32853".parse().unwrap()),
+                StunAttribute::Data(5),
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_data() {
+        let unpadded = [0x40, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5];
+        let (stun, data) = StunPacket::unmarshall(&unpadded).unwrap();
+        assert_eq!(
+            stun,
+            StunPacket::ChannelData {
+                channel: 0x4001,
+                length: 5
+            }
+        );
+        assert_eq!(data, Some(&[1, 2, 3, 4, 5][..]));
+
+        let padded = [0x40, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0];
+        assert_eq!(
+            StunPacket::unmarshall(&padded).unwrap().1,
+            Some(&[1, 2, 3, 4, 5][..])
+        );
+    }
+
+    #[test]
+    fn relayed_media_is_unwrapped() {
+        use crate::packet::{SessionPacket, SessionProtocol};
+        use crate::Packet;
+        use std::time::Duration;
+
+        let rtp = [
+            0x80, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, 1, 2, 3,
+        ];
+        let mut channel_data = vec![0x40, 0x00, 0x00, rtp.len() as u8];
+        channel_data.extend_from_slice(&rtp);
+        let peer = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let data_indication = message(0x0017, &[(XOR_PEER_ADDRESS, &peer), (DATA, &rtp)]);
+        let binding_request = message(0x0001, &[(USERNAME, b"user")]);
+
+        let guess = |payload: &[u8]| {
+            let source = "10.0.0.1:3478".parse().unwrap();
+            let destination = "10.0.0.2:50000".parse().unwrap();
+            let mut packet =
+                Packet::build_from_datagram(payload, 1, source, destination, Duration::ZERO);
+            packet.guess_payload();
+            packet
+        };
+
+        for payload in [channel_data, data_indication] {
+            let packet = guess(&payload);
+            assert_eq!(packet.session_protocol, SessionProtocol::Rtp);
+            let SessionPacket::Rtp(rtp) = packet.contents else {
+                panic!("not RTP: {:?}", packet.contents);
+            };
+            assert_eq!(rtp.ssrc, 0x12345678);
+            assert!(packet.relay.is_some());
+        }
+
+        let packet = guess(&binding_request);
+        assert_eq!(packet.session_protocol, SessionProtocol::Stun);
+        assert!(packet.relay.is_none());
+
+        let mut packet = guess(&message(0x0017, &[(DATA, b"not rtp")]));
+        assert_eq!(packet.session_protocol, SessionProtocol::Stun);
+        packet.parse_as(SessionProtocol::Rtp);
+        assert_eq!(packet.session_protocol, SessionProtocol::Stun);
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let mut wrong_cookie = message(0x0001, &[]);
+        wrong_cookie[4] = 0;
+        let mut wrong_length = message(0x0001, &[(USERNAME, b"user")]);
+        wrong_length.push(0);
+        let truncated_attribute = {
+            let mut buffer = message(0x0001, &[(USERNAME, b"user")]);
+            buffer[23] = 8;
+            buffer
+        };
+
+        for buffer in [
+            wrong_cookie,
+            wrong_length,
+            truncated_attribute,
+            vec![0x40, 0x01, 0x00, 0x08, 1, 2, 3],
+            vec![0x40, 0x01, 0x00, 0x01, 1, 0, 0, 0, 0],
+            vec![0x80, 0x60, 0x00, 0x01],
+        ] {
+            assert!(StunPacket::unmarshall(&buffer).is_none(), "{:?}", buffer);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::packet::{SessionPacket, SessionProtocol};
+use crate::{MpegtsStreamKey, Packet, Response, RtpStreamKey};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// Inclusive range of ports, matched against both the source and the destination port.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Selects the packets of a source the server forwards to a client.
+/// Empty selections don't restrict anything, so the default subscription matches every packet.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Subscription {
+    pub protocols: Vec<SessionProtocol>,
+    /// Once any stream is selected, only packets of the selected streams match
+    pub rtp_streams: Vec<RtpStreamKey>,
+    pub mpegts_streams: Vec<MpegtsStreamKey>,
+    pub ports: Vec<PortRange>,
+}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        RangeInclusive::new(self.start, self.end).contains(&port)
+    }
+}
+
+impl Subscription {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, packet: &Packet) -> bool {
+        self.matches_protocol(packet) && self.matches_port(packet) && self.matches_stream(packet)
+    }
+
+    /// Responses other than packets aren't subject to subscriptions.
+    pub fn matches_response(&self, response: &Response) -> bool {
+        match response {
+            Response::Packet(packet) => self.matches(packet),
+            _ => true,
+        }
+    }
+
+    fn matches_protocol(&self, packet: &Packet) -> bool {
+        self.protocols.is_empty() || self.protocols.contains(&packet.session_protocol)
+    }
+
+    fn matches_port(&self, packet: &Packet) -> bool {
+        let source_port = packet.source_addr.port();
+        let destination_port = packet.destination_addr.port();
+
+        self.ports.is_empty()
+            || self
+                .ports
+                .iter()
+                .any(|range| range.contains(source_port) || range.contains(destination_port))
+    }
+
+    fn matches_stream(&self, packet: &Packet) -> bool {
+        if self.rtp_streams.is_empty() && self.mpegts_streams.is_empty() {
+            return true;
+        }
+
+        match packet.contents {
+            SessionPacket::Rtp(ref rtp) => self.rtp_streams.contains(&(
+                packet.source_addr,
+                packet.destination_addr,
+                packet.transport_protocol,
+                rtp.ssrc,
+            )),
+            SessionPacket::Mpegts(_) => self.mpegts_streams.contains(&(
+                packet.source_addr,
+                packet.destination_addr,
+                packet.transport_protocol,
+            )),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::TransportProtocol;
+    use crate::MpegtsPacket;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn packet(source: &str, destination: &str, protocol: SessionProtocol) -> Packet {
+        let mut packet = Packet::build_from_datagram(
+            &[],
+            1,
+            source.parse().unwrap(),
+            destination.parse().unwrap(),
+            Duration::ZERO,
+        );
+        packet.session_protocol = protocol;
+        packet
+    }
+
+    #[test]
+    fn default_subscription_matches_everything() {
+        let subscription = Subscription::default();
+
+        assert!(subscription.is_empty());
+        assert!(subscription.matches(&packet(
+            "10.0.0.1:5000",
+            "10.0.0.2:6000",
+            SessionProtocol::Unknown
+        )));
+    }
+
+    #[test]
+    fn matches_protocols() {
+        let subscription = Subscription {
+            protocols: vec![SessionProtocol::Rtp, SessionProtocol::Rtcp],
+            ..Default::default()
+        };
+
+        assert!(subscription.matches(&packet("10.0.0.1:1", "10.0.0.2:2", SessionProtocol::Rtcp)));
+        assert!(!subscription.matches(&packet(
+            "10.0.0.1:1",
+            "10.0.0.2:2",
+            SessionProtocol::Mpegts
+        )));
+    }
+
+    #[test]
+    fn matches_either_port() {
+        let subscription = Subscription {
+            ports: vec![PortRange::new(5000, 5010), PortRange::new(8000, 8000)],
+            ..Default::default()
+        };
+        let protocol = SessionProtocol::Unknown;
+
+        assert!(subscription.matches(&packet("10.0.0.1:5010", "10.0.0.2:1", protocol)));
+        assert!(subscription.matches(&packet("10.0.0.1:1", "10.0.0.2:8000", protocol)));
+        assert!(!subscription.matches(&packet("10.0.0.1:5011", "10.0.0.2:7999", protocol)));
+    }
+
+    #[test]
+    fn selected_streams_exclude_other_packets() {
+        let source: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let destination: SocketAddr = "239.0.0.1:5000".parse().unwrap();
+        let subscription = Subscription {
+            mpegts_streams: vec![(source, destination, TransportProtocol::Udp)],
+            ..Default::default()
+        };
+
+        let mut selected = packet("10.0.0.1:5000", "239.0.0.1:5000", SessionProtocol::Mpegts);
+        selected.contents = SessionPacket::Mpegts(MpegtsPacket {
+            number_of_fragments: 0,
+            fragments: Vec::new(),
+        });
+        let mut other = selected.clone();
+        other.destination_addr = "239.0.0.2:5000".parse().unwrap();
+        let unknown = packet("10.0.0.1:5000", "239.0.0.1:5000", SessionProtocol::Unknown);
+
+        assert!(subscription.matches(&selected));
+        assert!(!subscription.matches(&other));
+        assert!(!subscription.matches(&unknown));
+    }
+}
@@ -0,0 +1,123 @@
+const HEADER_END: &[u8] = b"\r\n\r\n";
+const LINE_END: &str = "\r\n";
+
+/// Syntax of the start lines of a protocol whose messages are framed like HTTP ones,
+/// with a head of CRLF-terminated lines followed by the body, as RTSP and SIP messages are.
+#[derive(Debug, Clone, Copy)]
+pub struct TextSyntax {
+    /// Beginning of the version in the start lines, e.g. `RTSP/`
+    pub version_prefix: &'static str,
+    pub is_method_char: fn(char) -> bool,
+}
+
+/// Head of a message, the body begins at `body_start` in the parsed buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMessage<'a> {
+    pub start_line: &'a str,
+    pub headers: Vec<(String, String)>,
+    pub body_start: usize,
+}
+
+impl TextSyntax {
+    pub fn is_method(&self, token: &str) -> bool {
+        !token.is_empty() && token.chars().all(self.is_method_char)
+    }
+
+    /// Parses the head of the message, the buffer has to begin with a method
+    /// or the version so that other payloads aren't searched for the end of a head.
+    pub fn parse<'a>(&self, buffer: &'a [u8]) -> Option<TextMessage<'a>> {
+        if !self.starts_message(buffer) {
+            return None;
+        }
+
+        let header_end = buffer
+            .windows(HEADER_END.len())
+            .position(|window| window == HEADER_END)?;
+        let head = std::str::from_utf8(&buffer[..header_end]).ok()?;
+
+        let mut lines = head.split(LINE_END);
+        let start_line = lines.next()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Some(TextMessage {
+            start_line,
+            headers,
+            body_start: header_end + HEADER_END.len(),
+        })
+    }
+
+    fn starts_message(&self, buffer: &[u8]) -> bool {
+        if buffer.starts_with(self.version_prefix.as_bytes()) {
+            return true;
+        }
+
+        let method_length = buffer
+            .iter()
+            .take_while(|&&byte| (self.is_method_char)(byte as char))
+            .count();
+        method_length > 0 && buffer.get(method_length) == Some(&b' ')
+    }
+}
+
+impl TextMessage<'_> {
+    /// Value of the first header with the name, their names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The body of `length` bytes, or up to the end of the buffer without a length,
+    /// cut short if it continues past the buffer.
+    pub fn body(&self, buffer: &[u8], length: Option<usize>) -> String {
+        let body_start = self.body_start.min(buffer.len());
+        let body_end = match length {
+            Some(length) => body_start.saturating_add(length).min(buffer.len()),
+            None => buffer.len(),
+        };
+        String::from_utf8_lossy(&buffer[body_start..body_end]).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYNTAX: TextSyntax = TextSyntax {
+        version_prefix: "RTSP/",
+        is_method_char: |char| char.is_ascii_uppercase(),
+    };
+
+    #[test]
+    fn head_and_body() {
+        let buffer = b"OPTIONS * RTSP/1.0\r\nCSeq: 1\r\ncontent-length:  4 \r\n\r\nbodyrest";
+        let message = SYNTAX.parse(buffer).unwrap();
+
+        assert_eq!(message.start_line, "OPTIONS * RTSP/1.0");
+        assert_eq!(message.header("Content-Length"), Some("4"));
+        assert_eq!(message.body_start, buffer.len() - 8);
+        assert_eq!(message.body(buffer, Some(4)), "body");
+        assert_eq!(message.body(buffer, Some(100)), "bodyrest");
+        assert_eq!(message.body(buffer, None), "bodyrest");
+    }
+
+    #[test]
+    fn other_payloads() {
+        for buffer in [
+            &b"RTSP/1.0 200 OK\r\nCSeq: 1\r\n"[..],
+            b"\x80\x00OPTIONS * RTSP/1.0\r\n\r\n",
+            b"options * RTSP/1.0\r\n\r\n",
+            b"OPTIONS\r\n\r\n",
+            b"\r\n\r\n",
+            b"",
+        ] {
+            assert_eq!(SYNTAX.parse(buffer), None, "{:?}", buffer);
+        }
+
+        assert!(SYNTAX.parse(b"RTSP/1.0 200 OK\r\n\r\n").is_some());
+    }
+}
@@ -35,29 +35,70 @@ pub fn setup_packet_handlers(_input: TokenStream) -> TokenStream {
 #[proc_macro]
 pub fn setup_routes(_input: TokenStream) -> TokenStream {
     // Example usage:
-    // setup_routes!(clients, source_to_packets)
+    // setup_routes!(clients, source_to_packets, config)
     let input = parse_macro_input!(_input as syn::ExprTuple);
     let clients = &input.elems[0];
     let source_to_packets = &input.elems[1];
+    let config = &input.elems[2];
 
     let expanded = quote! {
         {
-            let clients_filter = warp::any().map(move || #clients.clone());
-            let source_to_packets_filter = warp::any().map(move || #source_to_packets.clone());
+            let clients_cl = #clients.clone();
+            let clients_filter = warp::any().map(move || clients_cl.clone());
+            let source_to_packets_cl = #source_to_packets.clone();
+            let source_to_packets_filter = warp::any().map(move || source_to_packets_cl.clone());
+            let config_cl = #config.clone();
+            let config_filter = warp::any().map(move || config_cl.clone());
+            let auth = crate::server::auth::authenticate(#config.auth.clone());
+            let restrict = crate::server::auth::restrict(#config.auth.clone());
+            let require_full_access = crate::server::auth::require_full_access(#config.auth.clone());
 
             let ws = warp::path(crate::server::constants::WEBSOCKET_PATH)
                 .and(warp::ws())
-                .and(clients_filter)
-                .and(source_to_packets_filter)
-                .map(|ws: warp::ws::Ws, clients_cl, source_to_packets_cl| {
+                .and(auth.clone())
+                .and(clients_filter.clone())
+                .and(source_to_packets_filter.clone())
+                .and(config_filter.clone())
+                .map(|ws: warp::ws::Ws, role, clients_cl, source_to_packets_cl, config_cl| {
                     ws.on_upgrade(move |socket| {
-                        crate::server::client::handle_connection(socket, clients_cl, source_to_packets_cl)
+                        crate::server::client::handle_connection(socket, role, clients_cl, source_to_packets_cl, config_cl)
                     })
                 });
 
-            let index_html = warp::path::end().and_then(crate::server::asset::serve_index);
-            let other = warp::path::tail().and_then(crate::server::asset::serve);
-            ws.or(index_html).or(other)
+            let upload = warp::path(crate::server::constants::UPLOAD_PATH)
+                .and(warp::path::param::<String>())
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(require_full_access)
+                .and(warp::body::content_length_limit(crate::server::constants::MAX_UPLOAD_SIZE))
+                .and(warp::body::bytes())
+                .and(clients_filter)
+                .and(source_to_packets_filter.clone())
+                .and(config_filter)
+                .and_then(crate::server::upload::upload);
+
+            let api = restrict.clone().and(crate::server::api::routes(#source_to_packets.clone()));
+            let metrics = warp::path(crate::server::constants::METRICS_PATH)
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(restrict.clone())
+                .and(source_to_packets_filter)
+                .and_then(crate::server::metrics::serve);
+
+            let index_html = warp::path::end()
+                .and(restrict.clone())
+                .and(warp::query::<crate::server::auth::TokenQuery>())
+                .and(warp::any().and_then(crate::server::asset::serve_index))
+                .map(crate::server::auth::remember_token);
+            let other = restrict
+                .and(warp::path::tail())
+                .and_then(crate::server::asset::serve);
+            ws.or(upload)
+                .or(api)
+                .or(metrics)
+                .or(index_html)
+                .or(other)
+                .recover(crate::server::auth::handle_rejection)
         }
     };
     expanded.into()
@@ -80,10 +121,8 @@ pub fn spawn_message_sender(_input: TokenStream) -> TokenStream {
                 loop {
                     ticker.tick().await;
                     let mut clients = clients_for_sender.write().await;
-                    for client in clients.values_mut() {
-                        if let Some(msg) = client.queue.pop_front() {
-                            let _ = client.sender.send(msg);
-                        }
+                    for (client_id, client) in clients.iter_mut() {
+                        client.flush(*client_id);
                     }
                 }
             });
@@ -96,15 +135,27 @@ pub fn spawn_message_sender(_input: TokenStream) -> TokenStream {
 #[proc_macro]
 pub fn run_server(_input: TokenStream) -> TokenStream {
     // Usage:
-    // run_server!(routes, addr)
+    // run_server!(routes, addr, tls)
     let input = parse_macro_input!(_input as syn::ExprTuple);
     let routes = &input.elems[0];
     let addr = &input.elems[1];
+    let tls = &input.elems[2];
 
     let expanded = quote! {
-        {
-            println!("Netpix running on http://{}/", #addr);
-            warp::serve(#routes).try_bind(#addr).await;
+        match #tls {
+            Some(tls) => {
+                println!("Netpix running on https://{}/", #addr);
+                warp::serve(#routes)
+                    .tls()
+                    .cert_path(&tls.cert)
+                    .key_path(&tls.key)
+                    .bind(#addr)
+                    .await;
+            }
+            None => {
+                println!("Netpix running on http://{}/", #addr);
+                warp::serve(#routes).try_bind(#addr).await;
+            }
         }
     };
     expanded.into()
@@ -0,0 +1,329 @@
+pub mod mpegts;
+pub mod rtp;
+
+use mpegts::{MpegtsStreamAnalysis, MpegtsStreamSummary};
+use netpix_common::packet::{SessionPacket, SessionProtocol};
+use netpix_common::{FragmentStats, MpegtsStreamKey, Packet, RtpStreamKey};
+use rtp::{RtpStreamAnalysis, RtpStreamSummary};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Incrementally computes per-stream statistics out of decoded packets,
+/// without depending on the client-side (wasm) stream structures.
+#[derive(Debug, Clone, Default)]
+pub struct Analyzer {
+    counters: ProtocolCounters,
+    rtp_streams: BTreeMap<RtpStreamKey, RtpStreamAnalysis>,
+    mpegts_streams: BTreeMap<MpegtsStreamKey, MpegtsStreamAnalysis>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Counter {
+    pub packets: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProtocolCounters {
+    pub total: Counter,
+    pub unknown: Counter,
+    pub rtp: Counter,
+    pub rtcp: Counter,
+    pub mpegts: Counter,
+    pub stun: Counter,
+    pub rtsp: Counter,
+    pub sip: Counter,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub counters: ProtocolCounters,
+    /// Filled in by the caller, as reassembly happens before packets reach the analyzer
+    pub fragments: FragmentStats,
+    pub rtp_streams: Vec<RtpStreamSummary>,
+    pub mpegts_streams: Vec<MpegtsStreamSummary>,
+}
+
+impl ProtocolCounters {
+    fn add(&mut self, protocol: SessionProtocol, bytes: usize) {
+        let counter = match protocol {
+            SessionProtocol::Unknown => &mut self.unknown,
+            SessionProtocol::Rtp => &mut self.rtp,
+            SessionProtocol::Rtcp => &mut self.rtcp,
+            SessionProtocol::Mpegts => &mut self.mpegts,
+            SessionProtocol::Stun => &mut self.stun,
+            SessionProtocol::Rtsp => &mut self.rtsp,
+            SessionProtocol::Sip => &mut self.sip,
+        };
+
+        for counter in [counter, &mut self.total] {
+            counter.packets += 1;
+            counter.bytes += bytes;
+        }
+    }
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_packet(&mut self, packet: &Packet) {
+        self.counters
+            .add(packet.session_protocol, packet.length as usize);
+
+        match packet.contents {
+            SessionPacket::Rtp(ref rtp) => {
+                let key = (
+                    packet.source_addr,
+                    packet.destination_addr,
+                    packet.transport_protocol,
+                    rtp.ssrc,
+                );
+
+                if let Some(stream) = self.rtp_streams.get_mut(&key) {
+                    stream.add_rtp_packet(packet, rtp);
+                } else {
+                    self.rtp_streams
+                        .insert(key, RtpStreamAnalysis::new(packet, rtp));
+                }
+            }
+            SessionPacket::Mpegts(ref mpegts) => {
+                let key = (
+                    packet.source_addr,
+                    packet.destination_addr,
+                    packet.transport_protocol,
+                );
+
+                self.mpegts_streams
+                    .entry(key)
+                    .or_insert_with(|| MpegtsStreamAnalysis::new(packet))
+                    .add_mpegts_packet(packet, mpegts);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn counters(&self) -> &ProtocolCounters {
+        &self.counters
+    }
+
+    pub fn rtp_streams(&self) -> &BTreeMap<RtpStreamKey, RtpStreamAnalysis> {
+        &self.rtp_streams
+    }
+
+    pub fn mpegts_streams(&self) -> &BTreeMap<MpegtsStreamKey, MpegtsStreamAnalysis> {
+        &self.mpegts_streams
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            counters: self.counters,
+            fragments: FragmentStats::default(),
+            rtp_streams: self.rtp_streams.values().map(|s| s.summary()).collect(),
+            mpegts_streams: self.mpegts_streams.values().map(|s| s.summary()).collect(),
+        }
+    }
+}
+
+impl Report {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Flattens both stream kinds into a single table, list-like columns
+    /// (payload types, PIDs, programs) are joined with `;`.
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![[
+            "kind",
+            "source",
+            "destination",
+            "protocol",
+            "ssrc",
+            "packets",
+            "expected_packets",
+            "lost_packets",
+            "loss_percentage",
+            "mean_jitter_ms",
+            "max_jitter_ms",
+            "bytes",
+            "mean_bitrate_bps",
+            "mean_packet_rate",
+            "duration_secs",
+            "payload_types",
+            "continuity_errors",
+            "pids",
+            "programs",
+        ]
+        .join(",")];
+
+        for stream in &self.rtp_streams {
+            let row = [
+                "rtp".to_string(),
+                stream.source_addr.to_string(),
+                stream.destination_addr.to_string(),
+                stream.protocol.to_string(),
+                format!("{:#010x}", stream.ssrc),
+                stream.packets.to_string(),
+                stream.expected_packets.to_string(),
+                stream.lost_packets.to_string(),
+                format!("{:.3}", stream.loss_percentage),
+                format_optional(stream.mean_jitter_ms),
+                format_optional(stream.max_jitter_ms),
+                stream.bytes.to_string(),
+                format!("{:.3}", stream.mean_bitrate_bps),
+                format!("{:.3}", stream.mean_packet_rate),
+                format!("{:.6}", stream.duration_secs),
+                stream.payload_types.join(";"),
+                String::new(),
+                String::new(),
+                String::new(),
+            ];
+            lines.push(csv_row(&row));
+        }
+
+        for stream in &self.mpegts_streams {
+            let pids: Vec<_> = stream.pids.iter().map(|pid| pid.pid.to_string()).collect();
+            let programs: Vec<_> = stream
+                .pmt
+                .iter()
+                .map(|pmt| format!("{}:{}", pmt.program_number, stream_pids(pmt).join("|")))
+                .collect();
+
+            let row = [
+                "mpegts".to_string(),
+                stream.source_addr.to_string(),
+                stream.destination_addr.to_string(),
+                stream.protocol.to_string(),
+                String::new(),
+                stream.packets.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                stream.bytes.to_string(),
+                format!("{:.3}", stream.mean_bitrate_bps),
+                format!("{:.3}", stream.mean_packet_rate),
+                format!("{:.6}", stream.duration_secs),
+                String::new(),
+                stream.continuity_errors.to_string(),
+                pids.join(";"),
+                programs.join(";"),
+            ];
+            lines.push(csv_row(&row));
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn stream_pids(pmt: &mpegts::PmtSummary) -> Vec<String> {
+    pmt.elementary_streams
+        .iter()
+        .map(|es| es.pid.to_string())
+        .collect()
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value.map_or(String::new(), |value| format!("{:.3}", value))
+}
+
+fn csv_row(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Captures `payload` as a UDP datagram from 10.0.0.1 to 10.0.0.2 on an Ethernet link.
+    pub(super) fn udp(payload: &[u8], id: usize, port: u16, arrival: Duration) -> Packet {
+        let mut frame = vec![0; 12];
+        frame.extend(0x0800u16.to_be_bytes());
+
+        frame.extend([0x45, 0x00]);
+        frame.extend((20 + 8 + payload.len() as u16).to_be_bytes());
+        frame.extend([0x00, 0x00, 0x40, 0x00, 64, 17, 0x00, 0x00]);
+        frame.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+
+        frame.extend(port.to_be_bytes());
+        frame.extend(port.to_be_bytes());
+        frame.extend((8 + payload.len() as u16).to_be_bytes());
+        frame.extend([0x00, 0x00]);
+        frame.extend(payload);
+
+        let header = pcap::PacketHeader {
+            ts: libc::timeval {
+                tv_sec: arrival.as_secs() as _,
+                tv_usec: arrival.subsec_micros() as _,
+            },
+            caplen: frame.len() as u32,
+            len: frame.len() as u32,
+        };
+        let raw_packet = pcap::Packet::new(&header, &frame);
+        let mut packet = Packet::build(&raw_packet, id, pcap::Linktype::ETHERNET).unwrap();
+        packet.guess_payload();
+        packet
+    }
+
+    fn rtp(ssrc: u32, sequence_number: u16) -> Packet {
+        let mut payload = vec![0x80, 0x00];
+        payload.extend(sequence_number.to_be_bytes());
+        payload.extend((sequence_number as u32 * 160).to_be_bytes());
+        payload.extend(ssrc.to_be_bytes());
+        payload.extend([0xff; 160]);
+
+        udp(
+            &payload,
+            sequence_number as usize,
+            5004,
+            Duration::from_millis(sequence_number as u64 * 20),
+        )
+    }
+
+    #[test]
+    fn streams_by_ssrc() {
+        let mut analyzer = Analyzer::new();
+        for packet in [rtp(1, 1), rtp(2, 1), rtp(1, 2), rtp(1, 4), rtp(2, 2)] {
+            analyzer.add_packet(&packet);
+        }
+
+        let counters = analyzer.counters();
+        assert_eq!(counters.total.packets, 5);
+        assert_eq!(counters.rtp.packets, 5);
+        assert_eq!(counters.rtp.bytes, 5 * (20 + 8 + 12 + 160));
+
+        let report = analyzer.report();
+        let streams: Vec<_> = report
+            .rtp_streams
+            .iter()
+            .map(|stream| (stream.ssrc, stream.packets, stream.lost_packets))
+            .collect();
+        assert_eq!(streams, [(1, 3, 1), (2, 2, 0)]);
+    }
+
+    #[test]
+    fn other_packets_are_only_counted() {
+        let mut analyzer = Analyzer::new();
+        analyzer.add_packet(&udp(b"hello", 1, 4000, Duration::ZERO));
+
+        assert_eq!(analyzer.counters().unknown.packets, 1);
+        assert!(analyzer.rtp_streams().is_empty());
+        assert!(analyzer.mpegts_streams().is_empty());
+
+        let csv = analyzer.report().to_csv();
+        assert_eq!(csv.lines().count(), 1);
+    }
+}
@@ -0,0 +1,395 @@
+use super::rtp::rate;
+use netpix_common::mpegts::aggregator::MpegtsAggregator;
+use netpix_common::mpegts::header::{AdaptationFieldControl, PIDTable};
+use netpix_common::mpegts::psi::pat::fragmentary_pat::FragmentaryProgramAssociationTable;
+use netpix_common::mpegts::psi::pat::ProgramAssociationTable;
+use netpix_common::mpegts::psi::pmt::fragmentary_pmt::FragmentaryProgramMapTable;
+use netpix_common::mpegts::psi::pmt::ProgramMapTable;
+use netpix_common::mpegts::psi::psi_buffer::{FragmentaryPsi, PsiBuffer};
+use netpix_common::mpegts::MpegtsFragment;
+use netpix_common::packet::TransportProtocol;
+use netpix_common::{MpegtsPacket, Packet};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const CONTINUITY_COUNTER_MODULO: u8 = 16;
+
+#[derive(Debug, Clone, Default)]
+struct PidAnalysis {
+    fragments: usize,
+    continuity_errors: usize,
+    transport_errors: usize,
+    last_continuity_counter: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MpegtsStreamAnalysis {
+    source_addr: SocketAddr,
+    destination_addr: SocketAddr,
+    protocol: TransportProtocol,
+    packets: usize,
+    bytes: usize,
+    first_time: Duration,
+    last_time: Duration,
+    pids: BTreeMap<u16, PidAnalysis>,
+    aggregator: MpegtsAggregator,
+    pat: Option<ProgramAssociationTable>,
+    pmt: BTreeMap<u16, ProgramMapTable>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MpegtsStreamSummary {
+    pub source_addr: SocketAddr,
+    pub destination_addr: SocketAddr,
+    pub protocol: TransportProtocol,
+    pub packets: usize,
+    pub fragments: usize,
+    pub bytes: usize,
+    pub mean_bitrate_bps: f64,
+    pub mean_packet_rate: f64,
+    pub duration_secs: f64,
+    pub continuity_errors: usize,
+    pub pids: Vec<PidSummary>,
+    pub pat: Option<PatSummary>,
+    pub pmt: Vec<PmtSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PidSummary {
+    pub pid: u16,
+    pub fragments: usize,
+    pub continuity_errors: usize,
+    pub transport_errors: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PatSummary {
+    pub transport_stream_id: u16,
+    pub programs: Vec<ProgramSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramSummary {
+    pub program_number: u16,
+    pub program_map_pid: Option<u16>,
+    pub network_pid: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PmtSummary {
+    pub program_map_pid: u16,
+    pub program_number: u16,
+    pub pcr_pid: u16,
+    pub elementary_streams: Vec<ElementaryStreamSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ElementaryStreamSummary {
+    pub pid: u16,
+    pub stream_type: String,
+}
+
+impl MpegtsStreamAnalysis {
+    pub fn new(packet: &Packet) -> Self {
+        Self {
+            source_addr: packet.source_addr,
+            destination_addr: packet.destination_addr,
+            protocol: packet.transport_protocol,
+            packets: 0,
+            bytes: 0,
+            first_time: packet.timestamp,
+            last_time: packet.timestamp,
+            pids: BTreeMap::new(),
+            aggregator: MpegtsAggregator::new(),
+            pat: None,
+            pmt: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_mpegts_packet(&mut self, packet: &Packet, mpegts: &MpegtsPacket) {
+        self.packets += 1;
+        self.bytes += packet.length as usize;
+        self.first_time = self.first_time.min(packet.timestamp);
+        self.last_time = self.last_time.max(packet.timestamp);
+
+        for fragment in &mpegts.fragments {
+            self.update_pid(fragment);
+            self.process_pat_fragment(fragment);
+            self.process_pmt_fragment(fragment);
+        }
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.last_time.saturating_sub(self.first_time)
+    }
+
+    pub fn get_continuity_errors(&self) -> usize {
+        self.pids.values().map(|pid| pid.continuity_errors).sum()
+    }
+
+    pub fn pid_continuity_errors(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.pids
+            .iter()
+            .map(|(pid, analysis)| (*pid, analysis.continuity_errors))
+    }
+
+    fn update_pid(&mut self, fragment: &MpegtsFragment) {
+        let header = &fragment.header;
+        let pid_analysis = self.pids.entry(u16::from(&header.pid)).or_default();
+        pid_analysis.fragments += 1;
+
+        if header.transport_error_indicator {
+            pid_analysis.transport_errors += 1;
+        }
+
+        // null packets carry no meaningful continuity counter and
+        // the counter does not increment for packets without payload
+        let has_payload =
+            header.adaptation_field_control != AdaptationFieldControl::AdaptationFieldOnly;
+        if header.pid == PIDTable::NullPacket || !has_payload {
+            return;
+        }
+
+        let discontinuity_indicated = fragment
+            .adaptation_field
+            .as_ref()
+            .is_some_and(|field| field.discontinuity_indicator);
+
+        if let Some(last) = pid_analysis.last_continuity_counter {
+            let expected = (last + 1) % CONTINUITY_COUNTER_MODULO;
+            let is_duplicate = header.continuity_counter == last;
+            if header.continuity_counter != expected && !is_duplicate && !discontinuity_indicated {
+                pid_analysis.continuity_errors += 1;
+            }
+        }
+        pid_analysis.last_continuity_counter = Some(header.continuity_counter);
+    }
+
+    fn process_pat_fragment(&mut self, fragment: &MpegtsFragment) {
+        if fragment.header.pid != PIDTable::ProgramAssociation {
+            return;
+        }
+
+        let Some(payload) = &fragment.payload else {
+            return;
+        };
+        let Some(pat_fragment) = FragmentaryProgramAssociationTable::unmarshall(
+            &payload.data,
+            fragment.header.payload_unit_start_indicator,
+        ) else {
+            return;
+        };
+
+        self.aggregator
+            .pat_buffer
+            .set_last_section_number(pat_fragment.header.last_section_number);
+        self.aggregator.add_pat(pat_fragment);
+        if let Some(pat) = self.aggregator.get_pat() {
+            self.pat = Some(pat);
+        }
+    }
+
+    fn process_pmt_fragment(&mut self, fragment: &MpegtsFragment) {
+        let Some(pat) = &self.pat else {
+            return;
+        };
+
+        let pid = u16::from(&fragment.header.pid);
+        if !pat.programs.iter().any(|p| p.program_map_pid == Some(pid)) {
+            return;
+        }
+
+        let Some(payload) = &fragment.payload else {
+            return;
+        };
+        let Some(pmt_fragment) = FragmentaryProgramMapTable::unmarshall(
+            &payload.data,
+            fragment.header.payload_unit_start_indicator,
+        ) else {
+            return;
+        };
+
+        self.aggregator.add_pmt(pid, pmt_fragment);
+        if let Some(pmt) = self.aggregator.get_pmt(pid) {
+            self.pmt.insert(pid, pmt);
+        }
+    }
+
+    pub fn summary(&self) -> MpegtsStreamSummary {
+        let duration = self.get_duration().as_secs_f64();
+
+        MpegtsStreamSummary {
+            source_addr: self.source_addr,
+            destination_addr: self.destination_addr,
+            protocol: self.protocol,
+            packets: self.packets,
+            fragments: self.pids.values().map(|pid| pid.fragments).sum(),
+            bytes: self.bytes,
+            mean_bitrate_bps: rate(self.bytes as f64 * 8.0, duration),
+            mean_packet_rate: rate(self.packets as f64, duration),
+            duration_secs: duration,
+            continuity_errors: self.get_continuity_errors(),
+            pids: self
+                .pids
+                .iter()
+                .map(|(pid, analysis)| PidSummary {
+                    pid: *pid,
+                    fragments: analysis.fragments,
+                    continuity_errors: analysis.continuity_errors,
+                    transport_errors: analysis.transport_errors,
+                })
+                .collect(),
+            pat: self.pat.as_ref().map(|pat| PatSummary {
+                transport_stream_id: pat.transport_stream_id,
+                programs: pat
+                    .programs
+                    .iter()
+                    .map(|program| ProgramSummary {
+                        program_number: program.program_number,
+                        program_map_pid: program.program_map_pid,
+                        network_pid: program.network_pid,
+                    })
+                    .collect(),
+            }),
+            pmt: self
+                .pmt
+                .iter()
+                .map(|(pid, pmt)| PmtSummary {
+                    program_map_pid: *pid,
+                    program_number: pmt.fields.program_number,
+                    pcr_pid: pmt.fields.pcr_pid,
+                    elementary_streams: pmt
+                        .elementary_streams_info
+                        .iter()
+                        .map(|es| ElementaryStreamSummary {
+                            pid: es.elementary_pid,
+                            stream_type: es.stream_type.to_string(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::udp;
+    use netpix_common::packet::SessionPacket;
+
+    const PID: u16 = 0x100;
+    const NULL_PID: u16 = 0x1fff;
+
+    #[derive(Clone, Copy)]
+    enum Fragment {
+        Payload(u16, u8),
+        // announces a discontinuity of the counter in its adaptation field
+        Discontinuity(u16, u8),
+        AdaptationOnly(u16, u8),
+    }
+
+    fn fragment(fragment: Fragment) -> Vec<u8> {
+        let (pid, control, counter) = match fragment {
+            Fragment::Payload(pid, counter) => (pid, 0b01, counter),
+            Fragment::Discontinuity(pid, counter) => (pid, 0b11, counter),
+            Fragment::AdaptationOnly(pid, counter) => (pid, 0b10, counter),
+        };
+
+        let mut data = vec![0x47, (pid >> 8) as u8, pid as u8, control << 4 | counter];
+        match fragment {
+            Fragment::Payload(..) => {}
+            Fragment::Discontinuity(..) => data.extend([1, 0x80]),
+            Fragment::AdaptationOnly(..) => data.extend([183, 0x00]),
+        }
+        data.resize(188, 0xff);
+        data
+    }
+
+    fn packet(fragments: &[Fragment]) -> Packet {
+        let payload: Vec<_> = fragments.iter().copied().flat_map(fragment).collect();
+        udp(&payload, 1, 5000, Duration::ZERO)
+    }
+
+    fn analyze(packets: &[Packet]) -> MpegtsStreamAnalysis {
+        let mut analysis = MpegtsStreamAnalysis::new(&packets[0]);
+        for packet in packets {
+            let SessionPacket::Mpegts(ref mpegts) = packet.contents else {
+                panic!("not decoded as MPEG-TS: {:?}", packet.contents);
+            };
+            analysis.add_mpegts_packet(packet, mpegts);
+        }
+        analysis
+    }
+
+    fn counters(pid: u16, counters: impl IntoIterator<Item = u8>) -> Vec<Fragment> {
+        counters
+            .into_iter()
+            .map(|counter| Fragment::Payload(pid, counter))
+            .collect()
+    }
+
+    #[test]
+    fn continuous_counters_wrap_around() {
+        let fragments = counters(PID, (0..16).chain(0..5));
+        let packets: Vec<_> = fragments.chunks(7).map(packet).collect();
+        let analysis = analyze(&packets);
+
+        assert_eq!(analysis.get_continuity_errors(), 0);
+        assert_eq!(analysis.summary().fragments, 21);
+        assert_eq!(analysis.summary().packets, 3);
+    }
+
+    #[test]
+    fn skipped_counters() {
+        let analysis = analyze(&[packet(&counters(PID, [0, 1, 3, 4, 9]))]);
+        assert_eq!(analysis.get_continuity_errors(), 2);
+
+        // across the packets
+        let analysis = analyze(&[
+            packet(&counters(PID, [14, 15])),
+            packet(&counters(PID, [1, 2])),
+        ]);
+        assert_eq!(analysis.get_continuity_errors(), 1);
+    }
+
+    #[test]
+    fn duplicates_are_not_errors() {
+        let analysis = analyze(&[packet(&counters(PID, [0, 1, 1, 2]))]);
+        assert_eq!(analysis.get_continuity_errors(), 0);
+    }
+
+    #[test]
+    fn counters_of_each_pid() {
+        let analysis = analyze(&[packet(&[
+            Fragment::Payload(PID, 0),
+            Fragment::Payload(PID + 1, 7),
+            Fragment::Payload(PID, 1),
+            Fragment::Payload(PID + 1, 9),
+            Fragment::Payload(PID, 2),
+        ])]);
+
+        let errors: Vec<_> = analysis.pid_continuity_errors().collect();
+        assert_eq!(errors, [(PID, 0), (PID + 1, 1)]);
+        assert_eq!(analysis.get_continuity_errors(), 1);
+    }
+
+    #[test]
+    fn ignored_counters() {
+        let analysis = analyze(&[packet(&[
+            Fragment::Payload(NULL_PID, 3),
+            Fragment::Payload(NULL_PID, 9),
+            Fragment::Payload(PID, 0),
+            // the counter doesn't increment without a payload
+            Fragment::AdaptationOnly(PID, 0),
+            Fragment::Payload(PID, 1),
+            Fragment::Discontinuity(PID, 8),
+            Fragment::Payload(PID, 9),
+        ])]);
+
+        assert_eq!(analysis.summary().fragments, 7);
+        assert_eq!(analysis.get_continuity_errors(), 0);
+    }
+}
@@ -0,0 +1,343 @@
+use netpix_common::packet::TransportProtocol;
+use netpix_common::rtp::payload_type::PayloadType;
+use netpix_common::{Packet, RtpPacket};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const SEQUENCE_MODULO: i64 = 1 << 16;
+const MAX_DROPOUT: i64 = 3000;
+
+#[derive(Debug, Clone)]
+pub struct RtpStreamAnalysis {
+    source_addr: SocketAddr,
+    destination_addr: SocketAddr,
+    protocol: TransportProtocol,
+    ssrc: u32,
+    packets: usize,
+    bytes: usize,
+    payload_bytes: usize,
+    first_time: Duration,
+    last_time: Duration,
+    // extended (wrap-aware) sequence numbers
+    base_sequence_number: i64,
+    highest_sequence_number: i64,
+    last_arrival: Duration,
+    last_rtp_timestamp: u32,
+    last_payload_type: u8,
+    jitter: f64,
+    sum_jitter: f64,
+    max_jitter: f64,
+    jitter_count: usize,
+    payload_types: BTreeSet<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RtpStreamSummary {
+    pub source_addr: SocketAddr,
+    pub destination_addr: SocketAddr,
+    pub protocol: TransportProtocol,
+    pub ssrc: u32,
+    pub packets: usize,
+    pub expected_packets: usize,
+    pub lost_packets: usize,
+    pub loss_percentage: f64,
+    pub mean_jitter_ms: Option<f64>,
+    pub max_jitter_ms: Option<f64>,
+    pub bytes: usize,
+    pub mean_bitrate_bps: f64,
+    pub mean_rtp_bitrate_bps: f64,
+    pub mean_packet_rate: f64,
+    pub duration_secs: f64,
+    pub payload_types: Vec<String>,
+}
+
+impl RtpStreamAnalysis {
+    pub fn new(packet: &Packet, rtp: &RtpPacket) -> Self {
+        let sequence_number = rtp.sequence_number as i64;
+
+        Self {
+            source_addr: packet.source_addr,
+            destination_addr: packet.destination_addr,
+            protocol: packet.transport_protocol,
+            ssrc: rtp.ssrc,
+            packets: 1,
+            bytes: packet.length as usize,
+            payload_bytes: rtp.payload_length,
+            first_time: packet.timestamp,
+            last_time: packet.timestamp,
+            base_sequence_number: sequence_number,
+            highest_sequence_number: sequence_number,
+            last_arrival: packet.timestamp,
+            last_rtp_timestamp: rtp.timestamp,
+            last_payload_type: rtp.payload_type.id,
+            jitter: 0.0,
+            sum_jitter: 0.0,
+            max_jitter: 0.0,
+            jitter_count: 0,
+            payload_types: BTreeSet::from([rtp.payload_type.id]),
+        }
+    }
+
+    pub fn add_rtp_packet(&mut self, packet: &Packet, rtp: &RtpPacket) {
+        self.packets += 1;
+        self.bytes += packet.length as usize;
+        self.payload_bytes += rtp.payload_length;
+        self.first_time = self.first_time.min(packet.timestamp);
+        self.last_time = self.last_time.max(packet.timestamp);
+        self.payload_types.insert(rtp.payload_type.id);
+
+        self.update_sequence_number(rtp.sequence_number);
+        self.update_jitter(packet, rtp);
+    }
+
+    fn update_sequence_number(&mut self, sequence_number: u16) {
+        // pick the extended sequence number closest to the highest one seen so far,
+        // so both wrap-arounds and reordered packets are accounted for
+        let highest = self.highest_sequence_number;
+        let cycle = highest - highest.rem_euclid(SEQUENCE_MODULO);
+        let extended = [cycle - SEQUENCE_MODULO, cycle, cycle + SEQUENCE_MODULO]
+            .into_iter()
+            .map(|base| base + sequence_number as i64)
+            .min_by_key(|candidate| (candidate - highest).abs())
+            .unwrap();
+
+        if extended > highest && extended - highest <= MAX_DROPOUT {
+            self.highest_sequence_number = extended;
+        } else if extended < self.base_sequence_number && highest - extended <= MAX_DROPOUT {
+            self.base_sequence_number = extended;
+        }
+    }
+
+    fn update_jitter(&mut self, packet: &Packet, rtp: &RtpPacket) {
+        let payload_type_changed = rtp.payload_type.id != self.last_payload_type;
+        let arrival_diff = packet.timestamp.as_secs_f64() - self.last_arrival.as_secs_f64();
+        let rtp_timestamp_diff = rtp.timestamp.wrapping_sub(self.last_rtp_timestamp) as i32 as i64;
+
+        self.last_arrival = packet.timestamp;
+        self.last_rtp_timestamp = rtp.timestamp;
+        self.last_payload_type = rtp.payload_type.id;
+
+        let Some(clock_rate) = rtp.payload_type.clock_rate else {
+            return;
+        };
+
+        if payload_type_changed {
+            self.jitter = 0.0;
+            return;
+        }
+
+        // see RFC 3550, section 6.4.1
+        let diff = arrival_diff - rtp_timestamp_diff as f64 / clock_rate as f64;
+        self.jitter += (diff.abs() - self.jitter) / 16.0;
+
+        self.max_jitter = self.max_jitter.max(self.jitter);
+        self.sum_jitter += self.jitter;
+        self.jitter_count += 1;
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.last_time.saturating_sub(self.first_time)
+    }
+
+    pub fn get_expected_count(&self) -> usize {
+        (self.highest_sequence_number - self.base_sequence_number + 1) as usize
+    }
+
+    pub fn get_lost_count(&self) -> usize {
+        self.get_expected_count().saturating_sub(self.packets)
+    }
+
+    /// Interarrival jitter estimate after the latest packet, in seconds.
+    pub fn get_jitter(&self) -> Option<f64> {
+        (self.jitter_count > 0).then_some(self.jitter)
+    }
+
+    pub fn get_mean_jitter(&self) -> Option<f64> {
+        if self.jitter_count == 0 {
+            return None;
+        }
+        Some(self.sum_jitter / self.jitter_count as f64)
+    }
+
+    pub fn get_max_jitter(&self) -> Option<f64> {
+        (self.jitter_count > 0).then_some(self.max_jitter)
+    }
+
+    pub fn summary(&self) -> RtpStreamSummary {
+        let duration = self.get_duration().as_secs_f64();
+        let expected_packets = self.get_expected_count();
+        let lost_packets = self.get_lost_count();
+
+        RtpStreamSummary {
+            source_addr: self.source_addr,
+            destination_addr: self.destination_addr,
+            protocol: self.protocol,
+            ssrc: self.ssrc,
+            packets: self.packets,
+            expected_packets,
+            lost_packets,
+            loss_percentage: lost_packets as f64 * 100.0 / expected_packets as f64,
+            mean_jitter_ms: self.get_mean_jitter().map(|jitter| jitter * 1000.0),
+            max_jitter_ms: self.get_max_jitter().map(|jitter| jitter * 1000.0),
+            bytes: self.bytes,
+            mean_bitrate_bps: rate(self.bytes as f64 * 8.0, duration),
+            mean_rtp_bitrate_bps: rate(self.payload_bytes as f64 * 8.0, duration),
+            mean_packet_rate: rate(self.packets as f64, duration),
+            duration_secs: duration,
+            payload_types: self
+                .payload_types
+                .iter()
+                .map(|id| {
+                    let payload_type = PayloadType::new(*id);
+                    format!("{} {}", payload_type.id, payload_type.name)
+                })
+                .collect(),
+        }
+    }
+}
+
+pub(super) fn rate(value: f64, duration: f64) -> f64 {
+    if duration > 0.0 {
+        value / duration
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::tests::udp;
+    use netpix_common::packet::SessionPacket;
+
+    const SSRC: u32 = 0x1234_5678;
+
+    // PCMU, with a clock rate of 8 kHz
+    fn packet(sequence_number: u16, timestamp: u32, arrival_ms: u64) -> Packet {
+        let mut payload = vec![0x80, 0x00];
+        payload.extend(sequence_number.to_be_bytes());
+        payload.extend(timestamp.to_be_bytes());
+        payload.extend(SSRC.to_be_bytes());
+        payload.extend([0xff; 160]);
+
+        udp(
+            &payload,
+            sequence_number as usize,
+            5004,
+            Duration::from_millis(arrival_ms),
+        )
+    }
+
+    fn analyze(packets: impl IntoIterator<Item = Packet>) -> RtpStreamAnalysis {
+        let mut analysis: Option<RtpStreamAnalysis> = None;
+        for packet in packets {
+            let SessionPacket::Rtp(ref rtp) = packet.contents else {
+                panic!("not decoded as RTP: {:?}", packet.contents);
+            };
+            match analysis {
+                Some(ref mut analysis) => analysis.add_rtp_packet(&packet, rtp),
+                None => analysis = Some(RtpStreamAnalysis::new(&packet, rtp)),
+            }
+        }
+        analysis.unwrap()
+    }
+
+    // 20 ms of audio per packet
+    fn sequence(numbers: impl IntoIterator<Item = u16>) -> Vec<Packet> {
+        numbers
+            .into_iter()
+            .map(|number| {
+                let index = number.wrapping_sub(1) as u32;
+                packet(number, index * 160, index as u64 * 20)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stream_without_loss() {
+        let analysis = analyze(sequence(1..=50));
+        assert_eq!(analysis.get_expected_count(), 50);
+        assert_eq!(analysis.get_lost_count(), 0);
+        assert_eq!(analysis.get_duration(), Duration::from_millis(980));
+
+        let summary = analysis.summary();
+        assert_eq!(summary.loss_percentage, 0.0);
+        assert!(summary.mean_jitter_ms.unwrap() < 1e-9);
+        assert_eq!(summary.payload_types, ["0 PCMU"]);
+    }
+
+    #[test]
+    fn lost_packets() {
+        let analysis = analyze(sequence([1, 2, 4, 5, 6, 8, 9, 10]));
+        assert_eq!(analysis.get_expected_count(), 10);
+        assert_eq!(analysis.get_lost_count(), 2);
+        assert_eq!(analysis.summary().loss_percentage, 20.0);
+
+        // the loss at the end isn't known until a later packet arrives
+        let analysis = analyze(sequence([1, 2, 3]));
+        assert_eq!(analysis.get_lost_count(), 0);
+    }
+
+    #[test]
+    fn reordered_packets_are_not_lost() {
+        let analysis = analyze(sequence([2, 1, 3, 5, 4, 6]));
+        assert_eq!(analysis.get_expected_count(), 6);
+        assert_eq!(analysis.get_lost_count(), 0);
+    }
+
+    #[test]
+    fn sequence_number_wrap_around() {
+        let analysis = analyze(sequence([65_534, 65_535, 0, 2, 3]));
+        assert_eq!(analysis.get_expected_count(), 6);
+        assert_eq!(analysis.get_lost_count(), 1);
+
+        // reordered across the wrap-around
+        let analysis = analyze(sequence([65_535, 0, 65_534, 1]));
+        assert_eq!(analysis.get_expected_count(), 4);
+        assert_eq!(analysis.get_lost_count(), 0);
+    }
+
+    #[test]
+    fn large_jumps_are_not_counted_as_loss() {
+        let analysis = analyze(sequence([1, 2, 3, 30_000, 4]));
+        assert_eq!(analysis.get_expected_count(), 4);
+        assert_eq!(analysis.packets, 5);
+        assert_eq!(analysis.get_lost_count(), 0);
+    }
+
+    #[test]
+    fn interarrival_jitter() {
+        // each packet arrives 10 ms later than its timestamp tells
+        let packets = (0..3).map(|index| packet(index as u16 + 1, index * 160, index as u64 * 30));
+        let analysis = analyze(packets);
+
+        let first = 0.010 / 16.0;
+        let second = first + (0.010 - first) / 16.0;
+        assert!((analysis.jitter - second).abs() < 1e-9);
+        assert!((analysis.get_max_jitter().unwrap() - second).abs() < 1e-9);
+        assert!((analysis.get_mean_jitter().unwrap() - (first + second) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rtp_timestamp_wrap_around() {
+        let start = u32::MAX - 159;
+        let packets = (0..3).map(|index| {
+            let timestamp = start.wrapping_add(index * 160);
+            packet(index as u16 + 1, timestamp, index as u64 * 20)
+        });
+        let analysis = analyze(packets);
+
+        assert!(analysis.get_jitter().unwrap() < 1e-9);
+        assert!(analysis.get_max_jitter().unwrap() < 1e-9);
+    }
+
+    #[test]
+    fn jitter_of_a_single_packet() {
+        let analysis = analyze(sequence([1]));
+        assert_eq!(analysis.get_max_jitter(), None);
+        assert_eq!(analysis.summary().mean_jitter_ms, None);
+        assert_eq!(analysis.summary().mean_bitrate_bps, 0.0);
+    }
+}
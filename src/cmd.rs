@@ -1,2 +1,4 @@
+pub mod analyze;
 pub mod list;
+mod profile;
 pub mod run;
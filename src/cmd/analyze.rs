@@ -0,0 +1,71 @@
+use crate::analysis::Analyzer;
+use crate::sniffer::Sniffer;
+use log::info;
+use netpix_common::DecodeRule;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Analyze {
+    /// Pcap file to analyze
+    #[arg(short, long)]
+    file: String,
+    /// Output format of the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json)]
+    format: ReportFormat,
+    /// Capture filter string in Wireshark/tcpdump syntax
+    #[arg(short, long, default_value_t = String::new())]
+    capture: String,
+    /// Decode the packets from or to a port, a port range or an address as the protocol
+    /// instead of guessing it, e.g. "5004=rtp", see "run --decode-as"
+    #[arg(long, value_name = "TARGET=PROTOCOL")]
+    decode_as: Vec<DecodeRule>,
+}
+
+impl Analyze {
+    pub async fn run(self) {
+        let mut sniffer = match Sniffer::from_file(&self.file) {
+            Ok(sniffer) => sniffer,
+            Err(err) => {
+                eprintln!(
+                    "Failed to capture packets from source {}, reason: {:?}",
+                    self.file, err
+                );
+                std::process::exit(1);
+            }
+        };
+
+        if sniffer.apply_filter(&self.capture).is_err() {
+            eprintln!("Error: provided capture filter is invalid");
+            std::process::exit(1);
+        }
+
+        let mut analyzer = Analyzer::new();
+        while let Some(result) = sniffer.next_packet().await {
+            match result {
+                Ok(mut packet) => {
+                    packet.decode_payload(&self.decode_as);
+                    analyzer.add_packet(&packet);
+                }
+                Err(err) => info!("Error when capturing a packet: {:?}", err),
+            }
+        }
+
+        let mut report = analyzer.report();
+        report.fragments = sniffer.fragment_stats();
+        match self.format {
+            ReportFormat::Json => match report.to_json() {
+                Ok(json) => println!("{}", json),
+                Err(err) => {
+                    eprintln!("Error: failed to serialize the report: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            ReportFormat::Csv => println!("{}", report.to_csv()),
+        }
+    }
+}
@@ -1,14 +1,82 @@
+use crate::sniffer::Sniffer;
 use clap::Args;
+use netpix_common::packet::SessionProtocol;
 use pcap::{Device, IfFlags};
-use std::net::IpAddr;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ProbeFormat {
+    Table,
+    Json,
+}
 
 #[derive(Debug, Args)]
-pub struct List {}
+pub struct List {
+    /// Capture on every interface that is up for the given number of seconds
+    /// and report the RTP, RTCP and MPEG-TS traffic found on them
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    probe: Option<u64>,
+    /// Output format of the probe report
+    #[arg(long, value_enum, default_value_t = ProbeFormat::Table, requires = "probe")]
+    format: ProbeFormat,
+    /// Enable promiscuous mode while probing
+    #[arg(short = 'P', long, requires = "probe")]
+    promisc: bool,
+}
+
+/// Traffic found on an interface while probing.
+#[derive(Debug, Default, Serialize)]
+struct InterfaceProbe {
+    interface: String,
+    packets: usize,
+    rtp: ProtocolProbe,
+    rtcp: ProtocolProbe,
+    mpegts: ProtocolProbe,
+    /// Why the interface couldn't be probed
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ProtocolProbe {
+    packets: usize,
+    /// Packets per second
+    rate: f64,
+    /// Multicast groups the packets were sent to
+    groups: BTreeSet<SocketAddr>,
+}
 
 impl List {
     pub async fn run(self) {
-        Device::list()
-            .expect("Error occurred while listing devices")
+        let devices = Device::list().expect("Error occurred while listing devices");
+
+        if let Some(secs) = self.probe {
+            let names = devices
+                .into_iter()
+                .filter(|device| {
+                    device.flags.if_flags.contains(IfFlags::UP) && device.name != "any"
+                })
+                .map(|device| device.name)
+                .collect();
+            let probes = probe_all(names, Duration::from_secs(secs), self.promisc).await;
+
+            match self.format {
+                ProbeFormat::Table => print_probes(&probes),
+                ProbeFormat::Json => match serde_json::to_string_pretty(&probes) {
+                    Ok(json) => println!("{}", json),
+                    Err(err) => {
+                        eprintln!("Error: failed to serialize the report: {}", err);
+                        std::process::exit(1);
+                    }
+                },
+            }
+            return;
+        }
+
+        devices
             .iter()
             .filter(|device| {
                 (device.flags.if_flags.contains(IfFlags::UP) && !device.addresses.is_empty())
@@ -33,6 +101,132 @@ impl List {
     }
 }
 
+/// Probes the interfaces at the same time, so it takes `duration` regardless of their number.
+async fn probe_all(names: Vec<String>, duration: Duration, promisc: bool) -> Vec<InterfaceProbe> {
+    let deadline = Instant::now() + duration;
+    let tasks: Vec<_> = names
+        .into_iter()
+        .map(|name| {
+            tokio::spawn(async move {
+                match Sniffer::from_device(&name, promisc) {
+                    Ok(sniffer) => probe(name, sniffer, deadline, duration).await,
+                    Err(err) => InterfaceProbe {
+                        interface: name,
+                        error: Some(format!("{:?}", err)),
+                        ..Default::default()
+                    },
+                }
+            })
+        })
+        .collect();
+
+    let mut probes = Vec::new();
+    for task in tasks {
+        match task.await {
+            Ok(probe) => probes.push(probe),
+            Err(err) => eprintln!("Error: probing an interface failed: {}", err),
+        }
+    }
+
+    probes
+}
+
+async fn probe(
+    interface: String,
+    mut sniffer: Sniffer,
+    deadline: Instant,
+    duration: Duration,
+) -> InterfaceProbe {
+    let mut probe = InterfaceProbe {
+        interface,
+        ..Default::default()
+    };
+
+    while let Ok(Some(result)) = timeout_at(deadline, sniffer.next_packet()).await {
+        let Ok(mut packet) = result else {
+            continue;
+        };
+
+        probe.packets += 1;
+        packet.guess_payload();
+        let protocol = match packet.session_protocol {
+            SessionProtocol::Rtp => &mut probe.rtp,
+            SessionProtocol::Rtcp => &mut probe.rtcp,
+            SessionProtocol::Mpegts => &mut probe.mpegts,
+            SessionProtocol::Stun
+            | SessionProtocol::Rtsp
+            | SessionProtocol::Sip
+            | SessionProtocol::Unknown => continue,
+        };
+
+        protocol.packets += 1;
+        if packet.destination_addr.ip().is_multicast() {
+            protocol.groups.insert(packet.destination_addr);
+        }
+    }
+
+    for protocol in [&mut probe.rtp, &mut probe.rtcp, &mut probe.mpegts] {
+        protocol.rate = protocol.packets as f64 / duration.as_secs_f64();
+    }
+
+    probe
+}
+
+impl InterfaceProbe {
+    fn groups(&self) -> String {
+        let protocols = [
+            ("RTP", &self.rtp),
+            ("RTCP", &self.rtcp),
+            ("MPEG-TS", &self.mpegts),
+        ];
+        let groups: Vec<_> = protocols
+            .iter()
+            .flat_map(|(name, protocol)| {
+                protocol
+                    .groups
+                    .iter()
+                    .map(move |group| format!("{} {}", group, name))
+            })
+            .collect();
+
+        if groups.is_empty() {
+            "-".to_string()
+        } else {
+            groups.join(", ")
+        }
+    }
+}
+
+fn print_probes(probes: &[InterfaceProbe]) {
+    let width = probes
+        .iter()
+        .map(|probe| probe.interface.len())
+        .chain(["INTERFACE".len()])
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:<width$}  {:>8}  {:>10}  {:>10}  {:>10}  MULTICAST GROUPS",
+        "INTERFACE", "PACKETS", "RTP/s", "RTCP/s", "MPEG-TS/s"
+    );
+    for probe in probes {
+        if let Some(ref error) = probe.error {
+            println!("{:<width$}  failed to capture: {}", probe.interface, error);
+            continue;
+        }
+
+        println!(
+            "{:<width$}  {:>8}  {:>10.1}  {:>10.1}  {:>10.1}  {}",
+            probe.interface,
+            probe.packets,
+            probe.rtp.rate,
+            probe.rtcp.rate,
+            probe.mpegts.rate,
+            probe.groups()
+        );
+    }
+}
+
 fn format_flags(flags: IfFlags) -> String {
     let mut result = Vec::new();
 
@@ -0,0 +1,229 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Contents of the `--config` file: options shared by all the profiles under `[defaults]`
+/// and named sets of options under `[profiles.<name>]`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    defaults: Profile,
+    profiles: HashMap<String, Profile>,
+}
+
+/// Options of the `run` command, named after its flags.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub files: Option<Vec<String>>,
+    pub merge: Option<bool>,
+    pub interfaces: Option<Vec<String>>,
+    pub sockets: Option<Vec<String>>,
+    pub capture: Option<String>,
+    pub decode_as: Option<Vec<String>>,
+    pub decode_rules_file: Option<PathBuf>,
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub promisc: Option<bool>,
+    pub buffer_size: Option<usize>,
+    pub message_interval: Option<u64>,
+    pub maximum_package_age: Option<u64>,
+    pub client_queue_size: Option<usize>,
+    pub replay: Option<f64>,
+    pub record: Option<PathBuf>,
+    pub record_file_size: Option<u64>,
+    pub record_file_duration: Option<u64>,
+    pub record_max_files: Option<usize>,
+    pub upload_dir: Option<PathBuf>,
+    pub session: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub token: Option<Vec<String>>,
+    pub read_only_token: Option<Vec<String>>,
+    pub user: Option<Vec<String>>,
+    pub read_only_user: Option<Vec<String>>,
+    /// Options of particular sources, by file path, interface name or socket address
+    pub sources: HashMap<String, SourceProfile>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SourceProfile {
+    /// Used instead of the `capture` filter, sockets can't have one
+    pub capture: Option<String>,
+    /// Used instead of the `buffer-size`
+    pub buffer_size: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(io::Error),
+    Parse(toml::de::Error),
+    UnknownProfile(String, Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(err) => write!(f, "cannot read the config file: {}", err),
+            Self::Parse(err) => write!(f, "invalid config file: {}", err),
+            Self::UnknownProfile(name, names) => write!(
+                f,
+                "no profile `{}` in the config file, available: {}",
+                name,
+                names.join(", ")
+            ),
+        }
+    }
+}
+
+/// Reads the profile from the config file, or only the defaults if no profile is picked.
+pub fn load(path: &Path, name: Option<&str>) -> Result<Profile, Error> {
+    let contents = fs::read_to_string(path).map_err(Error::Read)?;
+    parse(&contents, name)
+}
+
+fn parse(contents: &str, name: Option<&str>) -> Result<Profile, Error> {
+    let mut file: ConfigFile = toml::from_str(contents).map_err(Error::Parse)?;
+
+    let Some(name) = name else {
+        return Ok(file.defaults);
+    };
+
+    match file.profiles.remove(name) {
+        Some(profile) => Ok(profile.or(file.defaults)),
+        None => {
+            let mut names: Vec<_> = file.profiles.into_keys().collect();
+            names.sort_unstable();
+            Err(Error::UnknownProfile(name.to_string(), names))
+        }
+    }
+}
+
+impl Profile {
+    /// Fills the options missing from the profile with the `defaults`.
+    fn or(self, defaults: Profile) -> Profile {
+        let mut sources = defaults.sources;
+        sources.extend(self.sources);
+
+        Profile {
+            files: self.files.or(defaults.files),
+            merge: self.merge.or(defaults.merge),
+            interfaces: self.interfaces.or(defaults.interfaces),
+            sockets: self.sockets.or(defaults.sockets),
+            capture: self.capture.or(defaults.capture),
+            decode_as: self.decode_as.or(defaults.decode_as),
+            decode_rules_file: self.decode_rules_file.or(defaults.decode_rules_file),
+            address: self.address.or(defaults.address),
+            port: self.port.or(defaults.port),
+            promisc: self.promisc.or(defaults.promisc),
+            buffer_size: self.buffer_size.or(defaults.buffer_size),
+            message_interval: self.message_interval.or(defaults.message_interval),
+            maximum_package_age: self.maximum_package_age.or(defaults.maximum_package_age),
+            client_queue_size: self.client_queue_size.or(defaults.client_queue_size),
+            replay: self.replay.or(defaults.replay),
+            record: self.record.or(defaults.record),
+            record_file_size: self.record_file_size.or(defaults.record_file_size),
+            record_file_duration: self.record_file_duration.or(defaults.record_file_duration),
+            record_max_files: self.record_max_files.or(defaults.record_max_files),
+            upload_dir: self.upload_dir.or(defaults.upload_dir),
+            session: self.session.or(defaults.session),
+            tls_cert: self.tls_cert.or(defaults.tls_cert),
+            tls_key: self.tls_key.or(defaults.tls_key),
+            token: self.token.or(defaults.token),
+            read_only_token: self.read_only_token.or(defaults.read_only_token),
+            user: self.user.or(defaults.user),
+            read_only_user: self.read_only_user.or(defaults.read_only_user),
+            sources,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [defaults]
+        port = 3550
+        buffer-size = 1000
+        capture = "udp"
+
+        [defaults.sources."eth0"]
+        buffer-size = 10
+
+        [defaults.sources."eth1"]
+        capture = "udp port 5004"
+
+        [profiles.lab]
+        interfaces = ["eth0", "eth1"]
+        port = 8080
+
+        [profiles.lab.sources."eth1"]
+        buffer-size = 20
+
+        [profiles.replay]
+        files = ["a.pcap"]
+        replay = 2.0
+    "#;
+
+    #[test]
+    fn defaults_without_profile() {
+        let profile = parse(CONFIG, None).unwrap();
+        assert_eq!(profile.port, Some(3550));
+        assert_eq!(profile.interfaces, None);
+        assert_eq!(profile.sources.len(), 2);
+    }
+
+    #[test]
+    fn profile_takes_precedence_over_defaults() {
+        let profile = parse(CONFIG, Some("lab")).unwrap();
+        assert_eq!(profile.port, Some(8080));
+        assert_eq!(profile.buffer_size, Some(1000));
+        assert_eq!(profile.capture.as_deref(), Some("udp"));
+        assert_eq!(
+            profile.interfaces,
+            Some(vec!["eth0".to_string(), "eth1".to_string()])
+        );
+        assert_eq!(profile.replay, None);
+
+        // a source of the profile replaces its default options as a whole
+        assert_eq!(profile.sources["eth0"].buffer_size, Some(10));
+        assert_eq!(profile.sources["eth1"].buffer_size, Some(20));
+        assert_eq!(profile.sources["eth1"].capture, None);
+
+        let profile = parse(CONFIG, Some("replay")).unwrap();
+        assert_eq!(profile.port, Some(3550));
+        assert_eq!(profile.replay, Some(2.0));
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let err = parse(CONFIG, Some("prod")).unwrap_err();
+        assert!(
+            matches!(err, Error::UnknownProfile(ref name, ref names) if name == "prod" && names == &["lab", "replay"]),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn invalid_config() {
+        for config in [
+            "[defaults]\nprot = 3550",
+            "[defaults]\nport = \"http\"",
+            "[profiles.lab.sources.eth0]\nreplay = 2.0",
+            "port = 3550",
+        ] {
+            assert!(
+                matches!(parse(config, None), Err(Error::Parse(_))),
+                "{}",
+                config
+            );
+        }
+    }
+}
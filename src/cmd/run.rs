@@ -1,28 +1,69 @@
+use super::profile::{self, Profile, SourceProfile};
 use crate::server;
-use crate::server::config::Config;
-use crate::sniffer::{Error, Sniffer};
+use crate::server::auth::{Auth, Credentials, Role};
+use crate::server::config::{Config, SourceOverride, Tls};
+use crate::server::decode_rules;
+use crate::server::session::Session;
+use crate::sniffer::{Error, RecordConfig, Sniffer, SocketSpec, SourceOptions};
+use clap::parser::{ArgMatches, ValueSource};
+use netpix_common::DecodeRule;
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::u64;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 
 const DEFAULT_PORT: u16 = 3550;
 const DEFAULT_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
 const DEFAULT_PROMISC: bool = false;
 const DEFAULT_PACKET_BUFFER_SIZE: usize = 32_768;
 const DEFAULT_MAXIMUM_PACKAGE_AGE: u64 = 300;
-const DEFAULT_CLIENT_MESSAGE_INTERVAL_MS: u64 = 5; // ~ 300 messages per second
+const DEFAULT_CLIENT_MESSAGE_INTERVAL_MS: u64 = 5; // ~ 200 batches per second
+const DEFAULT_CLIENT_QUEUE_SIZE: usize = 65_536;
+const DEFAULT_RECORD_FILE_SIZE_MB: u64 = 100;
+const DEFAULT_RECORD_FILE_DURATION: u64 = 3600;
+const DEFAULT_RECORD_MAX_FILES: usize = 24;
+const DEFAULT_UPLOAD_DIR: &str = "netpix-uploads";
+const DEFAULT_SESSION_FILE: &str = "session.netpix";
+const DEFAULT_DECODE_RULES_FILE: &str = "decode-rules.txt";
 
 #[derive(Debug, clap::Args)]
 pub struct Run {
+    /// TOML file with the options, named after the flags, under `[defaults]`
+    /// and `[profiles.<name>]`, the ones given on the command line take precedence
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Profile of the config file to use on top of its defaults
+    #[arg(long, value_name = "NAME", requires = "config")]
+    profile: Option<String>,
     /// Pcap files to capture the packets from
     #[arg(short, long, num_args = 1..)]
     files: Vec<String>,
+    /// Merge the pcap files into a single source with their packets ordered by timestamps,
+    /// e.g. the parts of a capture rotated by "tcpdump -C"
+    #[arg(long)]
+    merge: bool,
     /// Network interfaces to capture the packets from
     #[arg(short, long, num_args = 1..)]
     interfaces: Vec<String>,
-    /// Capture filter string in Wireshark/tcpdump syntax, applies to all sources
+    /// UDP addresses to receive the packets on without capturing, as ADDRESS:PORT[@SOURCE][%INTERFACE],
+    /// multicast groups are joined, only from SOURCE if given, on the interface with the given
+    /// address (IPv4) or index (IPv6), e.g. "239.1.1.1:5000" or "232.1.1.1:5000@10.0.0.1"
+    #[arg(short, long, num_args = 1..)]
+    sockets: Vec<String>,
+    /// Capture filter string in Wireshark/tcpdump syntax, applies to all sources but sockets
     #[arg(short, long, default_value_t = String::new())]
     capture: String,
+    /// Decode the packets from or to a port, a port range or an address as the protocol
+    /// instead of guessing it, e.g. "5004=rtp", "5000-5010=rtcp" or "239.1.1.1:8000=mpegts",
+    /// the first matching rule applies, followed by the ones saved with the session
+    #[arg(long, value_name = "TARGET=PROTOCOL")]
+    decode_as: Vec<DecodeRule>,
+    /// File the decode rules set from the UI are saved to, one TARGET=PROTOCOL per line,
+    /// restored on start if it exists, defaults to "decode-rules.txt" in the current directory
+    #[arg(long, value_name = "FILE")]
+    decode_rules_file: Option<PathBuf>,
     /// IP address used by the application
     #[arg(short, long, default_value_t = DEFAULT_IP)]
     address: IpAddr,
@@ -36,59 +77,410 @@ pub struct Run {
     /// discharging it
     #[arg(short, long, default_value_t = DEFAULT_PACKET_BUFFER_SIZE)]
     buffer_size: usize,
-    /// Interval in milliseconds between client messages
+    /// Interval in milliseconds between client messages, each carrying a batch of packets
     #[arg(short='m', long, default_value_t = DEFAULT_CLIENT_MESSAGE_INTERVAL_MS)]
     message_interval: u64,
+    /// Number of packets queued for a client that can't keep up before new ones are dropped
+    #[arg(long, value_name = "PACKETS", default_value_t = DEFAULT_CLIENT_QUEUE_SIZE)]
+    client_queue_size: usize,
     /// Maximum age of a package in seconds before it is considered outdated
     #[arg(short='M', long, default_value_t = DEFAULT_MAXIMUM_PACKAGE_AGE)]
     maximum_package_age: u64,
+    /// Replay pcap files in real time using the packet timestamps, optionally
+    /// with a speed multiplier, e.g. "--replay 2" or "--replay 0.5"
+    #[arg(long, value_name = "SPEED", num_args = 0..=1, default_missing_value = "1", value_parser = parse_replay_speed)]
+    replay: Option<f64>,
+    /// Record the packets of network interfaces to rotating pcapng files in the directory
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Size in megabytes after which a new recording file is started
+    #[arg(long, value_name = "MB", default_value_t = DEFAULT_RECORD_FILE_SIZE_MB, value_parser = clap::value_parser!(u64).range(1..))]
+    record_file_size: u64,
+    /// Time in seconds after which a new recording file is started
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_RECORD_FILE_DURATION, value_parser = clap::value_parser!(u64).range(1..))]
+    record_file_duration: u64,
+    /// Number of recording files kept per interface, the oldest ones are removed, 0 keeps all
+    #[arg(long, value_name = "COUNT", default_value_t = DEFAULT_RECORD_MAX_FILES)]
+    record_max_files: usize,
+    /// Directory where pcap files uploaded from the browser are stored,
+    /// defaults to a directory in the system's temporary directory
+    #[arg(long, value_name = "DIR")]
+    upload_dir: Option<PathBuf>,
+    /// Session file with the packets and the view saved from the UI, restored on start
+    /// if it exists; sessions are saved to "session.netpix" in the current directory otherwise
+    #[arg(long, value_name = "FILE")]
+    session: Option<PathBuf>,
+    /// PEM certificate to serve the UI over HTTPS
+    #[arg(long, value_name = "FILE", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Token granting full access, passed as a bearer token or with "?token=" in the URL,
+    /// once any credentials are given the UI requires them
+    #[arg(long, value_name = "TOKEN")]
+    token: Vec<String>,
+    /// Token granting access to the packets only, without adding, removing,
+    /// replaying or reparsing anything
+    #[arg(long, value_name = "TOKEN")]
+    read_only_token: Vec<String>,
+    /// User granting full access with HTTP basic authentication
+    #[arg(long, value_name = "USER:PASSWORD", value_parser = Credentials::parse_basic)]
+    user: Vec<Credentials>,
+    /// User granting read-only access with HTTP basic authentication
+    #[arg(long, value_name = "USER:PASSWORD", value_parser = Credentials::parse_basic)]
+    read_only_user: Vec<Credentials>,
+    /// Options of particular sources, only set in the config file
+    #[arg(skip)]
+    sources: HashMap<String, SourceProfile>,
 }
 
 impl Run {
-    pub async fn run(self) {
+    pub async fn run(mut self, matches: &ArgMatches) {
+        if let Some(ref path) = self.config {
+            let profile = match profile::load(path, self.profile.as_deref()) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    return;
+                }
+            };
+
+            if let Err(err) = self.apply_profile(profile, matches) {
+                println!("Error: {}", err);
+                return;
+            }
+        }
+
+        if let Err(err) = self.check_record_options(matches) {
+            println!("Error: {}", err);
+            return;
+        }
+
+        let session = match self.session {
+            Some(ref path) if path.exists() => match Session::read(path) {
+                Ok(session) => Some(session),
+                Err(err) => {
+                    println!("Error: cannot read session {}: {}", path.display(), err);
+                    return;
+                }
+            },
+            _ => None,
+        };
+
         if self.interfaces.is_empty() && !self.files.is_empty() && self.promisc {
             println!("Error: promiscuous mode cannot be used with file captures only");
             return;
         }
 
-        let live_filter = self.create_capture_filter();
-
-        let mut file_sniffers = get_sniffers(self.files, Sniffer::from_file);
-        let mut interface_sniffers = get_sniffers(self.interfaces, |dev| {
-            Sniffer::from_device(dev, self.promisc)
+        let record = self.record.as_ref().map(|dir| {
+            RecordConfig::builder()
+                .dir(dir.clone())
+                .max_file_size(self.record_file_size.saturating_mul(1024 * 1024))
+                .max_file_duration(Duration::from_secs(self.record_file_duration))
+                .max_files(self.record_max_files)
+                .build()
         });
+        let options = SourceOptions::builder()
+            .filter(self.capture.clone())
+            .live_filter(self.create_capture_filter(&self.capture))
+            .promisc(self.promisc)
+            .maybe_replay(self.replay)
+            .maybe_record(record)
+            .build();
 
-        let file_res = apply_filters(&mut file_sniffers, &self.capture);
-        let interface_res = apply_filters(&mut interface_sniffers, &live_filter);
-
-        if file_res.is_err() || interface_res.is_err() {
-            println!("Error: provided capture filter is invalid");
+        // the datagrams of sockets aren't captured, so there is nothing to filter
+        let filtered_socket = self
+            .sources
+            .iter()
+            .find(|(name, source)| source.capture.is_some() && name.parse::<SocketSpec>().is_ok());
+        if let Some((name, _)) = filtered_socket {
+            println!(
+                "Error: capture filters cannot be applied to socket {}",
+                name
+            );
             return;
         }
 
-        let sniffers: HashMap<_, _> = file_sniffers
-            .into_iter()
-            .chain(interface_sniffers)
+        let overrides = self
+            .sources
+            .iter()
+            .map(|(name, source)| {
+                let capture = source.capture.as_ref().unwrap_or(&self.capture);
+                let options = SourceOptions {
+                    filter: capture.clone(),
+                    live_filter: self.create_capture_filter(capture),
+                    ..options.clone()
+                };
+                let source = SourceOverride {
+                    options,
+                    packet_buffer_size: source.buffer_size.unwrap_or(self.buffer_size),
+                };
+                (name.clone(), source)
+            })
             .collect();
 
-        if sniffers.is_empty() {
-            // TODO: use some pretty printing (colors, bold font etc.)
-            println!("Error: no valid sources were passed");
-            return;
+        let tls = match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => Some(Tls { cert, key }),
+            (None, None) => None,
+            _ => {
+                println!("Error: both the TLS certificate and its key are needed");
+                return;
+            }
+        };
+        if let Some(ref tls) = tls {
+            for file in [&tls.cert, &tls.key] {
+                if let Err(err) = std::fs::File::open(file) {
+                    println!("Error: cannot read {}: {}", file.display(), err);
+                    return;
+                }
+            }
+        }
+
+        let mut auth = Auth::default();
+        let tokens = self.token.into_iter().map(|token| (token, Role::Full));
+        let read_only_tokens = self
+            .read_only_token
+            .into_iter()
+            .map(|token| (token, Role::ReadOnly));
+        for (token, role) in tokens.chain(read_only_tokens) {
+            auth.allow(Credentials::Token(token), role);
+        }
+        for user in self.user {
+            auth.allow(user, Role::Full);
+        }
+        for user in self.read_only_user {
+            auth.allow(user, Role::ReadOnly);
+        }
+
+        if auth.is_enabled() && tls.is_none() && !self.address.is_loopback() {
+            println!("Warning: credentials are sent in plain text, consider using --tls-cert");
+        }
+
+        let decode_rules_path = self
+            .decode_rules_file
+            .take()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DECODE_RULES_FILE));
+        let saved_rules = if decode_rules_path.exists() {
+            match decode_rules::read(&decode_rules_path) {
+                Ok(rules) => rules,
+                Err(err) => {
+                    println!(
+                        "Error: cannot read decode rules {}: {}",
+                        decode_rules_path.display(),
+                        err
+                    );
+                    return;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+
+        let mut decode_rules = self.decode_as;
+        let session_rules = session.iter().flat_map(|session| &session.decode_rules);
+        for rule in saved_rules.iter().chain(session_rules) {
+            if !decode_rules.contains(rule) {
+                decode_rules.push(*rule);
+            }
         }
 
         let address = SocketAddr::new(self.address, self.port);
         let config = Config::builder()
             .client_message_interval_ms(self.message_interval)
+            .client_queue_size(self.client_queue_size)
             .max_packets_age(self.maximum_package_age)
             .packet_buffer_size(self.buffer_size)
             .addr(address)
+            .sources(options)
+            .overrides(overrides)
+            .upload_dir(self.upload_dir.unwrap_or_else(default_upload_dir))
+            .session(
+                self.session
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_SESSION_FILE)),
+            )
+            .maybe_session_view(session.as_ref().map(|session| session.view.clone()))
+            .decode_rules(Arc::new(watch::Sender::new(decode_rules)))
+            .decode_rules_file(decode_rules_path)
+            .auth(Arc::new(auth))
+            .maybe_tls(tls)
             .build();
 
-        server::run(sniffers, config).await;
+        let file_sniffers = if self.merge {
+            // named like the source, for its options in the config file
+            let name = self.files.join(",");
+            get_sniffers(vec![name], |name| {
+                Sniffer::open_files(&self.files, config.source_options(name))
+            })
+        } else {
+            get_sniffers(self.files, |file| {
+                Sniffer::open_file(file, config.source_options(file))
+            })
+        };
+        let interface_sniffers = get_sniffers(self.interfaces, |dev| {
+            Sniffer::open_interface(dev, config.source_options(dev))
+        });
+
+        let socket_sniffers = get_sniffers(self.sockets, |address| {
+            Sniffer::open_socket(address, config.source_options(address))
+        });
+
+        let (Ok(file_sniffers), Ok(interface_sniffers), Ok(socket_sniffers)) =
+            (file_sniffers, interface_sniffers, socket_sniffers)
+        else {
+            println!("Error: provided capture filter is invalid");
+            return;
+        };
+
+        let mut sniffers: HashMap<_, _> = file_sniffers
+            .into_iter()
+            .chain(interface_sniffers)
+            .chain(socket_sniffers)
+            .collect();
+
+        let restored = session.map(|session| session.sources).unwrap_or_default();
+        sniffers.retain(|name, sniffer| {
+            let saved = restored.iter().any(|saved| saved.source == sniffer.source);
+            if saved {
+                println!(
+                    "Warning: source {} is restored from the session instead",
+                    name
+                );
+            }
+            !saved
+        });
+
+        if sniffers.is_empty() && restored.is_empty() {
+            // TODO: use some pretty printing (colors, bold font etc.)
+            println!("Error: no valid sources were passed");
+            return;
+        }
+
+        server::run(sniffers, restored, config).await;
+    }
+
+    /// The recording options given on the command line need `--record`,
+    /// which may be set by the profile as well.
+    fn check_record_options(&self, matches: &ArgMatches) -> Result<(), String> {
+        if self.record.is_some() {
+            return Ok(());
+        }
+
+        let ids = [
+            "record_file_size",
+            "record_file_duration",
+            "record_max_files",
+        ];
+        match ids
+            .iter()
+            .find(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            Some(id) => Err(format!("--{} requires --record", id.replace('_', "-"))),
+            None => Ok(()),
+        }
     }
 
-    fn create_capture_filter(&self) -> String {
+    /// Takes the options that weren't given on the command line from the profile.
+    fn apply_profile(&mut self, profile: Profile, matches: &ArgMatches) -> Result<(), String> {
+        if let Some(speed) = profile.replay {
+            check_replay_speed(speed)?;
+        }
+        if profile.record_file_size == Some(0) || profile.record_file_duration == Some(0) {
+            return Err("record-file-size and record-file-duration must be positive".to_string());
+        }
+        let user = parse_users(profile.user)?;
+        let decode_as = parse_decode_rules(profile.decode_as)?;
+        let read_only_user = parse_users(profile.read_only_user)?;
+
+        let m = matches;
+        set_from_profile(m, "files", &mut self.files, profile.files);
+        set_from_profile(m, "merge", &mut self.merge, profile.merge);
+        set_from_profile(m, "interfaces", &mut self.interfaces, profile.interfaces);
+        set_from_profile(m, "sockets", &mut self.sockets, profile.sockets);
+        set_from_profile(m, "capture", &mut self.capture, profile.capture);
+        set_from_profile(m, "decode_as", &mut self.decode_as, decode_as);
+        set_from_profile(
+            m,
+            "decode_rules_file",
+            &mut self.decode_rules_file,
+            profile.decode_rules_file.map(Some),
+        );
+        set_from_profile(m, "address", &mut self.address, profile.address);
+        set_from_profile(m, "port", &mut self.port, profile.port);
+        set_from_profile(m, "promisc", &mut self.promisc, profile.promisc);
+        set_from_profile(m, "buffer_size", &mut self.buffer_size, profile.buffer_size);
+        set_from_profile(
+            m,
+            "message_interval",
+            &mut self.message_interval,
+            profile.message_interval,
+        );
+        set_from_profile(
+            m,
+            "maximum_package_age",
+            &mut self.maximum_package_age,
+            profile.maximum_package_age,
+        );
+        set_from_profile(
+            m,
+            "client_queue_size",
+            &mut self.client_queue_size,
+            profile.client_queue_size,
+        );
+        set_from_profile(m, "replay", &mut self.replay, profile.replay.map(Some));
+        set_from_profile(m, "record", &mut self.record, profile.record.map(Some));
+        set_from_profile(
+            m,
+            "record_file_size",
+            &mut self.record_file_size,
+            profile.record_file_size,
+        );
+        set_from_profile(
+            m,
+            "record_file_duration",
+            &mut self.record_file_duration,
+            profile.record_file_duration,
+        );
+        set_from_profile(
+            m,
+            "record_max_files",
+            &mut self.record_max_files,
+            profile.record_max_files,
+        );
+        set_from_profile(
+            m,
+            "upload_dir",
+            &mut self.upload_dir,
+            profile.upload_dir.map(Some),
+        );
+        set_from_profile(m, "session", &mut self.session, profile.session.map(Some));
+        set_from_profile(
+            m,
+            "tls_cert",
+            &mut self.tls_cert,
+            profile.tls_cert.map(Some),
+        );
+        set_from_profile(m, "tls_key", &mut self.tls_key, profile.tls_key.map(Some));
+        set_from_profile(m, "token", &mut self.token, profile.token);
+        set_from_profile(
+            m,
+            "read_only_token",
+            &mut self.read_only_token,
+            profile.read_only_token,
+        );
+        set_from_profile(m, "user", &mut self.user, user);
+        set_from_profile(
+            m,
+            "read_only_user",
+            &mut self.read_only_user,
+            read_only_user,
+        );
+        self.sources = profile.sources;
+
+        Ok(())
+    }
+
+    fn create_capture_filter(&self, capture: &str) -> String {
         // to filter out RTPeeker own WebSocket/HTTP messages
         let own_filter = if self.address.is_unspecified() {
             format!("not port {}", self.port)
@@ -96,41 +488,217 @@ impl Run {
             format!("not (host {} and port {})", self.address, self.port)
         };
 
-        if self.capture.is_empty() {
+        if capture.is_empty() {
             own_filter
         } else {
-            format!("({}) and ({})", own_filter, self.capture)
+            format!("({}) and ({})", own_filter, capture)
         }
     }
 }
 
-fn get_sniffers<F>(mut sources: Vec<String>, get_sniffer: F) -> HashMap<String, Sniffer>
+/// Fails only if the capture filter is invalid, as it applies to all sources,
+/// other failures are reported and the source is skipped.
+fn get_sniffers<F>(
+    mut sources: Vec<String>,
+    get_sniffer: F,
+) -> Result<HashMap<String, Sniffer>, Error>
 where
     F: Fn(&str) -> Result<Sniffer, Error>,
 {
     sources.sort_unstable();
     sources.dedup();
-    sources
-        .into_iter()
-        .filter_map(|source| match get_sniffer(&source) {
-            Ok(sniffer) => Some((source, sniffer)),
-            Err(err) => {
-                println!(
-                    "Failed to capture packets from source {}, reason: {:?}",
-                    source, err
-                );
-                None
+
+    let mut sniffers = HashMap::new();
+    for source in sources {
+        match get_sniffer(&source) {
+            Ok(sniffer) => {
+                sniffers.insert(source, sniffer);
             }
+            Err(Error::InvalidFilter) => return Err(Error::InvalidFilter),
+            Err(err) => println!(
+                "Failed to capture packets from source {}, reason: {:?}",
+                source, err
+            ),
+        }
+    }
+
+    Ok(sniffers)
+}
+
+fn default_upload_dir() -> PathBuf {
+    std::env::temp_dir().join(DEFAULT_UPLOAD_DIR)
+}
+
+/// Sets the option to the value from the profile, unless it was given on the command line.
+fn set_from_profile<T>(matches: &ArgMatches, id: &str, option: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *option = value;
+        }
+    }
+}
+
+fn parse_users(users: Option<Vec<String>>) -> Result<Option<Vec<Credentials>>, String> {
+    users
+        .map(|users| {
+            users
+                .iter()
+                .map(|user| Credentials::parse_basic(user))
+                .collect()
         })
-        .collect()
+        .transpose()
 }
 
-fn apply_filters(sniffers: &mut HashMap<String, Sniffer>, filter: &str) -> Result<(), Error> {
-    for (_, sniffer) in sniffers.iter_mut() {
-        if let err @ Err(_) = sniffer.apply_filter(filter) {
-            return err;
+fn parse_decode_rules(rules: Option<Vec<String>>) -> Result<Option<Vec<DecodeRule>>, String> {
+    rules
+        .map(|rules| {
+            rules
+                .iter()
+                .map(|rule| rule.parse().map_err(|err| format!("decode-as: {}", err)))
+                .collect()
+        })
+        .transpose()
+}
+
+fn parse_replay_speed(speed: &str) -> Result<f64, String> {
+    match speed.parse::<f64>() {
+        Ok(speed) => check_replay_speed(speed),
+        _ => Err(format!("`{}` is not a positive number", speed)),
+    }
+}
+
+fn check_replay_speed(speed: f64) -> Result<f64, String> {
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err(format!("`{}` is not a positive number", speed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Args, Command, FromArgMatches};
+
+    fn parse(args: &[&str]) -> (Run, ArgMatches) {
+        let command = Run::augment_args(Command::new("run"));
+        let matches = command
+            .try_get_matches_from(std::iter::once("run").chain(args.iter().copied()))
+            .unwrap();
+        let run = Run::from_arg_matches(&matches).unwrap();
+        (run, matches)
+    }
+
+    #[test]
+    fn profile_fills_default_options() {
+        let (mut run, matches) = parse(&[]);
+        let profile = Profile {
+            port: Some(8080),
+            buffer_size: Some(10),
+            promisc: Some(true),
+            replay: Some(2.0),
+            record: Some(PathBuf::from("recordings")),
+            decode_as: Some(vec!["5004=rtp".to_string()]),
+            ..Default::default()
+        };
+
+        run.apply_profile(profile, &matches).unwrap();
+        assert_eq!(run.port, 8080);
+        assert_eq!(run.buffer_size, 10);
+        assert!(run.promisc);
+        assert_eq!(run.replay, Some(2.0));
+        assert_eq!(run.record, Some(PathBuf::from("recordings")));
+        assert_eq!(run.decode_as, ["5004=rtp".parse().unwrap()]);
+        // not in the profile
+        assert_eq!(run.message_interval, DEFAULT_CLIENT_MESSAGE_INTERVAL_MS);
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let (mut run, matches) = parse(&["--port", "9000", "--replay", "--decode-as", "5006=rtcp"]);
+        let profile = Profile {
+            port: Some(8080),
+            buffer_size: Some(10),
+            replay: Some(2.0),
+            decode_as: Some(vec!["5004=rtp".to_string()]),
+            ..Default::default()
+        };
+
+        run.apply_profile(profile, &matches).unwrap();
+        assert_eq!(run.port, 9000);
+        assert_eq!(run.buffer_size, 10);
+        assert_eq!(run.replay, Some(1.0));
+        assert_eq!(run.decode_as, ["5006=rtcp".parse().unwrap()]);
+    }
+
+    #[test]
+    fn set_only_when_not_on_command_line() {
+        let (_, matches) = parse(&["--port", "9000"]);
+
+        let mut port = 9000;
+        set_from_profile(&matches, "port", &mut port, Some(8080));
+        assert_eq!(port, 9000);
+
+        let mut buffer_size = DEFAULT_PACKET_BUFFER_SIZE;
+        set_from_profile(&matches, "buffer_size", &mut buffer_size, Some(10));
+        assert_eq!(buffer_size, 10);
+        set_from_profile(&matches, "buffer_size", &mut buffer_size, None);
+        assert_eq!(buffer_size, 10);
+    }
+
+    #[test]
+    fn invalid_profile_options() {
+        let profiles = [
+            Profile {
+                replay: Some(0.0),
+                ..Default::default()
+            },
+            Profile {
+                record_file_size: Some(0),
+                ..Default::default()
+            },
+            Profile {
+                decode_as: Some(vec!["5004".to_string()]),
+                ..Default::default()
+            },
+            Profile {
+                user: Some(vec!["admin".to_string()]),
+                ..Default::default()
+            },
+        ];
+
+        for profile in profiles {
+            let (mut run, matches) = parse(&[]);
+            assert!(
+                run.apply_profile(profile.clone(), &matches).is_err(),
+                "{:?}",
+                profile
+            );
         }
     }
 
-    Ok(())
+    #[test]
+    fn record_options_need_recording() {
+        let (run, matches) = parse(&["--record-file-size", "5"]);
+        assert_eq!(
+            run.check_record_options(&matches),
+            Err("--record-file-size requires --record".to_string())
+        );
+
+        let (run, matches) = parse(&["--record-max-files", "5", "--record", "recordings"]);
+        assert_eq!(run.check_record_options(&matches), Ok(()));
+
+        // recording enabled by the profile
+        let (mut run, matches) = parse(&["--record-file-duration", "60"]);
+        let profile = Profile {
+            record: Some(PathBuf::from("recordings")),
+            ..Default::default()
+        };
+        run.apply_profile(profile, &matches).unwrap();
+        assert_eq!(run.check_record_options(&matches), Ok(()));
+        assert_eq!(run.record_file_duration, 60);
+
+        let (run, matches) = parse(&[]);
+        assert_eq!(run.check_record_options(&matches), Ok(()));
+    }
 }
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
-use clap::{Parser, Subcommand};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 
+mod analysis;
 mod cmd;
 mod server;
 mod sniffer;
@@ -9,8 +10,10 @@ mod sniffer;
 async fn main() {
     pretty_env_logger::init();
 
-    let cli = NetpixArgs::parse();
-    cli.run().await;
+    // the matches tell the options given on the command line from the default ones
+    let matches = NetpixArgs::command().get_matches();
+    let cli = NetpixArgs::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    cli.run(&matches).await;
 }
 
 #[derive(Debug, Parser)]
@@ -21,10 +24,12 @@ struct NetpixArgs {
 }
 
 impl NetpixArgs {
-    pub async fn run(self) {
+    pub async fn run(self, matches: &ArgMatches) {
+        let (_, matches) = matches.subcommand().expect("subcommand is required");
         match self.action {
-            NetpixSubcommands::Run(inner) => inner.run().await,
+            NetpixSubcommands::Run(inner) => inner.run(matches).await,
             NetpixSubcommands::List(inner) => inner.run().await,
+            NetpixSubcommands::Analyze(inner) => inner.run().await,
         }
     }
 }
@@ -32,8 +37,13 @@ impl NetpixArgs {
 #[derive(Debug, Subcommand)]
 enum NetpixSubcommands {
     /// Run the app. E.g "run -f rtp.pcap webex.pcap -i etn0 wireless". Obtain help with "run --help"
-    Run(cmd::run::Run),
+    Run(Box<cmd::run::Run>),
 
-    /// List network interfaces
+    /// List network interfaces, or find the ones carrying RTP, RTCP and MPEG-TS
+    /// with "list --probe 5"
     List(cmd::list::List),
+
+    /// Analyze a pcap file without starting the server and print per-stream statistics.
+    /// E.g "analyze -f rtp.pcap --format csv"
+    Analyze(cmd::analyze::Analyze),
 }
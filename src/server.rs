@@ -1,11 +1,19 @@
+mod api;
 mod asset;
+pub mod auth;
 mod client;
 pub mod config;
 mod constants;
+pub mod decode_rules;
+mod file;
 mod handler;
+mod metrics;
+pub mod session;
+mod upload;
 
 use crate::sniffer::Sniffer;
 use config::Config;
+use session::SavedSource;
 use std::collections::HashMap;
 use warp::Filter;
 
@@ -13,14 +21,15 @@ use netpix_macros::{
     run_server, setup_clients, setup_packet_handlers, setup_routes, spawn_message_sender,
 };
 
-pub async fn run(sniffers: HashMap<String, Sniffer>, config: Config) {
+pub async fn run(sniffers: HashMap<String, Sniffer>, restored: Vec<SavedSource>, config: Config) {
     let clients = setup_clients!();
-    let source_to_packets = setup_packet_handlers!((sniffers, clients, config));
+    let source_to_packets = setup_packet_handlers!((sniffers, clients, config.clone()));
+    handler::restore_sources(restored, &source_to_packets, &clients, &config).await;
     let sender_clients = clients.clone();
 
-    let routes = setup_routes!((clients, source_to_packets));
+    let routes = setup_routes!((clients, source_to_packets, config));
 
     spawn_message_sender!((sender_clients, config.client_message_interval_ms,));
 
-    run_server!((routes, config.addr));
+    run_server!((routes, config.addr, config.tls));
 }
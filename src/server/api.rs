@@ -0,0 +1,219 @@
+use super::constants::API_PATH;
+use super::handler::{PacketsMap, SourceHandle};
+use crate::analysis::Analyzer;
+use netpix_common::{Packet, PacketsStats, ReplayState, Response, Source, SourceInfo};
+use ringbuf::traits::{Consumer, Observer};
+use serde::{Deserialize, Serialize};
+use warp::{
+    http::StatusCode,
+    reply::{self, Reply},
+    Filter, Rejection,
+};
+
+const DEFAULT_PACKETS_LIMIT: usize = 1_000;
+const MAX_PACKETS_LIMIT: usize = 10_000;
+
+#[derive(Debug, Serialize)]
+struct SourceSummary {
+    /// Used to refer to the source in the other endpoints, e.g. `file:rtp.pcap`
+    id: String,
+    source: Source,
+    /// Number of packets currently kept in the buffer
+    packets: usize,
+    stats: PacketsStats,
+    info: SourceInfo,
+    replay: Option<ReplayState>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourceQuery {
+    source: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PacketsQuery {
+    source: String,
+    /// Lowest packet id, inclusive
+    from: Option<usize>,
+    /// Highest packet id, inclusive
+    to: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct PacketRange<'a> {
+    packets: Vec<ApiPacket<'a>>,
+    /// Id to pass as `from` to get the rest of the range
+    next: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiPacket<'a> {
+    #[serde(flatten)]
+    packet: &'a Packet,
+    /// File the packet was read from, for file sources
+    file: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiError {
+    error: String,
+}
+
+/// Read-only JSON endpoints for scripts and dashboards:
+/// `GET /api/sources`, `/api/stats?source=`, `/api/streams?source=`
+/// and `/api/packets?source=&from=&to=&limit=`.
+pub fn routes(
+    source_to_packets: PacketsMap,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let source_to_packets = warp::any().map(move || source_to_packets.clone());
+    let api = warp::path(API_PATH).and(warp::get());
+
+    let sources = api
+        .and(warp::path("sources"))
+        .and(warp::path::end())
+        .and(source_to_packets.clone())
+        .and_then(list_sources);
+
+    let stats = api
+        .and(warp::path("stats"))
+        .and(warp::path::end())
+        .and(warp::query::<SourceQuery>())
+        .and(source_to_packets.clone())
+        .and_then(get_stats);
+
+    let streams = api
+        .and(warp::path("streams"))
+        .and(warp::path::end())
+        .and(warp::query::<SourceQuery>())
+        .and(source_to_packets.clone())
+        .and_then(get_streams);
+
+    let packets = api
+        .and(warp::path("packets"))
+        .and(warp::path::end())
+        .and(warp::query::<PacketsQuery>())
+        .and(source_to_packets)
+        .and_then(get_packets);
+
+    sources.or(stats).or(streams).or(packets)
+}
+
+async fn list_sources(source_to_packets: PacketsMap) -> Result<reply::Response, Rejection> {
+    let handles: Vec<_> = source_to_packets
+        .read()
+        .await
+        .iter()
+        .map(|(source, handle)| (source.clone(), handle.clone()))
+        .collect();
+
+    let mut summaries = Vec::with_capacity(handles.len());
+    for (source, handle) in handles {
+        summaries.push(SourceSummary {
+            id: source_id(&source),
+            packets: handle.packets.read().await.occupied_len(),
+            stats: handle.stats.borrow().clone(),
+            info: handle.info.borrow().clone(),
+            replay: handle.replay.as_ref().map(|replay| *replay.state.borrow()),
+            source,
+        });
+    }
+    summaries.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(reply::json(&summaries).into_response())
+}
+
+async fn get_stats(
+    query: SourceQuery,
+    source_to_packets: PacketsMap,
+) -> Result<reply::Response, Rejection> {
+    let Some(handle) = find_source(&source_to_packets, &query.source).await else {
+        return Ok(source_not_found(&query.source));
+    };
+
+    let stats = handle.stats.borrow().clone();
+    Ok(reply::json(&stats).into_response())
+}
+
+async fn get_streams(
+    query: SourceQuery,
+    source_to_packets: PacketsMap,
+) -> Result<reply::Response, Rejection> {
+    let Some(handle) = find_source(&source_to_packets, &query.source).await else {
+        return Ok(source_not_found(&query.source));
+    };
+
+    // only the packets still kept in the buffer are accounted for
+    let mut analyzer = Analyzer::new();
+    for response in handle.packets.read().await.iter() {
+        if let Response::Packet(packet) = response {
+            analyzer.add_packet(packet);
+        }
+    }
+
+    let mut report = analyzer.report();
+    report.fragments = handle.stats.borrow().fragments;
+    Ok(reply::json(&report).into_response())
+}
+
+async fn get_packets(
+    query: PacketsQuery,
+    source_to_packets: PacketsMap,
+) -> Result<reply::Response, Rejection> {
+    let Some(handle) = find_source(&source_to_packets, &query.source).await else {
+        return Ok(source_not_found(&query.source));
+    };
+
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(usize::MAX);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PACKETS_LIMIT)
+        .min(MAX_PACKETS_LIMIT);
+
+    let source = handle.info.borrow().source.clone();
+    let packets = handle.packets.read().await;
+    let mut range = packets.iter().filter_map(|response| match response {
+        Response::Packet(packet) if (from..=to).contains(&packet.id) => Some(ApiPacket {
+            packet,
+            file: source.origin(packet),
+        }),
+        _ => None,
+    });
+
+    let packets = range.by_ref().take(limit).collect();
+    let next = range.next().map(|packet| packet.packet.id);
+    Ok(reply::json(&PacketRange { packets, next }).into_response())
+}
+
+async fn find_source(source_to_packets: &PacketsMap, id: &str) -> Option<SourceHandle> {
+    let source = parse_source_id(id)?;
+    source_to_packets.read().await.get(&source).cloned()
+}
+
+fn source_not_found(id: &str) -> reply::Response {
+    let error = ApiError {
+        error: format!("unknown source `{}`", id),
+    };
+    reply::with_status(reply::json(&error), StatusCode::NOT_FOUND).into_response()
+}
+
+pub(super) fn source_id(source: &Source) -> String {
+    match source {
+        Source::File(file) => format!("file:{}", file),
+        Source::Files(files) => format!("files:{}", Source::join_files(files)),
+        Source::Interface(interface) => format!("interface:{}", interface),
+        Source::Socket(address) => format!("socket:{}", address),
+    }
+}
+
+fn parse_source_id(id: &str) -> Option<Source> {
+    let (kind, name) = id.split_once(':')?;
+    match kind {
+        "file" => Some(Source::File(name.to_string())),
+        "files" => Some(Source::Files(Source::split_files(name))),
+        "interface" => Some(Source::Interface(name.to_string())),
+        "socket" => Some(Source::Socket(name.to_string())),
+        _ => None,
+    }
+}
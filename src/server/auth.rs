@@ -0,0 +1,339 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use netpix_common::Request;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use std::sync::Arc;
+use warp::{
+    http::{header, StatusCode},
+    reject::Reject,
+    reply::{self, Reply},
+    Filter, Rejection,
+};
+
+/// Remembers the token passed in the query of the page, so that the assets
+/// and the WebSocket opened by the client are authorized as well.
+const TOKEN_COOKIE: &str = "netpix_token";
+const REALM: &str = "netpix";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Can only watch the sources, without changing them for everyone else.
+    ReadOnly,
+    Full,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// Passed as a bearer token, in the `token` query parameter or in the cookie set from it
+    Token(String),
+    /// HTTP basic authentication, which browsers ask for on their own
+    Basic { user: String, password: String },
+}
+
+/// Credentials accepted by the server; everyone has full access if there are none.
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    credentials: Vec<(Credentials, Role)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    token: Option<String>,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+#[derive(Debug)]
+struct Forbidden;
+
+impl Reject for Unauthorized {}
+
+impl Reject for Forbidden {}
+
+impl Role {
+    /// Whether the request may be sent over the WebSocket by a client of this role.
+    pub fn allows(&self, request: &Request) -> bool {
+        match self {
+            Self::Full => true,
+            Self::ReadOnly => matches!(
+                request,
+                Request::FetchRange { .. }
+                    | Request::FetchTimeRange { .. }
+                    | Request::ChangeSource(_)
+                    | Request::Subscribe(_)
+            ),
+        }
+    }
+}
+
+impl Credentials {
+    /// Parses `USER:PASSWORD`.
+    pub fn parse_basic(credentials: &str) -> Result<Self, String> {
+        match credentials.split_once(':') {
+            Some((user, password)) if !user.is_empty() => Ok(Self::Basic {
+                user: user.to_string(),
+                password: password.to_string(),
+            }),
+            _ => Err(format!(
+                "`{}` is not in the USER:PASSWORD format",
+                credentials
+            )),
+        }
+    }
+}
+
+impl Auth {
+    pub fn allow(&mut self, credentials: Credentials, role: Role) {
+        self.credentials.push((credentials, role));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.credentials.is_empty()
+    }
+
+    fn role(&self, presented: &[Credentials]) -> Option<Role> {
+        if !self.is_enabled() {
+            return Some(Role::Full);
+        }
+
+        self.credentials
+            .iter()
+            .filter(|(credentials, _)| presented.iter().any(|other| matches(credentials, other)))
+            .map(|(_, role)| *role)
+            .max_by_key(|role| *role == Role::Full)
+    }
+}
+
+/// Extracts the role of the request, which is rejected if its credentials don't match any of the accepted ones.
+pub fn authenticate(auth: Arc<Auth>) -> impl Filter<Extract = (Role,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(header::AUTHORIZATION.as_str())
+        .and(warp::cookie::optional::<String>(TOKEN_COOKIE))
+        .and(warp::query::<TokenQuery>())
+        .and_then(
+            move |authorization: Option<String>, cookie: Option<String>, query: TokenQuery| {
+                let auth = auth.clone();
+                async move {
+                    let mut presented = Vec::new();
+                    presented.extend(authorization.as_deref().and_then(parse_authorization));
+                    presented.extend(query.token.map(Credentials::Token));
+                    presented.extend(cookie.as_deref().and_then(parse_cookie));
+
+                    auth.role(&presented)
+                        .ok_or_else(|| warp::reject::custom(Unauthorized))
+                }
+            },
+        )
+}
+
+/// Like [`authenticate`], for the routes available to every role.
+pub fn restrict(auth: Arc<Auth>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authenticate(auth).map(|_| ()).untuple_one()
+}
+
+/// Like [`authenticate`], for the routes available only with full access.
+pub fn require_full_access(
+    auth: Arc<Auth>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authenticate(auth)
+        .and_then(|role| async move {
+            match role {
+                Role::Full => Ok(()),
+                Role::ReadOnly => Err(warp::reject::custom(Forbidden)),
+            }
+        })
+        .untuple_one()
+}
+
+/// Stores the token from the query in a cookie sent with the following requests.
+pub fn remember_token(query: TokenQuery, reply: impl Reply) -> reply::Response {
+    let mut response = reply.into_response();
+    if let Some(token) = query.token {
+        let token = utf8_percent_encode(&token, NON_ALPHANUMERIC);
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict",
+            TOKEN_COOKIE, token
+        );
+        if let Ok(cookie) = cookie.parse() {
+            response.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+/// Asks the browser for credentials when the request wasn't authorized.
+pub async fn handle_rejection(err: Rejection) -> Result<reply::Response, Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        let reply = reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED);
+        let challenge = format!("Basic realm=\"{}\"", REALM);
+        return Ok(reply::with_header(reply, header::WWW_AUTHENTICATE, challenge).into_response());
+    }
+
+    if err.find::<Forbidden>().is_some() {
+        let reply = reply::with_status("Read-only access", StatusCode::FORBIDDEN);
+        return Ok(reply.into_response());
+    }
+
+    Err(err)
+}
+
+fn parse_authorization(authorization: &str) -> Option<Credentials> {
+    let (scheme, value) = authorization.trim().split_once(' ')?;
+    let value = value.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(Credentials::Token(value.to_string()));
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(value).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    Credentials::parse_basic(&decoded).ok()
+}
+
+fn parse_cookie(cookie: &str) -> Option<Credentials> {
+    let token = percent_decode_str(cookie).decode_utf8().ok()?;
+    Some(Credentials::Token(token.into_owned()))
+}
+
+fn matches(expected: &Credentials, presented: &Credentials) -> bool {
+    match (expected, presented) {
+        (Credentials::Token(expected), Credentials::Token(presented)) => {
+            constant_time_eq(expected.as_bytes(), presented.as_bytes())
+        }
+        (
+            Credentials::Basic { user, password },
+            Credentials::Basic {
+                user: presented_user,
+                password: presented_password,
+            },
+        ) => {
+            // both compared, so that the time doesn't tell which one is wrong
+            let user_matches = constant_time_eq(user.as_bytes(), presented_user.as_bytes());
+            let password_matches =
+                constant_time_eq(password.as_bytes(), presented_password.as_bytes());
+            user_matches & password_matches
+        }
+        _ => false,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn token(token: &str) -> Credentials {
+        Credentials::Token(token.to_string())
+    }
+
+    fn basic(user: &str, password: &str) -> Credentials {
+        Credentials::Basic {
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn authorization_headers() {
+        assert_eq!(parse_authorization("Bearer abc"), Some(token("abc")));
+        assert_eq!(parse_authorization("bearer  abc "), Some(token("abc")));
+
+        let header = format!("Basic {}", STANDARD.encode("user:pass:word"));
+        assert_eq!(
+            parse_authorization(&header),
+            Some(basic("user", "pass:word"))
+        );
+
+        assert_eq!(parse_authorization("Basic not-base64!"), None);
+        let header = format!("Basic {}", STANDARD.encode("user"));
+        assert_eq!(parse_authorization(&header), None);
+        let header = format!("Basic {}", STANDARD.encode(":password"));
+        assert_eq!(parse_authorization(&header), None);
+        assert_eq!(parse_authorization("Digest abc"), None);
+        assert_eq!(parse_authorization("Bearer"), None);
+    }
+
+    #[test]
+    fn cookies() {
+        assert_eq!(parse_cookie("abc"), Some(token("abc")));
+        assert_eq!(parse_cookie("a%20b%3Bc"), Some(token("a b;c")));
+        assert_eq!(parse_cookie("%FF"), None);
+    }
+
+    #[test]
+    fn matching_credentials() {
+        assert!(matches(&token("abc"), &token("abc")));
+        assert!(!matches(&token("abc"), &token("abd")));
+        assert!(!matches(&token("abc"), &token("abcd")));
+        assert!(matches(
+            &basic("user", "password"),
+            &basic("user", "password")
+        ));
+        assert!(!matches(
+            &basic("user", "password"),
+            &basic("user", "other")
+        ));
+        assert!(!matches(
+            &basic("user", "password"),
+            &basic("other", "password")
+        ));
+        assert!(!matches(
+            &token("user:password"),
+            &basic("user", "password")
+        ));
+
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn roles() {
+        let auth = Auth::default();
+        assert_eq!(auth.role(&[]), Some(Role::Full));
+        assert_eq!(auth.role(&[token("anything")]), Some(Role::Full));
+
+        let mut auth = Auth::default();
+        auth.allow(token("viewer"), Role::ReadOnly);
+        auth.allow(basic("admin", "secret"), Role::Full);
+
+        assert_eq!(auth.role(&[]), None);
+        assert_eq!(auth.role(&[token("wrong")]), None);
+        assert_eq!(auth.role(&[basic("admin", "wrong")]), None);
+        assert_eq!(auth.role(&[token("viewer")]), Some(Role::ReadOnly));
+        assert_eq!(auth.role(&[basic("admin", "secret")]), Some(Role::Full));
+
+        // e.g. a read-only token in the cookie and a full user in the header
+        let presented = [token("viewer"), basic("admin", "secret")];
+        assert_eq!(auth.role(&presented), Some(Role::Full));
+    }
+
+    #[test]
+    fn allowed_requests() {
+        let fetch = Request::FetchRange {
+            from_id: 0,
+            to_id: 10,
+        };
+        assert!(Role::ReadOnly.allows(&fetch));
+        assert!(Role::Full.allows(&fetch));
+
+        for request in [
+            Request::PauseReplay,
+            Request::SeekReplay(Duration::from_secs(1)),
+            Request::AddFile("capture.pcap".to_string()),
+            Request::SetDecodeRules(Vec::new()),
+        ] {
+            assert!(!Role::ReadOnly.allows(&request), "{:?}", request);
+            assert!(Role::Full.allows(&request), "{:?}", request);
+        }
+    }
+}
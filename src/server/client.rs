@@ -1,37 +1,75 @@
-use crate::server::handler::{handle_messages, send_pcap_filenames};
+use crate::server::handler::{
+    handle_messages, send_decode_rules, send_pcap_filenames, send_session_view,
+};
 
+use super::auth::Role;
+use super::config::Config;
+use super::constants::{MAX_BATCHES_IN_FLIGHT, MAX_BATCH_SIZE};
 use super::handler::PacketsMap;
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
 use log::{error, info};
-use netpix_common::Source;
+use netpix_common::{Response, Source, Subscription};
 use std::collections::HashMap;
-use std::collections::VecDeque;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc,
 };
 use tokio::sync::{
-    mpsc::{self, UnboundedSender},
+    mpsc::{self, Sender},
     RwLock,
 };
 use warp::ws::{Message, WebSocket};
 
+mod queue;
+
+pub use queue::{Encoded, SendQueue};
+
 static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
 
 pub struct Client {
-    pub sender: UnboundedSender<Message>,
+    sender: Sender<Message>,
     pub source: Option<Source>,
-    pub queue: VecDeque<Message>, // Messages waiting to be sent
+    pub subscription: Subscription,
+    pub queue: SendQueue,
 }
 
 impl Client {
-    pub fn new(sender: UnboundedSender<Message>) -> Self {
+    pub fn new(sender: Sender<Message>, queue_size: usize) -> Self {
         Self {
             sender,
             source: None,
-            queue: VecDeque::new(),
+            subscription: Subscription::default(),
+            queue: SendQueue::new(queue_size),
         }
     }
+
+    /// Whether the response of `source` should be forwarded to the client.
+    pub fn is_subscribed(&self, source: &Source, response: &Response) -> bool {
+        self.source.as_ref() == Some(source) && self.subscription.matches_response(response)
+    }
+
+    /// Sends the queued responses as a single message, unless the client
+    /// is still busy with the previous ones, in which case they keep waiting.
+    pub fn flush(&mut self, client_id: usize) {
+        let Ok(permit) = self.sender.try_reserve() else {
+            return;
+        };
+
+        let mut batch = self.queue.next_batch(MAX_BATCH_SIZE);
+        let encoded = match batch.len() {
+            0 => return,
+            1 => batch.pop().unwrap().to_vec(),
+            _ => match Response::batch(&batch).and_then(|batch| batch.encode()) {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    error!("Failed to encode batch: {}, client_id: {}", e, client_id);
+                    return;
+                }
+            },
+        };
+
+        permit.send(Message::binary(encoded));
+    }
 }
 
 pub type Clients = Arc<RwLock<HashMap<usize, Client>>>;
@@ -40,16 +78,28 @@ pub fn new_clients() -> Clients {
     Clients::default()
 }
 
-pub async fn handle_connection(ws: WebSocket, clients: Clients, packets: PacketsMap) {
+pub async fn handle_connection(
+    ws: WebSocket,
+    role: Role,
+    clients: Clients,
+    packets: PacketsMap,
+    config: Config,
+) {
     let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
 
-    info!("New client connected, assigned id: {}", client_id);
+    info!(
+        "New client connected, assigned id: {}, role: {:?}",
+        client_id, role
+    );
 
     let (mut ws_tx, ws_rx) = ws.split();
 
+    // the view is applied before the sources, as they make the client switch to its source
+    send_session_view(&client_id, &mut ws_tx, &config).await;
+    send_decode_rules(&client_id, &mut ws_tx, &config).await;
     send_pcap_filenames(&client_id, &mut ws_tx, &packets).await;
 
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (tx, mut rx) = mpsc::channel(MAX_BATCHES_IN_FLIGHT);
 
     tokio::task::spawn(async move {
         while let Some(message) = rx.recv().await {
@@ -62,9 +112,10 @@ pub async fn handle_connection(ws: WebSocket, clients: Clients, packets: Packets
         }
     });
 
-    clients.write().await.insert(client_id, Client::new(tx));
+    let client = Client::new(tx, config.client_queue_size);
+    clients.write().await.insert(client_id, client);
 
-    handle_messages(client_id, ws_rx, &clients, &packets).await;
+    handle_messages(client_id, role, ws_rx, &clients, &packets, &config).await;
 
     info!("Client disconnected, client_id: {}", client_id);
     clients.write().await.remove(&client_id);
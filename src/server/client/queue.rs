@@ -0,0 +1,77 @@
+use log::error;
+use netpix_common::Response;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Response encoded once and shared by the queues of all the clients it's sent to.
+pub type Encoded = Arc<[u8]>;
+
+#[derive(Debug)]
+enum Queued {
+    Packet(Encoded),
+    Other(Encoded),
+}
+
+/// Responses waiting to be sent to a client.
+///
+/// Only packets count towards the capacity; they are dropped once it's reached,
+/// while the rest of the responses, e.g. replay states, always get through.
+#[derive(Debug)]
+pub struct SendQueue {
+    responses: VecDeque<Queued>,
+    packets: usize,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            responses: VecDeque::new(),
+            packets: 0,
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, encoded: Encoded) {
+        self.responses.push_back(Queued::Other(encoded));
+    }
+
+    pub fn push_packet(&mut self, encoded: Encoded) {
+        if self.packets >= self.capacity {
+            self.dropped += 1;
+            return;
+        }
+
+        self.packets += 1;
+        self.responses.push_back(Queued::Packet(encoded));
+    }
+
+    /// Takes up to `max` responses from the front of the queue,
+    /// preceded by a notice if any packets were dropped since the previous batch.
+    pub fn next_batch(&mut self, max: usize) -> Vec<Encoded> {
+        let mut batch = Vec::new();
+
+        if self.dropped > 0 {
+            match Response::Dropped(self.dropped).encode() {
+                Ok(encoded) => batch.push(encoded.into()),
+                Err(e) => error!("Failed to encode dropped packets notice: {}", e),
+            }
+            self.dropped = 0;
+        }
+
+        while batch.len() < max {
+            match self.responses.pop_front() {
+                Some(Queued::Packet(encoded)) => {
+                    self.packets -= 1;
+                    batch.push(encoded);
+                }
+                Some(Queued::Other(encoded)) => batch.push(encoded),
+                None => break,
+            }
+        }
+
+        batch
+    }
+}
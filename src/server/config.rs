@@ -1,10 +1,184 @@
+use super::auth::Auth;
+use crate::sniffer::SourceOptions;
 use bon::Builder;
+use netpix_common::{DecodeRule, SessionView};
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::watch;
 
-#[derive(Debug, Builder, Clone, Copy)]
+/// Rules deciding the protocols of the captured packets, changed by the clients.
+pub type DecodeRules = Arc<watch::Sender<Vec<DecodeRule>>>;
+
+#[derive(Debug, Builder, Clone)]
 pub struct Config {
     pub max_packets_age: u64,
     pub client_message_interval_ms: u64,
+    /// Packets kept for a client that can't keep up before new ones are dropped
+    pub client_queue_size: usize,
     pub packet_buffer_size: usize,
     pub addr: SocketAddr,
+    /// Used to open the sources added while the server is running
+    pub sources: SourceOptions,
+    /// Options of particular sources, by file path, interface name or socket address
+    #[builder(default)]
+    pub overrides: HashMap<String, SourceOverride>,
+    pub upload_dir: PathBuf,
+    /// Session file written on the clients' requests
+    pub session: PathBuf,
+    /// View of the session the server was started with, if any
+    pub session_view: Option<SessionView>,
+    pub decode_rules: DecodeRules,
+    /// File the decode rules set by the clients are written to
+    pub decode_rules_file: PathBuf,
+    pub auth: Arc<Auth>,
+    /// Serves the UI over HTTPS instead of HTTP
+    pub tls: Option<Tls>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceOverride {
+    pub options: SourceOptions,
+    pub packet_buffer_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Config {
+    pub fn source_options(&self, name: &str) -> &SourceOptions {
+        self.overrides
+            .get(name)
+            .map_or(&self.sources, |source| &source.options)
+    }
+
+    pub fn packet_buffer_size(&self, name: &str) -> usize {
+        self.overrides
+            .get(name)
+            .map_or(self.packet_buffer_size, |source| source.packet_buffer_size)
+    }
+
+    /// The file if it's in the upload directory, once the links and `..` are resolved.
+    pub fn uploaded_file(&self, file: &str) -> Option<PathBuf> {
+        let dir = self.upload_dir.canonicalize().ok()?;
+        let path = Path::new(file).canonicalize().ok()?;
+        path.starts_with(dir).then_some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::file::temp_path;
+    use std::fs;
+
+    fn config(upload_dir: PathBuf) -> Config {
+        Config::builder()
+            .max_packets_age(0)
+            .client_message_interval_ms(0)
+            .client_queue_size(0)
+            .packet_buffer_size(0)
+            .addr("127.0.0.1:3550".parse().unwrap())
+            .sources(SourceOptions::builder().build())
+            .upload_dir(upload_dir)
+            .session(PathBuf::from("session.netpix"))
+            .decode_rules(Arc::new(watch::Sender::new(Vec::new())))
+            .decode_rules_file(PathBuf::from("decode-rules.txt"))
+            .auth(Arc::new(Auth::default()))
+            .build()
+    }
+
+    /// A directory with `uploads/capture.pcap` and `other/secret.pcap`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = temp_path(name);
+        let _ = fs::remove_dir_all(&dir);
+        for file in ["uploads/capture.pcap", "other/secret.pcap"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    fn uploaded_file(config: &Config, path: PathBuf) -> Option<PathBuf> {
+        config.uploaded_file(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn files_of_the_upload_directory() {
+        let dir = temp_dir("config-uploaded");
+        let config = config(dir.join("uploads"));
+        let expected = dir.join("uploads/capture.pcap").canonicalize().unwrap();
+
+        let path = dir.join("uploads/capture.pcap");
+        assert_eq!(uploaded_file(&config, path), Some(expected.clone()));
+        let path = dir.join("other/../uploads/capture.pcap");
+        assert_eq!(uploaded_file(&config, path), Some(expected));
+
+        assert_eq!(
+            uploaded_file(&config, dir.join("uploads/missing.pcap")),
+            None
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_outside_the_upload_directory() {
+        let dir = temp_dir("config-outside");
+        let config = config(dir.join("uploads"));
+
+        let path = dir.join("uploads/../other/secret.pcap");
+        assert_eq!(uploaded_file(&config, path), None);
+        assert_eq!(uploaded_file(&config, dir.join("other/secret.pcap")), None);
+        // a directory whose name merely starts with the upload directory's
+        fs::create_dir(dir.join("uploads-2")).unwrap();
+        fs::write(dir.join("uploads-2/capture.pcap"), b"").unwrap();
+        assert_eq!(
+            uploaded_file(&config, dir.join("uploads-2/capture.pcap")),
+            None
+        );
+        assert_eq!(config.uploaded_file("capture.pcap"), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_resolved() {
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir("config-symlinks");
+        let config = config(dir.join("uploads"));
+
+        symlink(dir.join("other/secret.pcap"), dir.join("uploads/link.pcap")).unwrap();
+        assert_eq!(uploaded_file(&config, dir.join("uploads/link.pcap")), None);
+
+        symlink(
+            dir.join("uploads/capture.pcap"),
+            dir.join("other/link.pcap"),
+        )
+        .unwrap();
+        let expected = dir.join("uploads/capture.pcap").canonicalize().unwrap();
+        assert_eq!(
+            uploaded_file(&config, dir.join("other/link.pcap")),
+            Some(expected)
+        );
+
+        // the upload directory itself may be a link
+        symlink(dir.join("uploads"), dir.join("linked-uploads")).unwrap();
+        let config = self::config(dir.join("linked-uploads"));
+        let expected = dir.join("uploads/capture.pcap").canonicalize().unwrap();
+        assert_eq!(
+            uploaded_file(&config, dir.join("uploads/capture.pcap")),
+            Some(expected)
+        );
+        assert_eq!(uploaded_file(&config, dir.join("other/secret.pcap")), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1 +1,17 @@
+use std::time::Duration;
+
 pub const WEBSOCKET_PATH: &str = "ws";
+pub const UPLOAD_PATH: &str = "upload";
+pub const API_PATH: &str = "api";
+pub const METRICS_PATH: &str = "metrics";
+pub const MAX_UPLOAD_SIZE: u64 = 512 * 1024 * 1024;
+/// Responses coalesced into a single message sent to a client
+pub const MAX_BATCH_SIZE: usize = 1024;
+/// Messages handed over to a client's socket before the rest are held back in its queue
+pub const MAX_BATCHES_IN_FLIGHT: usize = 4;
+/// Packets sent at most for a fetched range, the older ones are fetched page by page
+pub const HISTORY_PAGE_SIZE: usize = 2048;
+/// How often libpcap is asked for the packets dropped by the capture of an interface
+pub const CAPTURE_STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the packets recorded by the sniffers are written out to their files
+pub const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
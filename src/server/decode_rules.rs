@@ -0,0 +1,80 @@
+use super::file::write_atomically;
+use netpix_common::DecodeRule;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Reads the rules written by [`write`], one `TARGET=PROTOCOL` per line,
+/// skipping the empty lines and the ones starting with `#`.
+pub fn read(path: &Path) -> io::Result<Vec<DecodeRule>> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            line.parse().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number, err),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Replaces the rules of the file, see [`write_atomically`].
+pub fn write(path: &Path, rules: &[DecodeRule]) -> io::Result<()> {
+    write_atomically(path, |file| {
+        writeln!(
+            file,
+            "# decode rules set from the netpix UI, one TARGET=PROTOCOL per line"
+        )?;
+        for rule in rules {
+            writeln!(file, "{}", rule)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::file::temp_path;
+
+    #[test]
+    fn written_rules_read_back() {
+        let path = temp_path("rules.txt");
+        let rules: Vec<DecodeRule> = ["5004=rtp", "5000-5010=rtcp", "[::1]:5004=mpeg-ts"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+
+        write(&path, &rules).unwrap();
+        let read = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, rules);
+    }
+
+    #[test]
+    fn edited_rules() {
+        let path = temp_path("edited-rules.txt");
+        fs::write(&path, "# comment\n\n  5004 = rtp  \n239.1.1.1=mpegts\n").unwrap();
+        let rules = read(&path).unwrap();
+
+        fs::write(&path, "5004=rtp\n5004=h323\n").unwrap();
+        let invalid = read(&path);
+        fs::remove_file(&path).unwrap();
+
+        let expected: Vec<DecodeRule> = ["5004=rtp", "239.1.1.1=mpegts"]
+            .iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect();
+        assert_eq!(rules, expected);
+
+        let err = invalid.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    }
+}
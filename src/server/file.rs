@@ -0,0 +1,32 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// Writes the file next to `path` first, so that the previous one
+/// is replaced only once the new one is complete.
+pub fn write_atomically<E>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), E>,
+) -> Result<(), E>
+where
+    E: From<io::Error>,
+{
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    write(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Path in the temporary directory unique to the test process.
+#[cfg(test)]
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("netpix-{}-{}", std::process::id(), name))
+}
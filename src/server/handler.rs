@@ -1,77 +1,460 @@
-use super::{client::Clients, config::Config};
-use crate::sniffer::Sniffer;
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use super::{
+    auth::Role,
+    client::{Clients, Encoded},
+    config::Config,
+    constants::{CAPTURE_STATS_INTERVAL, HISTORY_PAGE_SIZE, RECORDING_FLUSH_INTERVAL},
+    decode_rules,
+    session::{SavedSource, Session},
+};
+use crate::analysis::Analyzer;
+use crate::sniffer::{self, ReplayCommand, Sniffer, SourceOptions};
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt, TryFutureExt,
 };
 use log::{error, info, warn};
-use netpix_common::{PacketsStats, Request, Response, RtpStreamKey, Sdp, Source};
+use netpix_common::{
+    packet::SessionProtocol, rtsp::RtspTracker, sip::CallTracker, CaptureStats, DecodeRule, Packet,
+    PacketsStats, ReplayState, Request, Response, RtpStreamKey, Sdp, SessionView, Source,
+    SourceInfo,
+};
 use ringbuf::{
     traits::{Consumer, Observer, RingBuffer},
     HeapRb,
 };
+use std::collections::{hash_map::Entry, HashMap};
+use std::sync::Arc;
 use std::time::SystemTime;
-use std::{collections::HashMap, io::Write, sync::Arc};
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch, RwLock,
+};
+use tokio::task::AbortHandle;
 use warp::ws::{Message, WebSocket};
 
 pub type PacketRingBuffer = HeapRb<Response>;
 pub type Packets = Arc<RwLock<PacketRingBuffer>>;
-pub type PacketsMap = Arc<HashMap<Source, Packets>>;
+pub type SharedAnalyzer = Arc<RwLock<Analyzer>>;
+pub type PacketsMap = Arc<RwLock<HashMap<Source, SourceHandle>>>;
+pub type Sdps = Arc<RwLock<HashMap<RtpStreamKey, Sdp>>>;
+
+#[derive(Clone)]
+pub struct SourceHandle {
+    pub packets: Packets,
+    pub replay: Option<ReplayHandle>,
+    /// Latest statistics of the source, updated by its `sniff` task
+    pub stats: watch::Receiver<PacketsStats>,
+    /// How the source was opened along with its counters, updated by its `sniff` task
+    pub info: watch::Receiver<SourceInfo>,
+    /// Analysis of all the packets of the source, not only the buffered ones
+    pub analyzer: SharedAnalyzer,
+    /// SDPs assigned to the RTP streams, sent to the clients switching to the source
+    pub sdps: Sdps,
+    /// The file was stored by the upload route, and is removed along with the source
+    pub uploaded: bool,
+    // the `sniff` task, stopped when the source is removed
+    task: AbortHandle,
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Unavailable(sniffer::Error),
+    AlreadyAdded,
+}
+
+/// Used to control a replayed file source, the commands are handled by its `sniff` task.
+#[derive(Clone)]
+pub struct ReplayHandle {
+    pub commands: UnboundedSender<ReplayCommand>,
+    pub state: watch::Receiver<ReplayState>,
+}
+
+struct ReplayControl {
+    commands: UnboundedReceiver<ReplayCommand>,
+    state: watch::Sender<ReplayState>,
+}
+
+// the ends of the channels held by the `sniff` task of a source, see `SourceHandle`
+struct SourceChannels {
+    replay: Option<ReplayControl>,
+    stats: watch::Sender<PacketsStats>,
+    info: watch::Sender<SourceInfo>,
+    analyzer: SharedAnalyzer,
+    sdps: Sdps,
+}
+
+fn replay_channels(state: ReplayState) -> (ReplayHandle, ReplayControl) {
+    let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+    let (state_tx, state_rx) = watch::channel(state);
+
+    let handle = ReplayHandle {
+        commands: commands_tx,
+        state: state_rx,
+    };
+    let control = ReplayControl {
+        commands: commands_rx,
+        state: state_tx,
+    };
+
+    (handle, control)
+}
 
 pub async fn setup_packet_handlers(
     sniffers: HashMap<String, Sniffer>,
     clients: Clients,
     config: Config,
 ) -> PacketsMap {
-    let mut source_to_packets = HashMap::new();
+    let source_to_packets = PacketsMap::default();
 
-    for (_file, sniffer) in sniffers {
-        let packets = Arc::new(RwLock::new(HeapRb::new(config.packet_buffer_size)));
-        source_to_packets.insert(sniffer.source.clone(), packets.clone());
+    for (name, sniffer) in sniffers {
+        let buffer_size = config.packet_buffer_size(&name);
+        let added = add_source(
+            sniffer,
+            buffer_size,
+            false,
+            &source_to_packets,
+            &clients,
+            &config,
+        )
+        .await;
+        if let Err(err) = added {
+            warn!("Failed to add source: {:?}", err);
+        }
+    }
+
+    source_to_packets
+}
 
-        let cloned_clients = clients.clone();
-        tokio::task::spawn(async move {
-            sniff(sniffer, packets, cloned_clients, config).await;
-        });
+/// Starts sniffing the source, the clients have to be notified with `broadcast_sources`.
+async fn add_source(
+    sniffer: Sniffer,
+    buffer_size: usize,
+    uploaded: bool,
+    source_to_packets: &PacketsMap,
+    clients: &Clients,
+    config: &Config,
+) -> Result<Source, SourceError> {
+    let mut source_to_packets = source_to_packets.write().await;
+    if source_to_packets.contains_key(&sniffer.source) {
+        return Err(SourceError::AlreadyAdded);
     }
 
-    Arc::new(source_to_packets)
+    let source = sniffer.source.clone();
+    let packets = Arc::new(RwLock::new(HeapRb::new(buffer_size)));
+    let (replay, replay_control) = sniffer.replay_state().map(replay_channels).unzip();
+    let (stats_tx, stats) = watch::channel(PacketsStats::default());
+    let (info_tx, info) = watch::channel(sniffer.info());
+    let analyzer = SharedAnalyzer::default();
+    let sdps = Sdps::default();
+
+    let channels = SourceChannels {
+        replay: replay_control,
+        stats: stats_tx,
+        info: info_tx,
+        analyzer: analyzer.clone(),
+        sdps: sdps.clone(),
+    };
+    let cloned_packets = packets.clone();
+    let cloned_clients = clients.clone();
+    let cloned_config = config.clone();
+    let task = tokio::task::spawn(async move {
+        sniff(
+            sniffer,
+            cloned_packets,
+            cloned_clients,
+            cloned_config,
+            channels,
+        )
+        .await;
+    });
+
+    let handle = SourceHandle {
+        packets,
+        replay,
+        stats,
+        info,
+        analyzer,
+        sdps,
+        uploaded,
+        task: task.abort_handle(),
+    };
+    source_to_packets.insert(source.clone(), handle);
+
+    Ok(source)
+}
+
+/// Adds the sources of the session the server was started with.
+pub async fn restore_sources(
+    restored: Vec<SavedSource>,
+    source_to_packets: &PacketsMap,
+    clients: &Clients,
+    config: &Config,
+) {
+    for saved in restored {
+        let count = saved.packets.len();
+        let sniffer = Sniffer::from_packets(saved.source, saved.packets);
+        let added = add_source(
+            sniffer,
+            saved.buffer_size,
+            false,
+            source_to_packets,
+            clients,
+            config,
+        )
+        .await;
+
+        match added {
+            Ok(source) => {
+                if let Some(handle) = get_source(source_to_packets, &source).await {
+                    handle.sdps.write().await.extend(saved.sdps);
+                }
+                info!("Restored source {:?} with {} packets", source, count);
+            }
+            Err(err) => warn!("Failed to restore source: {:?}", err),
+        }
+    }
+}
+
+/// Opens a source with the server's options for its file path, interface name or socket address
+/// and notifies the clients about it. See [`SourceHandle::uploaded`].
+pub async fn open_source<F>(
+    name: &str,
+    open: F,
+    uploaded: bool,
+    source_to_packets: &PacketsMap,
+    clients: &Clients,
+    config: &Config,
+) -> Result<Source, SourceError>
+where
+    F: FnOnce(&SourceOptions) -> Result<Sniffer, sniffer::Error> + Send + 'static,
+{
+    let options = config.source_options(name).clone();
+    // opening a replayed file reads it whole
+    let sniffer = tokio::task::spawn_blocking(move || open(&options))
+        .await
+        .expect("opening a source shouldn't panic")
+        .map_err(SourceError::Unavailable)?;
+
+    let buffer_size = config.packet_buffer_size(name);
+    let source = add_source(
+        sniffer,
+        buffer_size,
+        uploaded,
+        source_to_packets,
+        clients,
+        config,
+    )
+    .await?;
+    info!("Added source {:?}", source);
+    broadcast_sources(source_to_packets, clients).await;
+
+    Ok(source)
+}
+
+pub async fn remove_source(
+    source: &Source,
+    source_to_packets: &PacketsMap,
+    clients: &Clients,
+) -> bool {
+    let Some(handle) = source_to_packets.write().await.remove(source) else {
+        return false;
+    };
+    handle.task.abort();
+
+    for (_, client) in clients.write().await.iter_mut() {
+        if client.source.as_ref() == Some(source) {
+            client.source = None;
+        }
+    }
+
+    // uploaded files aren't of any use once their source is gone
+    if let (true, Source::File(file)) = (handle.uploaded, source) {
+        if let Err(err) = tokio::fs::remove_file(file).await {
+            warn!("Failed to remove uploaded file {}: {}", file, err);
+        }
+    }
+
+    info!("Removed source {:?}", source);
+    broadcast_sources(source_to_packets, clients).await;
+
+    true
+}
+
+/// The list of the sources followed by the info of each of them.
+async fn sources_responses(source_to_packets: &PacketsMap) -> Vec<Response> {
+    let source_to_packets = source_to_packets.read().await;
+    let sources = source_to_packets.keys().cloned().collect();
+    let infos = source_to_packets
+        .values()
+        .map(|handle| Response::SourceInfo(handle.info.borrow().clone()));
+
+    std::iter::once(Response::Sources(sources))
+        .chain(infos)
+        .collect()
+}
+
+async fn broadcast_sources(source_to_packets: &PacketsMap, clients: &Clients) {
+    for response in sources_responses(source_to_packets).await {
+        let Ok(encoded) = response.encode() else {
+            error!("Failed to encode sources");
+            return;
+        };
+
+        let encoded = Encoded::from(encoded);
+        for (_, client) in clients.write().await.iter_mut() {
+            client.queue.push(encoded.clone());
+        }
+    }
+}
+
+async fn broadcast_source_info(
+    current: SourceInfo,
+    info: &watch::Sender<SourceInfo>,
+    clients: &Clients,
+) {
+    info.send_replace(current.clone());
+
+    let Ok(encoded) = Response::SourceInfo(current).encode() else {
+        error!("Sniffer: failed to encode source info");
+        return;
+    };
+
+    let encoded = Encoded::from(encoded);
+    for (_, client) in clients.write().await.iter_mut() {
+        client.queue.push(encoded.clone());
+    }
+}
+
+pub async fn send_session_view(
+    client_id: &usize,
+    ws_tx: &mut SplitSink<WebSocket, Message>,
+    config: &Config,
+) {
+    let Some(ref view) = config.session_view else {
+        return;
+    };
+
+    let Ok(encoded) = Response::SessionView(view.clone()).encode() else {
+        error!("Failed to encode session view, client_id: {}", client_id);
+        return;
+    };
+
+    ws_tx
+        .send(Message::binary(encoded))
+        .unwrap_or_else(|e| {
+            error!("WebSocket send error: {}, client_id: {}", e, client_id);
+        })
+        .await;
 }
 
 pub async fn send_pcap_filenames(
     client_id: &usize,
     ws_tx: &mut SplitSink<WebSocket, Message>,
-    source_to_packets: &Arc<HashMap<Source, Packets>>,
+    source_to_packets: &PacketsMap,
 ) {
-    let sources = source_to_packets.keys().cloned().collect();
-    let response = Response::Sources(sources);
+    for response in sources_responses(source_to_packets).await {
+        let Ok(encoded) = response.encode() else {
+            error!("Failed to encode packet, client_id: {}", client_id);
+            return;
+        };
+
+        let msg = Message::binary(encoded);
+        ws_tx
+            .send(msg)
+            .unwrap_or_else(|e| {
+                error!("WebSocket send error: {}, client_id: {}", e, client_id);
+            })
+            .await;
+    }
+}
 
+pub async fn send_decode_rules(
+    client_id: &usize,
+    ws_tx: &mut SplitSink<WebSocket, Message>,
+    config: &Config,
+) {
+    let response = Response::DecodeRules(config.decode_rules.borrow().clone());
     let Ok(encoded) = response.encode() else {
-        error!("Failed to encode packet, client_id: {}", client_id);
+        error!("Failed to encode decode rules, client_id: {}", client_id);
         return;
     };
 
-    let msg = Message::binary(encoded);
     ws_tx
-        .send(msg)
+        .send(Message::binary(encoded))
         .unwrap_or_else(|e| {
             error!("WebSocket send error: {}, client_id: {}", e, client_id);
         })
         .await;
 }
 
-async fn send_stats(clients: &Clients, discharged: usize, overwritten: usize) {
-    let stats = PacketsStats {
-        discharged,
-        overwritten,
+// so that the rules set by the clients are there once the server restarts
+async fn save_decode_rules(rules: Vec<DecodeRule>, config: &Config) {
+    let path = config.decode_rules_file.clone();
+    let written = tokio::task::spawn_blocking(move || decode_rules::write(&path, &rules))
+        .await
+        .expect("writing decode rules shouldn't panic");
+
+    if let Err(err) = written {
+        error!(
+            "Failed to save decode rules to {}: {}",
+            config.decode_rules_file.display(),
+            err
+        );
+    }
+}
+
+async fn broadcast_decode_rules(clients: &Clients, config: &Config) {
+    let response = Response::DecodeRules(config.decode_rules.borrow().clone());
+    let Ok(encoded) = response.encode() else {
+        error!("Failed to encode decode rules");
+        return;
+    };
+
+    let encoded = Encoded::from(encoded);
+    for (_, client) in clients.write().await.iter_mut() {
+        client.queue.push(encoded.clone());
+    }
+}
+
+async fn send_stats(clients: &Clients, source: &Source, stats: PacketsStats) {
+    let Ok(encoded) = Response::PacketsStats(stats).encode() else {
+        error!("Failed to encode packets stats");
+        return;
     };
-    let response = Response::PacketsStats(stats);
+
+    let encoded = Encoded::from(encoded);
     for (_, client) in clients.write().await.iter_mut() {
-        if let Ok(encoded) = response.encode() {
-            client.queue.push_back(Message::binary(encoded));
+        if client.source.as_ref() == Some(source) {
+            client.queue.push(encoded.clone());
+        }
+    }
+}
+
+/// Logs the packets the capture dropped since the last poll, returns whether to keep polling.
+fn poll_capture_stats(sniffer: &mut Sniffer, last: &mut Option<CaptureStats>) -> bool {
+    match sniffer.capture_stats() {
+        Ok(Some(current)) => {
+            let previous = last.unwrap_or_default();
+            // the counters of libpcap wrap around
+            let dropped = current.dropped.wrapping_sub(previous.dropped);
+            let if_dropped = current.if_dropped.wrapping_sub(previous.if_dropped);
+            if dropped > 0 || if_dropped > 0 {
+                warn!(
+                    "Capture of {:?} dropped {} packets, its interface dropped {}",
+                    sniffer.source, dropped, if_dropped
+                );
+            }
+
+            *last = Some(current);
+            true
+        }
+        Ok(None) => false,
+        Err(err) => {
+            warn!(
+                "Statistics of the capture of {:?} are unavailable: {:?}",
+                sniffer.source, err
+            );
+            false
         }
     }
 }
@@ -96,28 +479,170 @@ async fn discharge_old_packets(packets: &mut PacketRingBuffer, max_packets_age:
     discharged_count
 }
 
-async fn sniff(mut sniffer: Sniffer, packets: Packets, clients: Clients, config: Config) {
+async fn next_replay_command(replay: &mut Option<ReplayControl>) -> Option<ReplayCommand> {
+    match replay {
+        Some(control) => control.commands.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn publish_replay_state(
+    state: Option<ReplayState>,
+    source: &Source,
+    control: &ReplayControl,
+    clients: &Clients,
+) {
+    let Some(state) = state else {
+        return;
+    };
+    control.state.send_replace(state);
+
+    let Ok(encoded) = Response::ReplayState(state).encode() else {
+        error!("Sniffer: failed to encode replay state");
+        return;
+    };
+
+    let encoded = Encoded::from(encoded);
+    for (_, client) in clients.write().await.iter_mut() {
+        if client.source.as_ref() == Some(source) {
+            client.queue.push(encoded.clone());
+        }
+    }
+}
+
+async fn control_replay(
+    sniffer: &mut Sniffer,
+    command: ReplayCommand,
+    control: &ReplayControl,
+    packets: &Packets,
+    analyzer: &SharedAnalyzer,
+    clients: &Clients,
+) {
+    let generation = sniffer.replay_state().map(|state| state.generation);
+    if let Err(err) = sniffer.control_replay(command) {
+        error!(
+            "Sniffer: failed to control replay of {:?}: {:?}",
+            sniffer.source, err
+        );
+        return;
+    }
+
+    if sniffer.replay_state().map(|state| state.generation) != generation {
+        // the file is replayed from its beginning, clients drop
+        // their packets once they receive the new generation
+        packets.write().await.clear();
+        *analyzer.write().await = Analyzer::new();
+    }
+
+    publish_replay_state(sniffer.replay_state(), &sniffer.source, control, clients).await;
+}
+
+async fn sniff(
+    mut sniffer: Sniffer,
+    packets: Packets,
+    clients: Clients,
+    config: Config,
+    channels: SourceChannels,
+) {
+    let SourceChannels {
+        mut replay,
+        stats,
+        info,
+        analyzer,
+        sdps,
+    } = channels;
     let mut overwritten_count = 0;
     let mut total_discharged_count = 0;
     let mut last_stats_time = SystemTime::now();
+    let mut capture_stats = None;
+    // keeps ticking while no packets arrive, which is when the capture may be dropping them
+    let mut polling = sniffer.is_live();
+    let mut poll_interval = tokio::time::interval(CAPTURE_STATS_INTERVAL);
+    // otherwise the tail of a burst stays buffered until the next packet arrives
+    let mut flush_interval = tokio::time::interval(RECORDING_FLUSH_INTERVAL);
+    // assign the SDPs of RTSP sessions and SIP calls to the RTP streams they set up
+    let mut rtsp = RtspTracker::default();
+    let mut calls = CallTracker::default();
+
+    loop {
+        let result = tokio::select! {
+            result = sniffer.next_packet() => result,
+            _ = poll_interval.tick(), if polling => {
+                polling = poll_capture_stats(&mut sniffer, &mut capture_stats);
+
+                let current_stats = PacketsStats {
+                    discharged: total_discharged_count,
+                    overwritten: overwritten_count,
+                    fragments: sniffer.fragment_stats(),
+                    capture: capture_stats,
+                };
+                stats.send_replace(current_stats.clone());
+                send_stats(&clients, &sniffer.source, current_stats).await;
+                continue;
+            }
+            _ = flush_interval.tick(), if sniffer.is_recording() => {
+                sniffer.flush_recording();
+                continue;
+            }
+            Some(command) = next_replay_command(&mut replay) => {
+                if let Some(ref control) = replay {
+                    control_replay(
+                        &mut sniffer,
+                        command,
+                        control,
+                        &packets,
+                        &analyzer,
+                        &clients,
+                    )
+                    .await;
+                }
+                broadcast_source_info(sniffer.info(), &info, &clients).await;
+                continue;
+            }
+        };
+
+        let Some(result) = result else {
+            // the counters of the whole capture, which likely ended between the periodic updates
+            broadcast_source_info(sniffer.info(), &info, &clients).await;
+
+            // replayed sources wait for a seek once they reach the end
+            match replay {
+                Some(ref control) => {
+                    publish_replay_state(
+                        sniffer.replay_state(),
+                        &sniffer.source,
+                        control,
+                        &clients,
+                    )
+                    .await;
+                    continue;
+                }
+                None => break,
+            }
+        };
 
-    while let Some(result) = sniffer.next_packet().await {
         match result {
             Ok(mut pack) => {
-                pack.guess_payload();
+                if !sniffer.is_restored() {
+                    pack.decode_payload(&config.decode_rules.borrow());
+                }
+                analyzer.write().await.add_packet(&pack);
+                calls.add_packet(&pack);
+                let assigned = rtsp.add_packet(&pack).or_else(|| calls.assign_sdp(&pack));
+                if let Some((stream_key, sdp)) = assigned {
+                    assign_sdp(&clients, &sdps, &sniffer.source, stream_key, sdp).await;
+                }
                 let response = Response::Packet(pack);
 
                 let Ok(encoded) = response.encode() else {
                     error!("Sniffer: failed to encode packet");
                     continue;
                 };
-                let msg = Message::binary(encoded);
+                let encoded = Encoded::from(encoded);
 
                 for (_, client) in clients.write().await.iter_mut() {
-                    if let Some(src) = &client.source {
-                        if *src == sniffer.source {
-                            client.queue.push_back(msg.clone());
-                        }
+                    if client.is_subscribed(&sniffer.source, &response) {
+                        client.queue.push_packet(encoded.clone());
                     }
                 }
 
@@ -132,9 +657,33 @@ async fn sniff(mut sniffer: Sniffer, packets: Packets, clients: Clients, config:
                 }
                 packets.push_overwrite(response);
 
+                let current_stats = PacketsStats {
+                    discharged: total_discharged_count,
+                    overwritten: overwritten_count,
+                    fragments: sniffer.fragment_stats(),
+                    capture: capture_stats,
+                };
+                stats.send_if_modified(|stats| {
+                    if *stats == current_stats {
+                        return false;
+                    }
+                    *stats = current_stats.clone();
+                    true
+                });
+
                 if let Ok(elapsed) = last_stats_time.elapsed() {
                     if elapsed.as_secs() >= 5 {
-                        send_stats(&clients, total_discharged_count, overwritten_count).await;
+                        send_stats(&clients, &sniffer.source, current_stats).await;
+                        broadcast_source_info(sniffer.info(), &info, &clients).await;
+                        if let Some(ref control) = replay {
+                            publish_replay_state(
+                                sniffer.replay_state(),
+                                &sniffer.source,
+                                control,
+                                &clients,
+                            )
+                            .await;
+                        }
                         last_stats_time = SystemTime::now();
                     }
                 }
@@ -144,53 +693,57 @@ async fn sniff(mut sniffer: Sniffer, packets: Packets, clients: Clients, config:
     }
 }
 
-async fn send_batch(packets: Vec<Response>, ws_tx: &UnboundedSender<Message>, client_id: usize) {
-    let encoded = bincode::serialize(&packets).unwrap_or_else(|e| {
-        error!("Failed to encode packet batch: {}", e);
-        Vec::new()
-    });
-
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(&encoded).unwrap_or_else(|e| {
-        error!("Failed to compress packet batch: {}", e);
-    });
-
-    let compressed = encoder.finish().unwrap_or_else(|e| {
-        error!("Failed to finish compression: {}", e);
-        Vec::new()
-    });
-
-    let msg = Message::binary(compressed);
-    ws_tx.send(msg).unwrap_or_else(|e| {
-        error!(
-            "WebSocket batch send error: {}, client_id: {}",
-            e, client_id
-        );
-    });
-}
-
-async fn send_all_packets(client_id: usize, packets: &Packets, clients: &Clients) {
+/// Sends the most recent packets of the range, up to a page of them,
+/// followed by a notice of the older ones left out.
+async fn send_packet_range<F>(client_id: usize, packets: &Packets, clients: &Clients, in_range: F)
+where
+    F: Fn(&Packet) -> bool,
+{
     let packets_read = packets.read().await;
     let mut wr_clients = clients.write().await;
-    let client = match wr_clients.get_mut(&client_id) {
-        Some(client) => client,
-        None => return, // The client might have disconnected
+    let Some(client) = wr_clients.get_mut(&client_id) else {
+        return; // The client might have disconnected
     };
 
-    for packet in packets_read.iter() {
-        let Ok(encoded) = packet.encode() else {
+    let matching: Vec<_> = packets_read
+        .iter()
+        .filter(|response| match response {
+            Response::Packet(packet) => in_range(packet) && client.subscription.matches(packet),
+            _ => false,
+        })
+        .collect();
+    let (left_out, page) = matching.split_at(matching.len().saturating_sub(HISTORY_PAGE_SIZE));
+
+    for response in page {
+        let Ok(encoded) = response.encode() else {
             error!("Failed to encode packet for client_id: {}", client_id);
             continue;
         };
-        let msg = Message::binary(encoded);
 
-        client.queue.push_back(msg);
+        client.queue.push_packet(encoded.into());
+    }
+
+    let first_id = |responses: &[&Response]| match responses.first() {
+        Some(Response::Packet(packet)) => packet.id,
+        _ => 0,
+    };
+    let fetched = Response::FetchedRange {
+        remaining: left_out.len(),
+        from_id: first_id(left_out),
+        to_id: first_id(page),
+    };
+    match fetched.encode() {
+        Ok(encoded) => client.queue.push(encoded.into()),
+        Err(e) => error!(
+            "Failed to encode fetched range: {}, client_id: {}",
+            e, client_id
+        ),
     }
 }
 
 async fn parse_sdp(
-    client_id: usize,
     clients: &Clients,
+    sdps: &Sdps,
     cur_source: &Source,
     stream_key: RtpStreamKey,
     raw_sdp: String,
@@ -202,30 +755,215 @@ async fn parse_sdp(
         );
         return;
     };
+    sdps.write().await.insert(stream_key, sdp.clone());
+    broadcast_sdp(clients, cur_source, stream_key, sdp).await;
+}
+
+/// Assigns the SDP found in an RTSP session or a SIP call, unless the stream already has one set by a client.
+async fn assign_sdp(
+    clients: &Clients,
+    sdps: &Sdps,
+    source: &Source,
+    stream_key: RtpStreamKey,
+    sdp: Sdp,
+) {
+    match sdps.write().await.entry(stream_key) {
+        Entry::Occupied(_) => return,
+        Entry::Vacant(entry) => entry.insert(sdp.clone()),
+    };
+    info!("Assigned negotiated SDP to {:?}: {:?}", source, stream_key);
+    broadcast_sdp(clients, source, stream_key, sdp).await;
+}
 
+async fn broadcast_sdp(clients: &Clients, source: &Source, stream_key: RtpStreamKey, sdp: Sdp) {
     let Ok(encoded) = Response::Sdp(stream_key, sdp).encode() else {
-        error!("Failed to encode sdp, client_id: {}", client_id);
+        error!("Failed to encode sdp of {:?}", stream_key);
         return;
     };
 
-    let msg = Message::binary(encoded);
+    let encoded = Encoded::from(encoded);
+    for (_, client) in clients.write().await.iter_mut() {
+        if client.source.as_ref() == Some(source) {
+            client.queue.push(encoded.clone());
+        }
+    }
+}
+
+async fn send_sdps(client_id: usize, sdps: &Sdps, clients: &Clients) {
+    let sdps = sdps.read().await;
+    let mut wr_clients = clients.write().await;
+    let Some(client) = wr_clients.get_mut(&client_id) else {
+        return; // The client might have disconnected
+    };
+
+    for (stream_key, sdp) in sdps.iter() {
+        match Response::Sdp(*stream_key, sdp.clone()).encode() {
+            Ok(encoded) => client.queue.push(encoded.into()),
+            Err(e) => error!("Failed to encode sdp: {}, client_id: {}", e, client_id),
+        }
+    }
+}
 
-    let clients_guard = clients.read().await;
-    for (_, client) in clients_guard.iter() {
-        if let Some(ref source) = client.source {
-            if *source == *cur_source {
-                if let Err(e) = client.sender.send(msg.clone()) {
-                    error!("Sniffer: error while sending sdp: {}", e);
-                }
-            }
+/// Writes the packets of all the sources and the client's view to the session file.
+async fn save_session(
+    client_id: usize,
+    view: SessionView,
+    source_to_packets: &PacketsMap,
+    clients: &Clients,
+    config: &Config,
+) {
+    let mut session = Session {
+        sources: Vec::new(),
+        view,
+        decode_rules: config.decode_rules.borrow().clone(),
+    };
+
+    for (source, handle) in source_to_packets.read().await.iter() {
+        let packets = handle.packets.read().await;
+        let saved = SavedSource {
+            source: source.clone(),
+            buffer_size: packets.capacity().get(),
+            packets: packets
+                .iter()
+                .filter_map(|response| match response {
+                    Response::Packet(packet) => Some(packet.clone()),
+                    _ => None,
+                })
+                .collect(),
+            sdps: handle
+                .sdps
+                .read()
+                .await
+                .iter()
+                .map(|(stream_key, sdp)| (*stream_key, sdp.clone()))
+                .collect(),
+        };
+        session.sources.push(saved);
+    }
+
+    let path = config.session.clone();
+    let written = tokio::task::spawn_blocking(move || session.write(&path))
+        .await
+        .expect("writing a session shouldn't panic");
+
+    let path = config.session.display().to_string();
+    let result = match written {
+        Ok(()) => {
+            info!("Saved session to {}, client_id: {}", path, client_id);
+            Ok(path)
+        }
+        Err(err) => {
+            error!(
+                "Failed to save session to {}: {}, client_id: {}",
+                path, err, client_id
+            );
+            Err(err.to_string())
+        }
+    };
+
+    let Ok(encoded) = Response::SessionSaved(result).encode() else {
+        error!("Failed to encode saved session, client_id: {}", client_id);
+        return;
+    };
+    if let Some(client) = clients.write().await.get_mut(&client_id) {
+        client.queue.push(encoded.into());
+    }
+}
+
+async fn reparse_packet(
+    client_id: usize,
+    clients: &Clients,
+    packets: &Packets,
+    cur_source: &Source,
+    id: usize,
+    packet_type: SessionProtocol,
+) {
+    let response = {
+        let mut packets = packets.write().await;
+        let Some(packet) = packets.iter_mut().find_map(|response| match response {
+            Response::Packet(packet) if packet.id == id => Some(packet),
+            _ => None,
+        }) else {
+            warn!(
+                "Received reparse request for non-existing packet {}, client_id: {}",
+                id, client_id
+            );
+            return;
+        };
+
+        packet.parse_as(packet_type);
+        Response::Packet(packet.clone())
+    };
+
+    let Ok(encoded) = response.encode() else {
+        error!("Failed to encode packet, client_id: {}", client_id);
+        return;
+    };
+
+    let encoded = Encoded::from(encoded);
+    for (_, client) in clients.write().await.iter_mut() {
+        if client.is_subscribed(cur_source, &response) {
+            client.queue.push_packet(encoded.clone());
         }
     }
 }
+
+async fn get_source(source_to_packets: &PacketsMap, source: &Source) -> Option<SourceHandle> {
+    source_to_packets.read().await.get(source).cloned()
+}
+
+async fn send_replay_command(
+    client_id: usize,
+    packets: &PacketsMap,
+    cur_source: &Option<Source>,
+    command: ReplayCommand,
+) {
+    let handle = match cur_source {
+        Some(source) => get_source(packets, source).await,
+        None => None,
+    };
+
+    let Some(replay) = handle.and_then(|handle| handle.replay) else {
+        warn!(
+            "Received replay request for a source that isn't replayed: {:?}, client_id: {}",
+            cur_source, client_id
+        );
+        return;
+    };
+
+    if let Err(e) = replay.commands.send(command) {
+        error!(
+            "Failed to send replay command: {}, client_id: {}",
+            e, client_id
+        );
+    }
+}
+
+async fn add_requested_source<F>(
+    client_id: usize,
+    name: &str,
+    open: F,
+    packets: &PacketsMap,
+    clients: &Clients,
+    config: &Config,
+) where
+    F: FnOnce(&SourceOptions) -> Result<Sniffer, sniffer::Error> + Send + 'static,
+{
+    if let Err(err) = open_source(name, open, false, packets, clients, config).await {
+        warn!(
+            "Failed to add source {}: {:?}, client_id: {}",
+            name, err, client_id
+        );
+    }
+}
+
 pub async fn handle_messages(
     client_id: usize,
+    role: Role,
     mut ws_rx: SplitStream<WebSocket>,
     clients: &Clients,
     packets: &PacketsMap,
+    config: &Config,
 ) {
     let rd_clients = clients.read().await;
     let client = rd_clients.get(&client_id).unwrap();
@@ -246,28 +984,58 @@ pub async fn handle_messages(
                     continue;
                 };
 
+                if !role.allows(&req) {
+                    warn!(
+                        "Ignored request not allowed for read-only client: {:?}, client_id: {}",
+                        req, client_id
+                    );
+                    continue;
+                }
+
                 match req {
-                    Request::FetchAll => {
-                        if let Some(ref cur_source) = source {
-                            if let Some(packets) = packets.get(cur_source) {
-                                send_all_packets(client_id, packets, clients).await;
-                            } else {
-                                warn!(
-                                    "No packets found for source: {:?}, client_id: {}",
-                                    cur_source, client_id
-                                );
-                            }
+                    Request::FetchRange { from_id, to_id } => {
+                        let Some(ref cur_source) = source else {
+                            warn!(
+                                "Received FetchRange request without a selected source, client_id: {}",
+                                client_id
+                            );
+                            continue;
+                        };
+                        if let Some(handle) = get_source(packets, cur_source).await {
+                            let in_range = |packet: &Packet| (from_id..to_id).contains(&packet.id);
+                            send_packet_range(client_id, &handle.packets, clients, in_range).await;
+                        }
+                    }
+                    Request::FetchTimeRange { from, to } => {
+                        let Some(ref cur_source) = source else {
+                            warn!(
+                                "Received FetchTimeRange request without a selected source, client_id: {}",
+                                client_id
+                            );
+                            continue;
+                        };
+                        if let Some(handle) = get_source(packets, cur_source).await {
+                            let in_range = |packet: &Packet| (from..to).contains(&packet.timestamp);
+                            send_packet_range(client_id, &handle.packets, clients, in_range).await;
                         }
                     }
                     Request::ChangeSource(new_source) => {
-                        if let Some(packets) = packets.get(&new_source) {
+                        if let Some(handle) = get_source(packets, &new_source).await {
                             {
                                 let mut wr_clients = clients.write().await;
                                 let client = wr_clients.get_mut(&client_id).unwrap();
                                 client.source = Some(new_source.clone());
+                                if let Some(ref replay) = handle.replay {
+                                    let state = *replay.state.borrow();
+                                    if let Ok(encoded) = Response::ReplayState(state).encode() {
+                                        client.queue.push(encoded.into());
+                                    }
+                                }
                             }
                             source = Some(new_source);
-                            send_all_packets(client_id, packets, clients).await;
+                            // the older packets are fetched once the client needs them
+                            send_packet_range(client_id, &handle.packets, clients, |_| true).await;
+                            send_sdps(client_id, &handle.sdps, clients).await;
                         } else {
                             warn!(
                                 "Attempted to change to unknown source: {:?}, client_id: {}",
@@ -276,27 +1044,122 @@ pub async fn handle_messages(
                         }
                     }
 
-                    Request::ParseSdp(stream_key, sdp) => {
-                        if let Some(cur_source) = &source {
-                            parse_sdp(client_id, clients, cur_source, stream_key, sdp).await;
+                    Request::Reparse(id, packet_type) => {
+                        let Some(ref cur_source) = source else {
+                            warn!(
+                                "Received Reparse request without a selected source, client_id: {}",
+                                client_id
+                            );
+                            continue;
+                        };
+
+                        if let Some(handle) = get_source(packets, cur_source).await {
+                            reparse_packet(
+                                client_id,
+                                clients,
+                                &handle.packets,
+                                cur_source,
+                                id,
+                                packet_type,
+                            )
+                            .await;
                         } else {
+                            warn!(
+                                "No packets found for source: {:?}, client_id: {}",
+                                cur_source, client_id
+                            );
+                        }
+                    }
+
+                    Request::ParseSdp(stream_key, sdp) => {
+                        let Some(ref cur_source) = source else {
                             warn!("Received ParseSdp request without a selected source, client_id: {}", client_id);
+                            continue;
+                        };
+
+                        if let Some(handle) = get_source(packets, cur_source).await {
+                            let sdps = &handle.sdps;
+                            parse_sdp(clients, sdps, cur_source, stream_key, sdp).await;
+                        }
+                    }
+
+                    Request::PauseReplay => {
+                        send_replay_command(client_id, packets, &source, ReplayCommand::Pause)
+                            .await;
+                    }
+                    Request::ResumeReplay => {
+                        send_replay_command(client_id, packets, &source, ReplayCommand::Resume)
+                            .await;
+                    }
+                    Request::SeekReplay(offset) => {
+                        let command = ReplayCommand::Seek(offset);
+                        send_replay_command(client_id, packets, &source, command).await;
+                    }
+
+                    Request::AddFile(file) => {
+                        // the clients can't open any file the server has access to
+                        let Some(path) = config.uploaded_file(&file) else {
+                            warn!(
+                                "Attempted to add file outside of the upload directory: {}, client_id: {}",
+                                file, client_id
+                            );
+                            continue;
+                        };
+                        let file = path.to_string_lossy().into_owned();
+                        let name = file.clone();
+                        let open =
+                            move |options: &SourceOptions| Sniffer::open_file(&file, options);
+                        add_requested_source(client_id, &name, open, packets, clients, config)
+                            .await;
+                    }
+                    Request::AddInterface(device) => {
+                        let name = device.clone();
+                        let open = move |options: &SourceOptions| {
+                            Sniffer::open_interface(&device, options)
+                        };
+                        add_requested_source(client_id, &name, open, packets, clients, config)
+                            .await;
+                    }
+                    Request::AddSocket(address) => {
+                        let name = address.clone();
+                        let open =
+                            move |options: &SourceOptions| Sniffer::open_socket(&address, options);
+                        add_requested_source(client_id, &name, open, packets, clients, config)
+                            .await;
+                    }
+                    Request::RemoveSource(removed) => {
+                        if !remove_source(&removed, packets, clients).await {
+                            warn!(
+                                "Attempted to remove unknown source: {:?}, client_id: {}",
+                                removed, client_id
+                            );
+                        } else if source.as_ref() == Some(&removed) {
+                            source = None;
                         }
                     }
 
-                    Request::PacketsStats(stats) => {
-                        let response = Response::PacketsStats(stats);
-                        if let Ok(encoded) = response.encode() {
-                            let msg = Message::binary(encoded);
-                            let mut wr_clients = clients.write().await;
-                            for (_, client) in wr_clients.iter_mut() {
-                                client.queue.push_back(msg.clone());
+                    Request::Subscribe(subscription) => {
+                        if let Some(client) = clients.write().await.get_mut(&client_id) {
+                            client.subscription = subscription;
+                        }
+
+                        if let Some(ref cur_source) = source {
+                            if let Some(handle) = get_source(packets, cur_source).await {
+                                send_packet_range(client_id, &handle.packets, clients, |_| true)
+                                    .await;
                             }
                         }
                     }
 
-                    _ => {
-                        warn!("Unhandled request: {:?}, client_id: {}", req, client_id);
+                    Request::SaveSession(view) => {
+                        save_session(client_id, view, packets, clients, config).await;
+                    }
+
+                    Request::SetDecodeRules(rules) => {
+                        info!("Decode rules set to {:?}, client_id: {}", rules, client_id);
+                        config.decode_rules.send_replace(rules.clone());
+                        broadcast_decode_rules(clients, config).await;
+                        save_decode_rules(rules, config).await;
                     }
                 }
             }
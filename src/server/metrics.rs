@@ -0,0 +1,342 @@
+use super::api::source_id;
+use super::handler::PacketsMap;
+use crate::analysis::Analyzer;
+use netpix_common::packet::TransportProtocol;
+use netpix_common::PacketsStats;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use warp::{http::header::HeaderValue, reply, Rejection};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(Debug, Clone, Copy)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+type Metric = (&'static str, MetricType, &'static str);
+
+#[derive(Debug)]
+struct Family {
+    name: &'static str,
+    metric_type: MetricType,
+    help: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+/// Collects samples grouped by metric, as the text format requires.
+#[derive(Debug, Default)]
+struct Metrics {
+    families: Vec<Family>,
+}
+
+impl Metrics {
+    fn add(&mut self, (name, metric_type, help): Metric, labels: &[(&str, &str)], value: f64) {
+        let position = self.families.iter().position(|family| family.name == name);
+        let family = match position {
+            Some(position) => &mut self.families[position],
+            None => {
+                self.families.push(Family {
+                    name,
+                    metric_type,
+                    help,
+                    samples: Vec::new(),
+                });
+                self.families.last_mut().unwrap()
+            }
+        };
+
+        let labels = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        family.samples.push((labels, value));
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        for family in &self.families {
+            let metric_type = match family.metric_type {
+                MetricType::Counter => "counter",
+                MetricType::Gauge => "gauge",
+            };
+            let _ = writeln!(output, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(output, "# TYPE {} {}", family.name, metric_type);
+            for (labels, value) in &family.samples {
+                let _ = writeln!(output, "{}{{{}}} {}", family.name, labels, value);
+            }
+        }
+
+        output
+    }
+}
+
+const PACKETS: Metric = (
+    "netpix_packets_total",
+    MetricType::Counter,
+    "Packets captured per session protocol",
+);
+const BYTES: Metric = (
+    "netpix_bytes_total",
+    MetricType::Counter,
+    "Bytes captured per session protocol",
+);
+const DISCHARGED: Metric = (
+    "netpix_packets_discharged_total",
+    MetricType::Counter,
+    "Packets removed from the buffer as outdated",
+);
+const OVERWRITTEN: Metric = (
+    "netpix_packets_overwritten_total",
+    MetricType::Counter,
+    "Packets removed from the buffer to make room for new ones",
+);
+const CAPTURE_RECEIVED: Metric = (
+    "netpix_capture_received_total",
+    MetricType::Counter,
+    "Packets received by the capture of the interface, according to libpcap",
+);
+const CAPTURE_DROPPED: Metric = (
+    "netpix_capture_dropped_total",
+    MetricType::Counter,
+    "Packets dropped by the capture as they weren't read fast enough",
+);
+const CAPTURE_IF_DROPPED: Metric = (
+    "netpix_capture_if_dropped_total",
+    MetricType::Counter,
+    "Packets dropped by the network interface or its driver",
+);
+const RTP_PACKETS: Metric = (
+    "netpix_rtp_packets_total",
+    MetricType::Counter,
+    "Packets received in the RTP stream",
+);
+const RTP_BYTES: Metric = (
+    "netpix_rtp_bytes_total",
+    MetricType::Counter,
+    "Bytes received in the RTP stream",
+);
+const RTP_LOST: Metric = (
+    "netpix_rtp_lost_packets",
+    MetricType::Gauge,
+    "Packets missing from the RTP stream according to the sequence numbers",
+);
+const RTP_LOSS: Metric = (
+    "netpix_rtp_loss_ratio",
+    MetricType::Gauge,
+    "Ratio of the expected packets missing from the RTP stream",
+);
+const RTP_JITTER: Metric = (
+    "netpix_rtp_jitter_seconds",
+    MetricType::Gauge,
+    "Current interarrival jitter of the RTP stream",
+);
+const RTP_MAX_JITTER: Metric = (
+    "netpix_rtp_max_jitter_seconds",
+    MetricType::Gauge,
+    "Highest interarrival jitter of the RTP stream",
+);
+const RTP_BITRATE: Metric = (
+    "netpix_rtp_bitrate_bps",
+    MetricType::Gauge,
+    "Mean bitrate of the RTP stream",
+);
+const MPEGTS_CONTINUITY_ERRORS: Metric = (
+    "netpix_mpegts_continuity_errors_total",
+    MetricType::Counter,
+    "Continuity counter errors of the MPEG-TS PID",
+);
+
+pub async fn serve(source_to_packets: PacketsMap) -> Result<reply::Response, Rejection> {
+    let handles: Vec<_> = source_to_packets
+        .read()
+        .await
+        .iter()
+        .map(|(source, handle)| (source_id(source), handle.clone()))
+        .collect();
+
+    let mut metrics = Metrics::default();
+    for (source, handle) in handles {
+        let stats = handle.stats.borrow().clone();
+        let analyzer = handle.analyzer.read().await;
+        add_source(&mut metrics, &source, &stats, &analyzer);
+    }
+
+    let mut response = reply::Response::new(metrics.render().into());
+    response
+        .headers_mut()
+        .insert("content-type", HeaderValue::from_static(CONTENT_TYPE));
+    Ok(response)
+}
+
+/// Adds the samples of a source, labeled with its id.
+fn add_source(metrics: &mut Metrics, source: &str, stats: &PacketsStats, analyzer: &Analyzer) {
+    let source_label = [("source", source)];
+    metrics.add(DISCHARGED, &source_label, stats.discharged as f64);
+    metrics.add(OVERWRITTEN, &source_label, stats.overwritten as f64);
+    if let Some(capture) = stats.capture {
+        metrics.add(CAPTURE_RECEIVED, &source_label, capture.received as f64);
+        metrics.add(CAPTURE_DROPPED, &source_label, capture.dropped as f64);
+        metrics.add(CAPTURE_IF_DROPPED, &source_label, capture.if_dropped as f64);
+    }
+
+    let counters = analyzer.counters();
+    for (protocol, counter) in [
+        ("unknown", counters.unknown),
+        ("rtp", counters.rtp),
+        ("rtcp", counters.rtcp),
+        ("mpegts", counters.mpegts),
+        ("stun", counters.stun),
+        ("rtsp", counters.rtsp),
+        ("sip", counters.sip),
+    ] {
+        let labels = [("source", source), ("protocol", protocol)];
+        metrics.add(PACKETS, &labels, counter.packets as f64);
+        metrics.add(BYTES, &labels, counter.bytes as f64);
+    }
+
+    for ((source_addr, destination_addr, protocol, ssrc), stream) in analyzer.rtp_streams() {
+        let summary = stream.summary();
+        let addresses = address_labels(source_addr, destination_addr, protocol);
+        let ssrc = ssrc.to_string();
+        let labels = [
+            ("source", source),
+            ("src", addresses[0].as_str()),
+            ("dst", addresses[1].as_str()),
+            ("transport", addresses[2].as_str()),
+            ("ssrc", ssrc.as_str()),
+        ];
+
+        metrics.add(RTP_PACKETS, &labels, summary.packets as f64);
+        metrics.add(RTP_BYTES, &labels, summary.bytes as f64);
+        metrics.add(RTP_LOST, &labels, summary.lost_packets as f64);
+        metrics.add(RTP_LOSS, &labels, summary.loss_percentage / 100.0);
+        if let Some(jitter) = stream.get_jitter() {
+            metrics.add(RTP_JITTER, &labels, jitter);
+        }
+        if let Some(jitter) = stream.get_max_jitter() {
+            metrics.add(RTP_MAX_JITTER, &labels, jitter);
+        }
+        metrics.add(RTP_BITRATE, &labels, summary.mean_bitrate_bps);
+    }
+
+    for ((source_addr, destination_addr, protocol), stream) in analyzer.mpegts_streams() {
+        let addresses = address_labels(source_addr, destination_addr, protocol);
+        for (pid, errors) in stream.pid_continuity_errors() {
+            let pid = pid.to_string();
+            let labels = [
+                ("source", source),
+                ("src", addresses[0].as_str()),
+                ("dst", addresses[1].as_str()),
+                ("transport", addresses[2].as_str()),
+                ("pid", pid.as_str()),
+            ];
+            metrics.add(MPEGTS_CONTINUITY_ERRORS, &labels, errors as f64);
+        }
+    }
+}
+
+fn address_labels(
+    source_addr: &SocketAddr,
+    destination_addr: &SocketAddr,
+    protocol: &TransportProtocol,
+) -> [String; 3] {
+    [
+        source_addr.to_string(),
+        destination_addr.to_string(),
+        format!("{:?}", protocol).to_lowercase(),
+    ]
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netpix_common::{Packet, Source};
+    use std::time::Duration;
+
+    fn rtp(sequence_number: u16) -> Packet {
+        let mut payload = vec![0x80, 0x00];
+        payload.extend(sequence_number.to_be_bytes());
+        payload.extend((sequence_number as u32 * 160).to_be_bytes());
+        payload.extend(0x1234u32.to_be_bytes());
+        payload.extend([0xff; 160]);
+
+        let mut packet = Packet::build_from_datagram(
+            &payload,
+            sequence_number as usize,
+            "10.0.0.1:5004".parse().unwrap(),
+            "10.0.0.2:5004".parse().unwrap(),
+            Duration::from_millis(sequence_number as u64 * 20),
+        );
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    #[test]
+    fn rendered_source() {
+        let mut analyzer = Analyzer::new();
+        for sequence_number in [1, 2, 4] {
+            analyzer.add_packet(&rtp(sequence_number));
+        }
+        let stats = PacketsStats {
+            overwritten: 3,
+            ..Default::default()
+        };
+        let source = source_id(&Source::File(r#"/tmp/a "b"\c.pcap"#.to_string()));
+
+        let mut metrics = Metrics::default();
+        add_source(&mut metrics, &source, &stats, &analyzer);
+        let output = metrics.render();
+        let lines: Vec<_> = output.lines().collect();
+
+        let label = r#"source="file:/tmp/a \"b\"\\c.pcap""#;
+        assert_eq!(
+            lines[..6],
+            [
+                "# HELP netpix_packets_discharged_total Packets removed from the buffer as outdated",
+                "# TYPE netpix_packets_discharged_total counter",
+                &format!("netpix_packets_discharged_total{{{}}} 0", label),
+                "# HELP netpix_packets_overwritten_total Packets removed from the buffer to make room for new ones",
+                "# TYPE netpix_packets_overwritten_total counter",
+                &format!("netpix_packets_overwritten_total{{{}}} 3", label),
+            ]
+        );
+
+        // the capture counters are only known for interfaces
+        assert!(!output.contains("netpix_capture_"));
+        assert!(lines.contains(&"# TYPE netpix_packets_total counter"));
+        assert!(lines
+            .contains(&format!("netpix_packets_total{{{},protocol=\"rtp\"}} 3", label).as_str()));
+        assert!(lines.contains(&"# TYPE netpix_rtp_lost_packets gauge"));
+
+        let stream = format!(
+            "{},src=\"10.0.0.1:5004\",dst=\"10.0.0.2:5004\",transport=\"udp\",ssrc=\"4660\"",
+            label
+        );
+        assert!(lines.contains(&format!("netpix_rtp_packets_total{{{}}} 3", stream).as_str()));
+        assert!(lines.contains(&format!("netpix_rtp_lost_packets{{{}}} 1", stream).as_str()));
+        assert!(lines.contains(&format!("netpix_rtp_loss_ratio{{{}}} 0.25", stream).as_str()));
+
+        // each family is described once, before its samples
+        let help = lines
+            .iter()
+            .filter(|line| line.starts_with("# HELP netpix_packets_total "))
+            .count();
+        assert_eq!(help, 1);
+    }
+
+    #[test]
+    fn escaped_label_values() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), r"a\nb");
+    }
+}
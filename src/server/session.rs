@@ -0,0 +1,217 @@
+use super::file::write_atomically;
+use netpix_common::{DecodeRule, Packet, RtpStreamKey, Sdp, SessionView, Source};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"NETPIXSS";
+const VERSION: u32 = 1;
+
+/// Contents of a session file: the buffered packets of every source and the view
+/// of the client that saved it, written with bincode after the magic and version.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub sources: Vec<SavedSource>,
+    pub view: SessionView,
+    pub decode_rules: Vec<DecodeRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSource {
+    pub source: Source,
+    pub buffer_size: usize,
+    /// Kept as they were parsed, reparsed packets included
+    pub packets: Vec<Packet>,
+    pub sdps: Vec<(RtpStreamKey, Sdp)>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Format(bincode::Error),
+    NotASession,
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Format(err) => write!(f, "corrupted session file: {}", err),
+            Self::NotASession => write!(f, "not a netpix session file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported session file version {}", version)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Self::Format(err)
+    }
+}
+
+impl Session {
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| Error::NotASession)?;
+        if &magic != MAGIC {
+            return Err(Error::NotASession);
+        }
+
+        let mut version = [0; 4];
+        reader
+            .read_exact(&mut version)
+            .map_err(|_| Error::NotASession)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    /// Replaces the session file, see [`write_atomically`].
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        write_atomically(path, |writer| {
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            bincode::serialize_into(writer, self)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::file::temp_path;
+    use netpix_common::packet::{SessionProtocol, TransportProtocol};
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn packet(id: usize) -> Packet {
+        let source: SocketAddr = "10.0.0.1:5004".parse().unwrap();
+        let destination: SocketAddr = "239.1.1.1:5004".parse().unwrap();
+        let mut packet = Packet::build_from_datagram(
+            &[0x80, 0x60, 0, id as u8, 0, 0, 0, 0, 0, 0, 0, 1],
+            id,
+            source,
+            destination,
+            Duration::from_millis(id as u64),
+        );
+        packet.decode_payload(&[]);
+        packet
+    }
+
+    #[test]
+    fn written_session_reads_back() {
+        let path = temp_path("round-trip.netpix");
+        let key = (
+            "10.0.0.1:5004".parse().unwrap(),
+            "239.1.1.1:5004".parse().unwrap(),
+            TransportProtocol::Udp,
+            1,
+        );
+        let session = Session {
+            sources: vec![SavedSource {
+                source: Source::Files(vec!["a.pcap".to_string(), "b c.pcap".to_string()]),
+                buffer_size: 100,
+                packets: vec![packet(1), packet(2)],
+                sdps: Vec::new(),
+            }],
+            view: SessionView {
+                source: Some(Source::Interface("eth0".to_string())),
+                tab: Some("RTP streams".to_string()),
+                filters: vec![("Packets".to_string(), "source:10.0.0.1".to_string())],
+                aliases: vec![(key, "A".to_string())],
+            },
+            decode_rules: vec!["5004=rtp".parse().unwrap()],
+        };
+
+        session.write(&path).unwrap();
+        let read = Session::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.view, session.view);
+        assert_eq!(read.decode_rules, session.decode_rules);
+        assert_eq!(read.sources.len(), 1);
+
+        let source = &read.sources[0];
+        assert_eq!(source.source, session.sources[0].source);
+        assert_eq!(source.buffer_size, 100);
+        let ids: Vec<_> = source.packets.iter().map(|packet| packet.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(source.packets[1].session_protocol, SessionProtocol::Rtp);
+        assert_eq!(source.packets[1].timestamp, Duration::from_millis(2));
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = temp_path("bad-magic.netpix");
+        fs::write(&path, b"\xd4\xc3\xb2\xa1\x02\x00\x04\x00").unwrap();
+        let result = Session::read(&path);
+        assert!(matches!(result, Err(Error::NotASession)));
+
+        // shorter than the magic
+        fs::write(&path, b"NETPIX").unwrap();
+        let result = Session::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::NotASession)));
+
+        let result = Session::read(&temp_path("missing.netpix"));
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let path = temp_path("bad-version.netpix");
+        Session::default().write(&path).unwrap();
+
+        let mut contents = fs::read(&path).unwrap();
+        contents[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&path, contents).unwrap();
+
+        let result = Session::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(result, Err(Error::UnsupportedVersion(version)) if version == VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn truncated_sessions_are_corrupted() {
+        let path = temp_path("truncated.netpix");
+        let session = Session {
+            sources: vec![SavedSource {
+                source: Source::File("a.pcap".to_string()),
+                buffer_size: 100,
+                packets: vec![packet(1)],
+                sdps: Vec::new(),
+            }],
+            ..Default::default()
+        };
+        session.write(&path).unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() - 4]).unwrap();
+
+        let result = Session::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::Format(_))));
+    }
+}
@@ -0,0 +1,191 @@
+use super::client::Clients;
+use super::config::Config;
+use super::handler::{open_source, PacketsMap, SourceError};
+use crate::sniffer::{Sniffer, SourceOptions};
+use log::{error, warn};
+use percent_encoding::percent_decode_str;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use warp::{
+    http::StatusCode,
+    hyper::body::Bytes,
+    reply::{self, Reply},
+    Rejection,
+};
+
+const DEFAULT_FILE_NAME: &str = "upload.pcap";
+
+/// Stores the uploaded pcap file and adds it as a new source.
+pub async fn upload(
+    name: String,
+    body: Bytes,
+    clients: Clients,
+    source_to_packets: PacketsMap,
+    config: Config,
+) -> Result<reply::Response, Rejection> {
+    let path = match store(&config.upload_dir, &name, &body).await {
+        Ok(path) => path,
+        Err(err) => {
+            error!("Failed to store uploaded file {}: {}", name, err);
+            let reply = reply::with_status(
+                "Failed to store the file",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+            return Ok(reply.into_response());
+        }
+    };
+
+    let file = path.to_string_lossy().into_owned();
+    let opened = file.clone();
+    let open = move |options: &SourceOptions| Sniffer::open_file(&opened, options);
+    match open_source(&file, open, true, &source_to_packets, &clients, &config).await {
+        Ok(source) => {
+            Ok(reply::with_status(reply::json(&source), StatusCode::CREATED).into_response())
+        }
+        Err(err) => {
+            warn!("Failed to open uploaded file {}: {:?}", name, err);
+            if let Err(err) = fs::remove_file(&path).await {
+                warn!("Failed to remove uploaded file {}: {}", path.display(), err);
+            }
+
+            let message = match err {
+                SourceError::Unavailable(err) => format!("Failed to open the file: {:?}", err),
+                SourceError::AlreadyAdded => "The file was already added".to_string(),
+            };
+            Ok(reply::with_status(message, StatusCode::UNPROCESSABLE_ENTITY).into_response())
+        }
+    }
+}
+
+async fn store(dir: &Path, name: &str, body: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir).await?;
+
+    let name = sanitize(&percent_decode_str(name).decode_utf8_lossy());
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name.as_str(), None),
+    };
+
+    // the same file might be uploaded more than once
+    for index in 0.. {
+        let file_name = match (index, extension) {
+            (0, _) => name.clone(),
+            (_, Some(extension)) => format!("{}-{}.{}", stem, index, extension),
+            (_, None) => format!("{}-{}", stem, index),
+        };
+        let path = dir.join(file_name);
+
+        let mut file = match fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+
+        file.write_all(body).await?;
+        file.flush().await?;
+        return Ok(path);
+    }
+
+    unreachable!("there is always a free file name")
+}
+
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+
+    // a leading dot would make it hidden, or a path to a parent directory
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        DEFAULT_FILE_NAME.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::file::temp_path;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = temp_path(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("capture-1_a.pcap"), "capture-1_a.pcap");
+        assert_eq!(sanitize("a b(1).pcapng"), "a_b_1_.pcapng");
+        assert_eq!(sanitize("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize("/tmp/capture.pcap"), "_tmp_capture.pcap");
+        assert_eq!(sanitize("..\\capture.pcap"), "_capture.pcap");
+        assert_eq!(sanitize(".hidden.pcap"), "hidden.pcap");
+        assert_eq!(sanitize("..."), DEFAULT_FILE_NAME);
+        assert_eq!(sanitize(""), DEFAULT_FILE_NAME);
+    }
+
+    #[tokio::test]
+    async fn stored_in_the_upload_directory() {
+        let dir = temp_dir("upload-store");
+
+        let path = store(&dir, "..%2F..%2Fcapture.pcap", b"data")
+            .await
+            .unwrap();
+        assert_eq!(path, dir.join("_.._capture.pcap"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+
+        let path = store(&dir, ".pcap", b"").await.unwrap();
+        assert_eq!(path, dir.join("pcap"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn name_collisions() {
+        let dir = temp_dir("upload-collisions");
+
+        let mut paths = Vec::new();
+        for (name, body) in [
+            ("capture.pcap", "first"),
+            ("capture.pcap", "second"),
+            ("capture.pcap", "third"),
+            ("capture", "fourth"),
+            ("capture", "fifth"),
+        ] {
+            paths.push(store(&dir, name, body.as_bytes()).await.unwrap());
+        }
+
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "capture.pcap",
+                "capture-1.pcap",
+                "capture-2.pcap",
+                "capture",
+                "capture-1"
+            ]
+        );
+        // the earlier uploads aren't overwritten
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), b"first");
+        assert_eq!(std::fs::read(&paths[2]).unwrap(), b"third");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,6 +1,24 @@
+mod recorder;
+mod replay;
+mod socket;
+
+use bon::Builder;
 use futures_util::StreamExt;
-use netpix_common::{Packet, Source};
-use pcap::{Capture, PacketCodec, PacketStream};
+use log::{error, warn};
+use netpix_common::packet::{
+    framing::Deframer, get_duration, link::LinkFrame, reassembly::Reassembler,
+};
+use netpix_common::{CaptureStats, FragmentStats, Packet, ReplayState, Source, SourceInfo};
+use pcap::{Capture, Linktype, PacketCodec, PacketHeader, PacketStream};
+use recorder::Recorder;
+use replay::Replay;
+use socket::SocketStream;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use recorder::RecordConfig;
+pub use replay::ReplayCommand;
+pub use socket::SocketSpec;
 
 #[derive(Debug)]
 pub enum Error {
@@ -11,75 +29,316 @@ pub enum Error {
     UnsupportedPacketType,
     InvalidFilter,
     PacketStreamUnavailable,
+    IncompleteDatagram,
+    IncompleteFrame,
+    ReplayUnavailable,
+    RecordingUnavailable,
+    InvalidSocketAddress,
+    SocketUnavailable,
+    StatsUnavailable,
+}
+
+/// How sources are opened, shared by the ones passed on the command line
+/// and the ones added while the server is running.
+#[derive(Debug, Builder, Clone, Default)]
+pub struct SourceOptions {
+    /// Capture filter of file sources
+    #[builder(default)]
+    pub filter: String,
+    /// Capture filter of interfaces, it also excludes the traffic of netpix itself
+    #[builder(default)]
+    pub live_filter: String,
+    #[builder(default)]
+    pub promisc: bool,
+    /// Speed of the real time replay of file sources
+    pub replay: Option<f64>,
+    pub record: Option<RecordConfig>,
 }
 #[derive(Debug)]
 struct PacketDecoder {
     packet_id: usize,
+    link_type: Linktype,
+    reassembler: Reassembler,
+    deframer: Deframer,
+    // the rest of the packets split out of the last TCP segment
+    framed: VecDeque<Packet>,
+    recorder: Option<Recorder>,
 }
 
 impl PacketDecoder {
-    pub fn new() -> Self {
-        Self { packet_id: 1 }
+    pub fn new(link_type: Linktype) -> Self {
+        Self {
+            packet_id: 1,
+            link_type,
+            reassembler: Reassembler::default(),
+            deframer: Deframer::default(),
+            framed: VecDeque::new(),
+            recorder: None,
+        }
     }
-}
 
-impl PacketCodec for PacketDecoder {
-    type Item = Result<Packet, Error>;
+    pub fn next_id(&mut self) -> usize {
+        let id = self.packet_id;
+        self.packet_id += 1;
+        id
+    }
 
-    fn decode(&mut self, packet: pcap::Packet<'_>) -> Self::Item {
-        let res = match Packet::build(&packet, self.packet_id) {
-            Some(packet) => Ok(packet),
-            None => Err(Error::UnsupportedPacketType),
+    pub fn decode(&mut self, packet: &pcap::Packet<'_>) -> Result<Packet, Error> {
+        self.decode_as(packet, self.link_type, None)
+    }
+
+    /// Like [`PacketDecoder::decode`], for a packet of another link type read from
+    /// the file at `origin`, e.g. one of the merged files.
+    pub fn decode_as(
+        &mut self,
+        packet: &pcap::Packet<'_>,
+        link_type: Linktype,
+        origin: Option<usize>,
+    ) -> Result<Packet, Error> {
+        let id = self.next_id();
+        self.record(packet);
+
+        let Some(frame) = LinkFrame::decode(packet.data, link_type) else {
+            return Err(Error::UnsupportedPacketType);
         };
 
-        self.packet_id += 1;
-        res
+        let Some(frame) = self.reassembler.reassemble(frame, packet) else {
+            return Err(Error::IncompleteDatagram);
+        };
+
+        let Some(mut segment) = Packet::build_from_frame(packet, id, &frame) else {
+            return Err(Error::UnsupportedPacketType);
+        };
+        segment.origin = origin;
+
+        // a TCP segment may carry several RTP packets, or only a part of one
+        let packet_id = &mut self.packet_id;
+        let mut packets = self.deframer.deframe(&frame, segment, || {
+            let id = *packet_id;
+            *packet_id += 1;
+            id
+        });
+        if packets.is_empty() {
+            return Err(Error::IncompleteFrame);
+        }
+
+        let first = packets.remove(0);
+        self.framed.extend(packets);
+        Ok(first)
+    }
+
+    fn next_framed(&mut self) -> Option<Packet> {
+        self.framed.pop_front()
+    }
+
+    // raw bytes are recorded before decoding, unsupported packets included
+    fn record(&mut self, packet: &pcap::Packet<'_>) {
+        let Some(ref mut recorder) = self.recorder else {
+            return;
+        };
+
+        if let Err(err) = recorder.write(packet.header, packet.data) {
+            error!("Failed to record a packet, recording stopped: {}", err);
+            self.recorder = None;
+        }
+    }
+
+    fn flush_recording(&mut self) {
+        let Some(ref mut recorder) = self.recorder else {
+            return;
+        };
+
+        if let Err(err) = recorder.flush() {
+            error!("Failed to flush the recording, recording stopped: {}", err);
+            self.recorder = None;
+        }
+    }
+}
+
+// PacketStream doesn't expose its codec, so decoding stays in the Sniffer
+// where the reassembly state and its statistics remain reachable
+struct OwnedPacketCodec;
+
+impl PacketCodec for OwnedPacketCodec {
+    type Item = (PacketHeader, Vec<u8>);
+
+    fn decode(&mut self, packet: pcap::Packet<'_>) -> Self::Item {
+        (*packet.header, packet.data.to_vec())
     }
 }
 
 // well, it's not technically a Stream...
+// the packets of several files are merged in the order of their timestamps
 struct OfflineStream {
+    files: Vec<OfflineFile>,
+}
+
+struct OfflineFile {
     capture: Capture<pcap::Offline>,
-    decoder: PacketDecoder,
+    path: String,
+    link_type: Linktype,
+    // read ahead to find the file with the earliest packet
+    next: Option<(PacketHeader, Vec<u8>)>,
+    finished: bool,
+}
+
+impl OfflineFile {
+    fn open(path: &str) -> Result<Self, Error> {
+        let Ok(capture) = pcap::Capture::from_file(path) else {
+            return Err(Error::FileNotFound);
+        };
+
+        Ok(Self {
+            link_type: capture.get_datalink(),
+            capture,
+            path: path.to_string(),
+            next: None,
+            finished: false,
+        })
+    }
+
+    fn next_timestamp(&mut self) -> Result<Option<Duration>, pcap::Error> {
+        if self.next.is_none() && !self.finished {
+            match self.capture.next_packet() {
+                Ok(packet) => self.next = Some((*packet.header, packet.data.to_vec())),
+                Err(pcap::Error::NoMorePackets) => self.finished = true,
+                Err(err) => {
+                    // the rest of a truncated file can't be read anyway
+                    self.finished = true;
+                    return Err(err);
+                }
+            }
+        }
+
+        let timestamp = self
+            .next
+            .as_ref()
+            .map(|(header, data)| get_duration(&pcap::Packet::new(header, data)));
+        Ok(timestamp)
+    }
 }
 
 impl OfflineStream {
-    pub fn new(capture: Capture<pcap::Offline>, decoder: PacketDecoder) -> Self {
-        Self { capture, decoder }
+    pub fn open(files: &[String]) -> Result<Self, Error> {
+        let files = files
+            .iter()
+            .map(|file| OfflineFile::open(file))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { files })
     }
 
-    pub fn next(&mut self) -> Option<Result<Result<Packet, Error>, pcap::Error>> {
-        let packet = match self.capture.next_packet() {
-            Err(pcap::Error::NoMorePackets) => return None,
-            Err(err) => return Some(Err(err)),
-            Ok(packet) => packet,
-        };
+    fn filter(&mut self, filter: &str) -> Result<(), pcap::Error> {
+        self.files
+            .iter_mut()
+            .try_for_each(|file| file.capture.filter(filter, true))
+    }
+
+    /// Opens the files again to read them from their beginning.
+    fn reopen(&mut self, filter: &str) -> Result<(), Error> {
+        let paths: Vec<_> = self.files.iter().map(|file| file.path.clone()).collect();
+        let mut stream = Self::open(&paths)?;
+        stream.filter(filter).map_err(|_| Error::InvalidFilter)?;
 
-        Some(Ok(self.decoder.decode(packet)))
+        *self = stream;
+        Ok(())
+    }
+
+    pub fn next(
+        &mut self,
+        decoder: &mut PacketDecoder,
+    ) -> Option<Result<Result<Packet, Error>, pcap::Error>> {
+        // nothing to merge, the packet doesn't need to be copied
+        if let [file] = self.files.as_mut_slice() {
+            let packet = match file.capture.next_packet() {
+                Err(pcap::Error::NoMorePackets) => return None,
+                Err(err) => return Some(Err(err)),
+                Ok(packet) => packet,
+            };
+
+            return Some(Ok(decoder.decode(&packet)));
+        }
+
+        let mut earliest: Option<(usize, Duration)> = None;
+        for (index, file) in self.files.iter_mut().enumerate() {
+            match file.next_timestamp() {
+                Ok(Some(timestamp)) if earliest.map_or(true, |(_, first)| timestamp < first) => {
+                    earliest = Some((index, timestamp));
+                }
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        let (index, _) = earliest?;
+        let file = &mut self.files[index];
+        let (header, data) = file.next.take()?;
+        let packet = decoder.decode_as(
+            &pcap::Packet::new(&header, &data),
+            file.link_type,
+            Some(index),
+        );
+
+        Some(Ok(packet))
     }
 }
 enum CaptureType {
     Offline(OfflineStream),
-    Online(PacketStream<pcap::Active, PacketDecoder>),
+    Online(PacketStream<pcap::Active, OwnedPacketCodec>),
+    Socket(SocketStream),
+    /// Packets of a saved session, already parsed
+    Restored(std::vec::IntoIter<Packet>),
 }
 
 pub struct Sniffer {
     capture: CaptureType,
+    decoder: PacketDecoder,
+    filter: String,
+    replay: Option<Replay>,
+    // replayed packet held back until it's due
+    pending: Option<Packet>,
+    promisc: bool,
+    start_time: Duration,
+    counters: Counters,
     pub source: Source,
 }
 
+#[derive(Debug, Default)]
+struct Counters {
+    packets: usize,
+    bytes: u64,
+    errors: usize,
+}
+
 impl Sniffer {
     pub fn from_file(file: &str) -> Result<Self, Error> {
-        let Ok(capture) = pcap::Capture::from_file(file) else {
+        Self::from_files(&[file.to_string()])
+    }
+
+    /// Merges the files into a single source, e.g. a capture split into several of them,
+    /// their packets are numbered in the order of their timestamps.
+    pub fn from_files(files: &[String]) -> Result<Self, Error> {
+        let stream = OfflineStream::open(files)?;
+        let Some(first) = stream.files.first() else {
             return Err(Error::FileNotFound);
         };
 
-        let decoder = PacketDecoder::new();
-        let stream = OfflineStream::new(capture, decoder);
+        let decoder = PacketDecoder::new(first.link_type);
+        let source = match files {
+            [file] => Source::File(file.clone()),
+            _ => Source::Files(files.to_vec()),
+        };
 
         Ok(Self {
             capture: CaptureType::Offline(stream),
-            source: Source::File(file.to_string()),
+            decoder,
+            filter: String::new(),
+            replay: None,
+            pending: None,
+            promisc: false,
+            start_time: since_epoch(),
+            counters: Counters::default(),
+            source,
         })
     }
 
@@ -96,35 +355,338 @@ impl Sniffer {
             return Err(Error::DeviceUnavailable);
         };
 
-        let decoder = PacketDecoder::new();
-        let Ok(stream) = capture.stream(decoder) else {
+        let decoder = PacketDecoder::new(capture.get_datalink());
+        let Ok(stream) = capture.stream(OwnedPacketCodec) else {
             return Err(Error::PacketStreamUnavailable);
         };
 
         Ok(Self {
             capture: CaptureType::Online(stream),
-            source: Source::Interface(format!("{} {}", device, if promisc { "👁️" } else { "" })),
+            decoder,
+            filter: String::new(),
+            replay: None,
+            pending: None,
+            promisc,
+            start_time: since_epoch(),
+            counters: Counters::default(),
+            source: Source::Interface(device.to_string()),
         })
     }
 
+    /// Receives the datagrams on a UDP socket, which needs no privileges, unlike capturing.
+    /// Needs to be called within the Tokio runtime.
+    pub fn from_socket(spec: &str) -> Result<Self, Error> {
+        let parsed = spec.parse::<SocketSpec>().map_err(|err| {
+            error!("Invalid socket address {}: {}", spec, err);
+            Error::InvalidSocketAddress
+        })?;
+        let stream = SocketStream::open(&parsed)?;
+
+        Ok(Self {
+            capture: CaptureType::Socket(stream),
+            // the datagrams come without the link and IP headers, nothing is decoded
+            decoder: PacketDecoder::new(Linktype::RAW),
+            filter: String::new(),
+            replay: None,
+            pending: None,
+            promisc: false,
+            start_time: since_epoch(),
+            counters: Counters::default(),
+            source: Source::Socket(spec.to_string()),
+        })
+    }
+
+    /// Yields the packets saved with a session, keeping their ids and the protocols they were parsed as.
+    pub fn from_packets(source: Source, packets: Vec<Packet>) -> Self {
+        Self {
+            capture: CaptureType::Restored(packets.into_iter()),
+            decoder: PacketDecoder::new(Linktype::RAW),
+            filter: String::new(),
+            replay: None,
+            pending: None,
+            promisc: false,
+            start_time: since_epoch(),
+            counters: Counters::default(),
+            source,
+        }
+    }
+
+    pub fn open_file(file: &str, options: &SourceOptions) -> Result<Self, Error> {
+        Self::open_files(&[file.to_string()], options)
+    }
+
+    pub fn open_files(files: &[String], options: &SourceOptions) -> Result<Self, Error> {
+        let mut sniffer = Self::from_files(files)?;
+        sniffer.apply_filter(&options.filter)?;
+
+        if let Some(speed) = options.replay {
+            sniffer.enable_replay(speed)?;
+        }
+
+        Ok(sniffer)
+    }
+
+    pub fn open_interface(device: &str, options: &SourceOptions) -> Result<Self, Error> {
+        let mut sniffer = Self::from_device(device, options.promisc)?;
+        sniffer.apply_filter(&options.live_filter)?;
+
+        if let Some(ref config) = options.record {
+            match Recorder::new(config.clone(), device, sniffer.link_type()) {
+                Ok(recorder) => sniffer.decoder.recorder = Some(recorder),
+                Err(err) => {
+                    error!("Failed to record interface {}: {}", device, err);
+                    return Err(Error::RecordingUnavailable);
+                }
+            }
+        }
+
+        Ok(sniffer)
+    }
+
+    /// Capture filters can't be applied to sockets and their datagrams aren't recorded,
+    /// as they come without the link and IP headers.
+    pub fn open_socket(spec: &str, options: &SourceOptions) -> Result<Self, Error> {
+        if options.record.is_some() {
+            warn!("Socket {} is not recorded, only interfaces are", spec);
+        }
+
+        Self::from_socket(spec)
+    }
+
     pub fn apply_filter(&mut self, filter: &str) -> Result<(), Error> {
         match self.capture {
             CaptureType::Online(ref mut stream) => stream.capture_mut().filter(filter, true),
-            CaptureType::Offline(ref mut stream) => stream.capture.filter(filter, true),
+            CaptureType::Offline(ref mut stream) => stream.filter(filter),
+            CaptureType::Socket(_) | CaptureType::Restored(_) if filter.is_empty() => Ok(()),
+            CaptureType::Socket(_) | CaptureType::Restored(_) => return Err(Error::InvalidFilter),
+        }
+        .map_err(|_| Error::InvalidFilter)?;
+
+        self.filter = filter.to_string();
+        Ok(())
+    }
+
+    /// Paces the packets of a file source as they were captured, `speed` scales the pace.
+    pub fn enable_replay(&mut self, speed: f64) -> Result<(), Error> {
+        let CaptureType::Offline(ref stream) = self.capture else {
+            return Err(Error::ReplayUnavailable);
+        };
+
+        let mut bounds: Option<(Duration, Duration)> = None;
+        for file in stream.files.iter() {
+            let Ok(mut capture) = pcap::Capture::from_file(&file.path) else {
+                return Err(Error::FileNotFound);
+            };
+
+            while let Ok(packet) = capture.next_packet() {
+                let timestamp = get_duration(&packet);
+                bounds = Some(match bounds {
+                    Some((first, last)) => (first.min(timestamp), last.max(timestamp)),
+                    None => (timestamp, timestamp),
+                });
+            }
+        }
+
+        let (first, last) = bounds.unwrap_or_default();
+        self.replay = Some(Replay::new(speed, first, last - first));
+        Ok(())
+    }
+
+    /// Whether the packets come from a saved session, so they don't need to be parsed.
+    pub fn is_restored(&self) -> bool {
+        matches!(self.capture, CaptureType::Restored(_))
+    }
+
+    pub fn link_type(&self) -> Linktype {
+        self.decoder.link_type
+    }
+
+    pub fn replay_state(&self) -> Option<ReplayState> {
+        self.replay.as_ref().map(Replay::state)
+    }
+
+    pub fn control_replay(&mut self, command: ReplayCommand) -> Result<(), Error> {
+        let Some(ref mut replay) = self.replay else {
+            return Err(Error::ReplayUnavailable);
+        };
+
+        if replay.apply(command) {
+            self.restart()?;
+        }
+
+        Ok(())
+    }
+
+    fn restart(&mut self) -> Result<(), Error> {
+        let CaptureType::Offline(ref mut stream) = self.capture else {
+            return Err(Error::ReplayUnavailable);
+        };
+
+        stream.reopen(&self.filter)?;
+        self.decoder.reassembler.clear();
+        self.decoder.deframer.clear();
+        self.decoder.framed.clear();
+        self.pending = None;
+        self.counters = Counters::default();
+        Ok(())
+    }
+
+    pub fn fragment_stats(&self) -> FragmentStats {
+        self.decoder.reassembler.stats()
+    }
+
+    /// Whether the packets are captured on an interface, as they arrive.
+    pub fn is_live(&self) -> bool {
+        matches!(self.capture, CaptureType::Online(_))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.decoder.recorder.is_some()
+    }
+
+    /// Writes out the packets recorded since the last call.
+    pub fn flush_recording(&mut self) {
+        self.decoder.flush_recording();
+    }
+
+    /// Asks libpcap how many packets the capture of an interface received and dropped,
+    /// other sources don't have such statistics.
+    pub fn capture_stats(&mut self) -> Result<Option<CaptureStats>, Error> {
+        let CaptureType::Online(ref mut stream) = self.capture else {
+            return Ok(None);
+        };
+
+        let Ok(stat) = stream.capture_mut().stats() else {
+            return Err(Error::StatsUnavailable);
+        };
+
+        Ok(Some(CaptureStats {
+            received: stat.received,
+            dropped: stat.dropped,
+            if_dropped: stat.if_dropped,
+        }))
+    }
+
+    pub fn info(&self) -> SourceInfo {
+        // sockets and restored packets carry no link-layer headers
+        let link_type = match self.capture {
+            CaptureType::Offline(_) | CaptureType::Online(_) => self.link_type().get_name().ok(),
+            CaptureType::Socket(_) | CaptureType::Restored(_) => None,
+        };
+
+        SourceInfo {
+            source: self.source.clone(),
+            promisc: self.promisc,
+            filter: self.filter.clone(),
+            link_type,
+            start_time: self.start_time,
+            packets: self.counters.packets,
+            bytes: self.counters.bytes,
+            errors: self.counters.errors,
         }
-        .map_err(|_| Error::InvalidFilter)
     }
 
+    /// Returns `None` once the capture ends. A replayed source waits for commands instead,
+    /// so the future has to be dropped (e.g. in `select!`) to issue `control_replay`.
     pub async fn next_packet(&mut self) -> Option<Result<Packet, Error>> {
-        let packet = match self.capture {
-            CaptureType::Offline(ref mut stream) => stream.next(),
-            CaptureType::Online(ref mut stream) => stream.next().await,
+        let result = if self.replay.is_some() {
+            self.next_replayed_packet().await
+        } else {
+            self.next_captured_packet().await
         };
 
-        match packet {
-            None => None,
-            Some(Err(_)) => Some(Err(Error::CouldntReceivePacket)),
-            Some(Ok(pack)) => Some(pack),
+        match result {
+            Some(Ok(ref packet)) => {
+                self.counters.packets += 1;
+                self.counters.bytes += u64::from(packet.length);
+            }
+            Some(Err(_)) => self.counters.errors += 1,
+            None => {}
+        }
+
+        result
+    }
+
+    async fn next_captured_packet(&mut self) -> Option<Result<Packet, Error>> {
+        loop {
+            if let Some(packet) = self.decoder.next_framed() {
+                return Some(Ok(packet));
+            }
+
+            let packet = match self.capture {
+                CaptureType::Offline(ref mut stream) => stream.next(&mut self.decoder),
+                CaptureType::Online(ref mut stream) => stream.next().await.map(|result| {
+                    result.map(|(header, data)| {
+                        self.decoder.decode(&pcap::Packet::new(&header, &data))
+                    })
+                }),
+                CaptureType::Socket(ref mut stream) => Some(
+                    stream
+                        .next(&mut self.decoder)
+                        .await
+                        .map(Ok)
+                        .map_err(pcap::Error::from),
+                ),
+                // otherwise packets of an older session would be discharged right away
+                CaptureType::Restored(ref mut packets) => packets.next().map(|mut packet| {
+                    packet.creation_time = SystemTime::now();
+                    Ok(Ok(packet))
+                }),
+            };
+
+            match packet {
+                None => return None,
+                Some(Err(_)) => return Some(Err(Error::CouldntReceivePacket)),
+                // fragments are held back until the whole datagram arrives,
+                // and so are the beginnings of the packets framed on TCP streams
+                Some(Ok(Err(Error::IncompleteDatagram | Error::IncompleteFrame))) => continue,
+                Some(Ok(pack)) => return Some(pack),
+            }
         }
     }
+
+    async fn next_replayed_packet(&mut self) -> Option<Result<Packet, Error>> {
+        let CaptureType::Offline(ref mut stream) = self.capture else {
+            return None;
+        };
+        let replay = self.replay.as_mut()?;
+
+        if replay.is_waiting() {
+            std::future::pending::<()>().await;
+        }
+
+        let packet = loop {
+            if let Some(packet) = self.pending.take().or_else(|| self.decoder.next_framed()) {
+                break packet;
+            }
+
+            match stream.next(&mut self.decoder) {
+                None => {
+                    replay.finish();
+                    return None;
+                }
+                Some(Err(_)) => return Some(Err(Error::CouldntReceivePacket)),
+                Some(Ok(Err(Error::IncompleteDatagram | Error::IncompleteFrame))) => continue,
+                Some(Ok(Err(err))) => return Some(Err(err)),
+                Some(Ok(Ok(packet))) => break packet,
+            }
+        };
+
+        let due = replay.due(packet.timestamp);
+        // keep the packet in case the wait gets cancelled
+        self.pending = Some(packet);
+        if let Some(due) = due {
+            tokio::time::sleep_until(due).await;
+        }
+
+        let packet = self.pending.take()?;
+        replay.delivered(packet.timestamp);
+        Some(Ok(packet))
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
 }
@@ -0,0 +1,260 @@
+mod pcapng;
+
+use bon::Builder;
+use log::warn;
+use pcap::{Linktype, PacketHeader};
+use pcapng::PcapngWriter;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const FILE_EXTENSION: &str = "pcapng";
+
+#[derive(Debug, Builder, Clone)]
+pub struct RecordConfig {
+    pub dir: PathBuf,
+    /// Size in bytes after which a new file is started.
+    pub max_file_size: u64,
+    /// Time after which a new file is started.
+    pub max_file_duration: Duration,
+    /// Number of files kept per source, the oldest ones are removed. 0 keeps all of them.
+    pub max_files: usize,
+}
+
+/// Writes the raw packets of a source to rotating pcapng files
+/// named `<source>_<unix time>_<index>.pcapng`.
+#[derive(Debug)]
+pub struct Recorder {
+    config: RecordConfig,
+    name: String,
+    link_type: Linktype,
+    writer: Option<PcapngWriter<BufWriter<File>>>,
+    opened_at: Instant,
+    // oldest first, includes the files left by previous runs
+    files: VecDeque<PathBuf>,
+    next_index: usize,
+}
+
+impl Recorder {
+    pub fn new(config: RecordConfig, source: &str, link_type: Linktype) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+
+        let name = sanitize(source);
+        let mut files: Vec<_> = fs::read_dir(&config.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_recording_of(path, &name))
+            .collect();
+        // the fixed width of the time and index makes it chronological
+        files.sort_unstable();
+
+        Ok(Self {
+            config,
+            name,
+            link_type,
+            writer: None,
+            opened_at: Instant::now(),
+            files: files.into(),
+            next_index: 0,
+        })
+    }
+
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8]) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+
+        let Some(ref mut writer) = self.writer else {
+            return Ok(());
+        };
+        writer.write_packet(header, data)
+    }
+
+    /// Writes out the buffered packets, which is up to the caller so that
+    /// the tail of a burst isn't held back until the next packet arrives.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.writer {
+            Some(ref mut writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn should_rotate(&self) -> bool {
+        match self.writer {
+            None => true,
+            Some(ref writer) => {
+                writer.written() >= self.config.max_file_size
+                    || self.opened_at.elapsed() >= self.config.max_file_duration
+            }
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+
+        let (path, file) = self.create_file()?;
+        let writer = PcapngWriter::new(BufWriter::new(file), self.link_type, &self.name)?;
+        self.writer = Some(writer);
+        self.opened_at = Instant::now();
+        self.files.push_back(path);
+
+        if self.config.max_files == 0 {
+            return Ok(());
+        }
+
+        while self.files.len() > self.config.max_files {
+            let Some(oldest) = self.files.pop_front() else {
+                break;
+            };
+            if let Err(err) = fs::remove_file(&oldest) {
+                warn!("Failed to remove recording {}: {}", oldest.display(), err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_file(&mut self) -> io::Result<(PathBuf, File)> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        loop {
+            let path = self.config.dir.join(format!(
+                "{}_{:010}_{:06}.{}",
+                self.name, time, self.next_index, FILE_EXTENSION
+            ));
+            self.next_index += 1;
+
+            // never overwrite a recording, e.g. one left by a quickly restarted run
+            match File::options().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+fn sanitize(source: &str) -> String {
+    source
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn is_recording_of(path: &Path, name: &str) -> bool {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    let Some(suffix) = file_name
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .and_then(|rest| rest.strip_suffix(FILE_EXTENSION))
+        .and_then(|rest| rest.strip_suffix('.'))
+    else {
+        return false;
+    };
+
+    // otherwise e.g. "eth0" would claim the recordings of "eth0_1"
+    suffix
+        .split_once('_')
+        .is_some_and(|(time, index)| is_number(time) && is_number(index))
+}
+
+fn is_number(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcap::Capture;
+
+    fn config(dir: PathBuf) -> RecordConfig {
+        RecordConfig::builder()
+            .dir(dir)
+            .max_file_size(u64::MAX)
+            .max_file_duration(Duration::from_secs(3600))
+            .max_files(0)
+            .build()
+    }
+
+    fn header(sec: i64, usec: i64, len: usize) -> PacketHeader {
+        PacketHeader {
+            ts: libc::timeval {
+                tv_sec: sec as _,
+                tv_usec: usec as _,
+            },
+            caplen: len as u32,
+            len: len as u32 + 10,
+        }
+    }
+
+    #[test]
+    fn recorded_packets_read_back() {
+        let dir = std::env::temp_dir().join(format!("netpix-recorder-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let packets: [(PacketHeader, &[u8]); 3] = [
+            (header(1_700_000_000, 1, 5), &[1, 2, 3, 4, 5]),
+            (header(1_700_000_000, 999_999, 2), &[6, 7]),
+            (header(1_700_000_001, 0, 7), &[8, 9, 10, 11, 12, 13, 14]),
+        ];
+
+        let mut recorder = Recorder::new(config(dir.clone()), "eth0", Linktype::ETHERNET).unwrap();
+        for (header, data) in packets.iter() {
+            recorder.write(header, data).unwrap();
+        }
+        // read while the recorder is still open, as after a crash
+        recorder.flush().unwrap();
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(is_recording_of(&files[0], "eth0"));
+
+        let mut capture = Capture::from_file(&files[0]).unwrap();
+        assert_eq!(capture.get_datalink(), Linktype::ETHERNET);
+        for (header, data) in packets.iter() {
+            let packet = capture.next_packet().unwrap();
+            assert_eq!(packet.header.ts.tv_sec, header.ts.tv_sec);
+            assert_eq!(packet.header.ts.tv_usec, header.ts.tv_usec);
+            assert_eq!(packet.header.caplen, header.caplen);
+            assert_eq!(packet.header.len, header.len);
+            assert_eq!(packet.data, *data);
+        }
+        assert!(capture.next_packet().is_err());
+
+        drop(recorder);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recordings_of_other_sources() {
+        assert!(is_recording_of(
+            Path::new("eth0_1700000000_000001.pcapng"),
+            "eth0"
+        ));
+        assert!(!is_recording_of(
+            Path::new("eth0_1_1700000000_000001.pcapng"),
+            "eth0"
+        ));
+        assert!(!is_recording_of(
+            Path::new("eth0_1700000000_000001.pcap"),
+            "eth0"
+        ));
+        assert_eq!(sanitize("udp://239.0.0.1:5004"), "udp___239.0.0.1_5004");
+    }
+}
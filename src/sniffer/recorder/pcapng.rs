@@ -0,0 +1,105 @@
+use pcap::{Linktype, PacketHeader};
+use std::io::{self, Write};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+// section length is not known upfront
+const UNSPECIFIED_SECTION_LENGTH: i64 = -1;
+// 0 means no limit
+const SNAPSHOT_LENGTH: u32 = 0;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+// timestamps in microseconds, as in the pcap packet headers
+const TIMESTAMP_RESOLUTION: u8 = 6;
+
+/// Writes a pcapng file consisting of a single section with a single interface.
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(inner: W, link_type: Linktype, interface: &str) -> io::Result<Self> {
+        let mut writer = Self { inner, written: 0 };
+
+        let mut section = Vec::new();
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&UNSPECIFIED_SECTION_LENGTH.to_le_bytes());
+        push_option(
+            &mut section,
+            OPT_SHB_USERAPPL,
+            env!("CARGO_PKG_NAME").as_bytes(),
+        );
+        push_option(&mut section, OPT_ENDOFOPT, &[]);
+        writer.write_block(SECTION_HEADER_BLOCK, &section)?;
+
+        let mut description = Vec::new();
+        // link types are 16 bit values in pcapng
+        description.extend_from_slice(&(link_type.0 as u16).to_le_bytes());
+        description.extend_from_slice(&0u16.to_le_bytes());
+        description.extend_from_slice(&SNAPSHOT_LENGTH.to_le_bytes());
+        push_option(&mut description, OPT_IF_NAME, interface.as_bytes());
+        push_option(&mut description, OPT_IF_TSRESOL, &[TIMESTAMP_RESOLUTION]);
+        push_option(&mut description, OPT_ENDOFOPT, &[]);
+        writer.write_block(INTERFACE_DESCRIPTION_BLOCK, &description)?;
+
+        Ok(writer)
+    }
+
+    pub fn write_packet(&mut self, header: &PacketHeader, data: &[u8]) -> io::Result<()> {
+        let timestamp = header.ts.tv_sec as u64 * 1_000_000 + header.ts.tv_usec as u64;
+
+        let mut packet = Vec::with_capacity(20 + data.len());
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&header.len.to_le_bytes());
+        packet.extend_from_slice(data);
+
+        self.write_block(ENHANCED_PACKET_BLOCK, &packet)
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let padding = padding(body.len());
+        // block type, two block total lengths and the padded body
+        let total_length = (12 + body.len() + padding) as u32;
+
+        self.inner.write_all(&block_type.to_le_bytes())?;
+        self.inner.write_all(&total_length.to_le_bytes())?;
+        self.inner.write_all(body)?;
+        self.inner.write_all(&[0; 3][..padding])?;
+        self.inner.write_all(&total_length.to_le_bytes())?;
+
+        self.written += total_length as u64;
+        Ok(())
+    }
+}
+
+fn push_option(buffer: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buffer.extend_from_slice(&code.to_le_bytes());
+    buffer.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(value);
+    buffer.extend(std::iter::repeat(0).take(padding(value.len())));
+}
+
+fn padding(length: usize) -> usize {
+    (4 - length % 4) % 4
+}
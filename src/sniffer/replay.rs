@@ -0,0 +1,207 @@
+use netpix_common::ReplayState;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReplayCommand {
+    Pause,
+    Resume,
+    Seek(Duration),
+}
+
+/// Paces the packets of an offline source according to their capture timestamps.
+#[derive(Debug)]
+pub struct Replay {
+    state: ReplayState,
+    // wall clock instant at which the packet captured at the second timestamp is due
+    anchor: Option<(Instant, Duration)>,
+    // after a seek, packets captured before this timestamp are let through right away
+    seek_target: Option<Duration>,
+}
+
+impl Replay {
+    pub fn new(speed: f64, start: Duration, duration: Duration) -> Self {
+        Self {
+            state: ReplayState {
+                speed,
+                paused: false,
+                finished: false,
+                start,
+                duration,
+                position: Duration::ZERO,
+                generation: 0,
+            },
+            anchor: None,
+            seek_target: None,
+        }
+    }
+
+    pub fn state(&self) -> ReplayState {
+        self.state
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.state.paused || self.state.finished
+    }
+
+    /// Returns the instant the packet captured at `timestamp` is due,
+    /// `None` if it should be delivered right away.
+    pub fn due(&mut self, timestamp: Duration) -> Option<Instant> {
+        if let Some(target) = self.seek_target {
+            if timestamp < target {
+                return None;
+            }
+            self.seek_target = None;
+            self.anchor = Some((Instant::now(), target));
+        }
+
+        let (instant, anchor_timestamp) = *self
+            .anchor
+            .get_or_insert_with(|| (Instant::now(), timestamp));
+        let offset = timestamp.checked_sub(anchor_timestamp)?;
+
+        Some(instant + offset.div_f64(self.state.speed))
+    }
+
+    pub fn delivered(&mut self, timestamp: Duration) {
+        self.state.position = timestamp
+            .saturating_sub(self.state.start)
+            .min(self.state.duration);
+    }
+
+    pub fn finish(&mut self) {
+        self.state.finished = true;
+        self.state.position = self.state.duration;
+    }
+
+    /// Returns `true` if the capture has to be read again from its beginning.
+    pub fn apply(&mut self, command: ReplayCommand) -> bool {
+        match command {
+            ReplayCommand::Pause => {
+                self.state.paused = true;
+                // re-anchored on the first packet after resuming
+                self.anchor = None;
+                false
+            }
+            ReplayCommand::Resume => {
+                self.state.paused = false;
+                false
+            }
+            ReplayCommand::Seek(offset) => {
+                let offset = offset.min(self.state.duration);
+                let rewind = offset < self.state.position;
+
+                self.seek_target = Some(self.state.start + offset);
+                self.anchor = None;
+                if rewind {
+                    self.state.generation += 1;
+                    self.state.finished = false;
+                    self.state.position = Duration::ZERO;
+                }
+
+                rewind
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: Duration = Duration::from_secs(100);
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn speed_scaling() {
+        let mut replay = Replay::new(2.0, START, secs(10.0));
+        let first = replay.due(START).unwrap();
+
+        assert_eq!(replay.due(START + secs(1.0)).unwrap() - first, secs(0.5));
+        assert_eq!(replay.due(START + secs(4.0)).unwrap() - first, secs(2.0));
+        // out of order packets are delivered right away
+        assert_eq!(replay.due(START - secs(1.0)), None);
+
+        let mut replay = Replay::new(0.5, START, secs(10.0));
+        let first = replay.due(START).unwrap();
+        assert_eq!(replay.due(START + secs(1.0)).unwrap() - first, secs(2.0));
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut replay = Replay::new(1.0, START, secs(10.0));
+        replay.due(START);
+        replay.delivered(START + secs(3.0));
+        assert_eq!(replay.state().position, secs(3.0));
+
+        assert!(!replay.apply(ReplayCommand::Pause));
+        assert!(replay.is_waiting());
+        assert!(replay.state().paused);
+
+        assert!(!replay.apply(ReplayCommand::Resume));
+        assert!(!replay.is_waiting());
+        assert_eq!(replay.state().position, secs(3.0));
+        assert_eq!(replay.state().generation, 0);
+
+        // paced from the first packet after resuming, not from the beginning
+        let due = replay.due(START + secs(4.0)).unwrap();
+        assert!(due <= Instant::now());
+        assert_eq!(replay.due(START + secs(5.0)).unwrap() - due, secs(1.0));
+    }
+
+    #[test]
+    fn forward_seek() {
+        let mut replay = Replay::new(1.0, START, secs(10.0));
+        replay.due(START);
+        replay.delivered(START + secs(1.0));
+
+        assert!(!replay.apply(ReplayCommand::Seek(secs(5.0))));
+        assert_eq!(replay.state().generation, 0);
+
+        // the packets in between are skipped through
+        assert_eq!(replay.due(START + secs(2.0)), None);
+        assert_eq!(replay.due(START + secs(4.9)), None);
+        let due = replay.due(START + secs(5.0)).unwrap();
+        assert!(due <= Instant::now());
+        assert_eq!(replay.due(START + secs(6.0)).unwrap() - due, secs(1.0));
+    }
+
+    #[test]
+    fn backward_seek() {
+        let mut replay = Replay::new(1.0, START, secs(10.0));
+        replay.due(START);
+        replay.delivered(START + secs(6.0));
+        replay.finish();
+        assert!(replay.is_waiting());
+        assert_eq!(replay.state().position, secs(10.0));
+
+        // the capture is read again from its beginning
+        assert!(replay.apply(ReplayCommand::Seek(secs(2.0))));
+        let state = replay.state();
+        assert_eq!(state.generation, 1);
+        assert_eq!(state.position, Duration::ZERO);
+        assert!(!state.finished);
+        assert!(!replay.is_waiting());
+
+        assert_eq!(replay.due(START), None);
+        let due = replay.due(START + secs(2.0)).unwrap();
+        assert_eq!(replay.due(START + secs(3.0)).unwrap() - due, secs(1.0));
+
+        replay.delivered(START + secs(3.0));
+        assert!(replay.apply(ReplayCommand::Seek(secs(1.0))));
+        assert_eq!(replay.state().generation, 2);
+    }
+
+    #[test]
+    fn seek_past_the_end() {
+        let mut replay = Replay::new(1.0, START, secs(10.0));
+        replay.delivered(START + secs(4.0));
+
+        assert!(!replay.apply(ReplayCommand::Seek(secs(60.0))));
+        assert_eq!(replay.due(START + secs(9.9)), None);
+        assert!(replay.due(START + secs(10.0)).is_some());
+    }
+}
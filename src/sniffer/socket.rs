@@ -0,0 +1,338 @@
+use super::{Error, PacketDecoder};
+use log::error;
+use netpix_common::Packet;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// Address a socket source receives the datagrams on, written as
+/// `ADDRESS:PORT[@SOURCE][%INTERFACE]`, e.g. `239.1.1.1:5000`, `232.1.1.1:5000@10.0.0.1`
+/// to receive the group only from the given source (SSM) or `[ff3e::1]:5000%2`.
+/// The interface is given by its address for IPv4 groups and by its index for IPv6 ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketSpec {
+    addr: SocketAddr,
+    source: Option<IpAddr>,
+    interface: Option<MulticastInterface>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MulticastInterface {
+    Address(Ipv4Addr),
+    Index(u32),
+}
+
+impl FromStr for SocketSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (rest, interface) = match spec.rsplit_once('%') {
+            // the scope of an IPv6 address is part of it, e.g. `[fe80::1%2]:5000`
+            Some((rest, interface)) if !interface.contains(']') => (rest, Some(interface)),
+            _ => (spec, None),
+        };
+        let (addr, source) = match rest.split_once('@') {
+            Some((addr, source)) => (addr, Some(source)),
+            None => (rest, None),
+        };
+
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| format!("`{}` is not an ADDRESS:PORT", addr))?;
+
+        let source = source
+            .map(|source| match source.parse::<IpAddr>() {
+                Ok(source) if source.is_ipv4() == addr.is_ipv4() => Ok(source),
+                _ => Err(format!("`{}` is not a source address of the group", source)),
+            })
+            .transpose()?;
+
+        let interface = interface
+            .map(|interface| {
+                let parsed = match addr {
+                    SocketAddr::V4(_) => interface.parse().map(MulticastInterface::Address).ok(),
+                    SocketAddr::V6(_) => interface.parse().map(MulticastInterface::Index).ok(),
+                };
+                parsed
+                    .ok_or_else(|| format!("`{}` is not an interface address or index", interface))
+            })
+            .transpose()?;
+
+        if !addr.ip().is_multicast() && (source.is_some() || interface.is_some()) {
+            return Err(format!("`{}` is not a multicast address", addr.ip()));
+        }
+
+        Ok(Self {
+            addr,
+            source,
+            interface,
+        })
+    }
+}
+
+impl fmt::Display for SocketSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if let Some(source) = self.source {
+            write!(f, "@{}", source)?;
+        }
+        match self.interface {
+            Some(MulticastInterface::Address(address)) => write!(f, "%{}", address),
+            Some(MulticastInterface::Index(index)) => write!(f, "%{}", index),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct SocketStream {
+    socket: UdpSocket,
+    // the multicast group, as the datagrams come without their destination
+    destination: SocketAddr,
+    buffer: Vec<u8>,
+}
+
+impl SocketStream {
+    pub fn open(spec: &SocketSpec) -> Result<Self, Error> {
+        let socket = bind(spec).map_err(|err| {
+            error!("Failed to open socket {}: {}", spec, err);
+            Error::SocketUnavailable
+        })?;
+
+        let destination = match socket.local_addr() {
+            Ok(addr) if addr.ip().is_unspecified() => SocketAddr::new(spec.addr.ip(), addr.port()),
+            Ok(addr) => addr,
+            Err(_) => spec.addr,
+        };
+
+        Ok(Self {
+            socket,
+            destination,
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+
+    pub async fn next(&mut self, decoder: &mut PacketDecoder) -> Result<Packet, io::Error> {
+        let (len, source) = self.socket.recv_from(&mut self.buffer).await?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(Packet::build_from_datagram(
+            &self.buffer[..len],
+            decoder.next_id(),
+            source,
+            self.destination,
+            timestamp,
+        ))
+    }
+}
+
+fn bind(spec: &SocketSpec) -> io::Result<UdpSocket> {
+    let domain = Domain::for_address(spec.addr);
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    // other receivers of the group, e.g. the monitored application, may use the port as well
+    socket.set_reuse_address(true)?;
+    if spec.addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    let multicast = spec.addr.ip().is_multicast();
+    socket.bind(&SockAddr::from(bind_addr(spec.addr, multicast)))?;
+
+    if multicast {
+        join(&socket, spec)?;
+    }
+
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// binding to the group keeps out the datagrams of other groups sent to the same port,
+// which Windows doesn't allow
+fn bind_addr(addr: SocketAddr, multicast: bool) -> SocketAddr {
+    if !multicast || cfg!(unix) {
+        return addr;
+    }
+
+    match addr {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port()),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), addr.port()),
+    }
+}
+
+fn join(socket: &Socket, spec: &SocketSpec) -> io::Result<()> {
+    let interface_addr = match spec.interface {
+        Some(MulticastInterface::Address(address)) => address,
+        _ => Ipv4Addr::UNSPECIFIED,
+    };
+    let interface_index = match spec.interface {
+        Some(MulticastInterface::Index(index)) => index,
+        _ => 0,
+    };
+
+    match (spec.addr.ip(), spec.source) {
+        (IpAddr::V4(group), None) => socket.join_multicast_v4(&group, &interface_addr),
+        (IpAddr::V4(group), Some(IpAddr::V4(source))) => {
+            socket.join_ssm_v4(&source, &group, &interface_addr)
+        }
+        (IpAddr::V6(group), None) => socket.join_multicast_v6(&group, interface_index),
+        (IpAddr::V6(group), Some(IpAddr::V6(source))) => {
+            join_ssm_v6(socket, group, source, interface_index)
+        }
+        _ => Err(io::ErrorKind::InvalidInput.into()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn join_ssm_v6(
+    socket: &Socket,
+    group: Ipv6Addr,
+    source: Ipv6Addr,
+    interface: u32,
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // not provided by libc
+    #[repr(C)]
+    struct GroupSourceReq {
+        interface: u32,
+        group: libc::sockaddr_storage,
+        source: libc::sockaddr_storage,
+    }
+
+    let request = GroupSourceReq {
+        interface,
+        group: SockAddr::from(SocketAddr::new(group.into(), 0)).as_storage(),
+        source: SockAddr::from(SocketAddr::new(source.into(), 0)).as_storage(),
+    };
+
+    // SAFETY: the option is passed a properly initialized `group_source_req` of its size
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::MCAST_JOIN_SOURCE_GROUP,
+            &request as *const GroupSourceReq as *const libc::c_void,
+            std::mem::size_of::<GroupSourceReq>() as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn join_ssm_v6(_: &Socket, _: Ipv6Addr, _: Ipv6Addr, _: u32) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> Result<SocketSpec, String> {
+        spec.parse()
+    }
+
+    #[test]
+    fn unicast_addresses() {
+        let spec = parse("127.0.0.1:5000").unwrap();
+        assert_eq!(spec.addr, "127.0.0.1:5000".parse().unwrap());
+        assert_eq!(spec.source, None);
+        assert_eq!(spec.interface, None);
+
+        let spec = parse("[::1]:5000").unwrap();
+        assert_eq!(spec.addr, "[::1]:5000".parse().unwrap());
+
+        // the scope belongs to the address, it's not an interface to join on
+        let spec = parse("[fe80::1%2]:5000").unwrap();
+        assert_eq!(spec.addr, "[fe80::1%2]:5000".parse().unwrap());
+        assert_eq!(spec.interface, None);
+    }
+
+    #[test]
+    fn multicast_groups() {
+        let spec = parse("239.1.1.1:5000").unwrap();
+        assert_eq!(spec.addr, "239.1.1.1:5000".parse().unwrap());
+        assert_eq!(spec.source, None);
+
+        let spec = parse("239.1.1.1:5000%10.0.0.2").unwrap();
+        assert_eq!(
+            spec.interface,
+            Some(MulticastInterface::Address(Ipv4Addr::new(10, 0, 0, 2)))
+        );
+
+        let spec = parse("[ff3e::1]:5000%2").unwrap();
+        assert_eq!(spec.addr, "[ff3e::1]:5000".parse().unwrap());
+        assert_eq!(spec.interface, Some(MulticastInterface::Index(2)));
+    }
+
+    #[test]
+    fn source_specific_groups() {
+        let spec = parse("232.1.1.1:5000@10.0.0.1").unwrap();
+        assert_eq!(spec.addr, "232.1.1.1:5000".parse().unwrap());
+        assert_eq!(spec.source, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(spec.interface, None);
+
+        let spec = parse("232.1.1.1:5000@10.0.0.1%10.0.0.2").unwrap();
+        assert_eq!(spec.source, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(
+            spec.interface,
+            Some(MulticastInterface::Address(Ipv4Addr::new(10, 0, 0, 2)))
+        );
+
+        let spec = parse("[ff3e::1]:5000@2001:db8::1%3").unwrap();
+        assert_eq!(spec.source, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(spec.interface, Some(MulticastInterface::Index(3)));
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let specs = [
+            "",
+            "239.1.1.1",
+            "239.1.1.1:port",
+            "eth0:5000",
+            "ff3e::1:5000",
+            // the source of another address family
+            "232.1.1.1:5000@2001:db8::1",
+            "[ff3e::1]:5000@10.0.0.1",
+            "232.1.1.1:5000@source",
+            // interfaces of IPv4 groups by address, of IPv6 ones by index
+            "239.1.1.1:5000%2",
+            "239.1.1.1:5000%eth0",
+            "[ff3e::1]:5000%10.0.0.2",
+            // only groups are joined from a source or on an interface
+            "10.0.0.1:5000@10.0.0.2",
+            "10.0.0.1:5000%10.0.0.2",
+            "[2001:db8::2]:5000%2",
+        ];
+
+        for spec in specs {
+            assert!(parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn displayed_as_parsed() {
+        let specs = [
+            "127.0.0.1:5000",
+            "239.1.1.1:5000%10.0.0.2",
+            "232.1.1.1:5000@10.0.0.1",
+            "[ff3e::1]:5000@2001:db8::1%3",
+        ];
+
+        for spec in specs {
+            assert_eq!(parse(spec).unwrap().to_string(), spec);
+        }
+    }
+}